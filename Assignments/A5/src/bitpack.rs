@@ -61,9 +61,7 @@ pub fn gets(word: u64, width: u64, lsb: u64) -> Option<i64> {
 /// * `lsb`: the least-significant bit of the bit field
 pub fn getu(word: u64, width: u64, lsb: u64) -> u64 {
     let place_holder = (1 << width) - 1;
-    let part = (word >> lsb) & place_holder;
-
-    return part.try_into().unwrap();
+    (word >> lsb) & place_holder
 }
 
 
@@ -85,19 +83,8 @@ pub fn newu(word: u64, width: u64, lsb: u64, value: u64) -> Option<u64> {
     if !fitsu(value, width) {
         return None
     }
-    let right: u64;
-    let left: u64;
-    if lsb == 0 { 
-        right = 0; 
-    } else { 
-        right = (word << (64 - lsb)) >> (64 - lsb); 
-    }
-    if width + lsb == 64 { 
-        left = 0; 
-    } 
-    else { 
-        left = (word >> (width + lsb)) << (width + lsb);
-    }
+    let right = if lsb == 0 { 0 } else { (word << (64 - lsb)) >> (64 - lsb) };
+    let left = if width + lsb == 64 { 0 } else { (word >> (width + lsb)) << (width + lsb) };
     let newu : Option<u64> = Some(left|value << lsb|right);
     newu
 }
@@ -120,21 +107,9 @@ pub fn news(word: u64, width: u64, lsb: u64, value: i64) -> Option<u64> {
     if !fitss(value, width) {
         return None
     }
-    let right: u64;
-    let left: u64;
     let mut middle: u64;
-    if lsb == 0 { 
-        right = 0; 
-    } 
-    else { 
-        right = (word << (64 - lsb)) >> (64 - lsb); 
-    }
-    if width+lsb == 64 { 
-        left = 0; 
-    } 
-    else { 
-        left = (word >> (width + lsb)) << (width + lsb);
-    }
+    let right = if lsb == 0 { 0 } else { (word << (64 - lsb)) >> (64 - lsb) };
+    let left = if width + lsb == 64 { 0 } else { (word >> (width + lsb)) << (width + lsb) };
     if value < 0 {
         middle = (!(value<<(64-width))).try_into().unwrap();
         middle = (!middle)>>(64-width-lsb);
@@ -1,9 +1,42 @@
-use std::io::Write;
-use std::io::Read;
+use std::io;
+use umcore::fault::UmFault;
+use umcore::io::UmIo;
 use crate::memory::UmState;
 
+/// The address and word of the instruction being executed, for a fault.
+///
+/// Arguments:
+/// *`state`: A struct containing the registers, memory, instance counter, and memory tracker.
+fn site(state: &UmState) -> (usize, u32) {
+    let pc = state.inst_count as usize;
+    (pc, state.memory[0][pc])
+}
+
+/// A fault for an I/O device that failed.
+///
+/// Arguments:
+/// *`state`: A struct containing the registers, memory, instance counter, and memory tracker.
+/// *`error`: The error of the device
+fn io_fault(state: &UmState, error: io::Error) -> UmFault {
+    let (pc, instruction) = site(state);
+    UmFault::Io { pc, instruction, kind: error.kind() }
+}
+
+/// The word at `offset` in segment `segment`, or the fault for reaching it.
+///
+/// Arguments:
+/// *`state`: A struct containing the registers, memory, instance counter, and memory tracker.
+/// *`segment`: The segment identifier
+/// *`offset`: The offset in the segment
+fn word(state: &mut UmState, segment: u32, offset: u32) -> Result<&mut u32, UmFault> {
+    let (pc, instruction) = site(state);
+    let words = state.memory.get_mut(segment as usize)
+        .ok_or(UmFault::UnmappedSegment { pc, instruction, segment })?;
+    words.get_mut(offset as usize).ok_or(UmFault::SegmentOutOfBounds { pc, instruction, segment, offset })
+}
+
 /// Move instruction.
-/// 
+///
 /// Arguments:
 /// *`state`: A struct containing the registers, memory, instance counter, and memory tracker.
 /// *`rega`: Index of register A
@@ -17,31 +50,35 @@ pub fn cmov(state: &mut UmState, rega:usize, regb:usize, regc:usize) {
 }
 
 /// Load instruction.
-/// 
+///
 /// Arguments:
 /// *`state`: A struct containing the registers, memory, instance counter, and memory tracker.
 /// *`rega`: Index of register A
 /// *`regb`: Index of register B
 /// *`rebc`: Index of register C
-pub fn sload(state: &mut UmState, rega:usize, regb:usize, regc:usize) {
-    state.registers[rega] = state.memory[state.registers[regb] as usize][state.registers[regc] as usize];
+pub fn sload(state: &mut UmState, rega:usize, regb:usize, regc:usize) -> Result<(), UmFault> {
+    let (segment, offset) = (state.registers[regb], state.registers[regc]);
+    state.registers[rega] = *word(state, segment, offset)?;
     state.inst_count += 1;
+    Ok(())
 }
 
 /// Store instruction.
-/// 
+///
 /// Arguments:
 /// *`state`: A struct containing the registers, memory, instance counter, and memory tracker.
 /// *`rega`: Index of register A
 /// *`regb`: Index of register B
 /// *`rebc`: Index of register C
-pub fn store(state: &mut UmState, rega:usize, regb:usize, regc:usize) {
-    state.memory[state.registers[rega] as usize][state.registers[regb] as usize] = state.registers[regc];
+pub fn store(state: &mut UmState, rega:usize, regb:usize, regc:usize) -> Result<(), UmFault> {
+    let (segment, offset, value) = (state.registers[rega], state.registers[regb], state.registers[regc]);
+    *word(state, segment, offset)? = value;
     state.inst_count += 1;
+    Ok(())
 }
 
 /// Add instruction.
-/// 
+///
 /// Arguments:
 /// *`state`: A struct containing the registers, memory, instance counter, and memory tracker.
 /// *`rega`: Index of register A
@@ -53,7 +90,7 @@ pub fn add(state: &mut UmState, rega:usize, regb:usize, regc:usize) {
 }
 
 /// Multiply instruction.
-/// 
+///
 /// Arguments:
 /// *`state`: A struct containing the registers, memory, instance counter, and memory tracker.
 /// *`rega`: Index of register A
@@ -64,20 +101,27 @@ pub fn mul(state: &mut UmState, rega:usize, regb:usize, regc:usize) {
     state.inst_count += 1;
 }
 
-/// Divide instruction.
-/// 
+/// Divide instruction. Fails the machine when register C is 0.
+///
 /// Arguments:
 /// *`state`: A struct containing the registers, memory, instance counter, and memory tracker.
 /// *`rega`: Index of register A
 /// *`regb`: Index of register B
 /// *`rebc`: Index of register C
-pub fn div(state: &mut UmState, rega:usize, regb:usize, regc:usize) {
-    state.registers[rega] = state.registers[regb] / state.registers[regc];
+pub fn div(state: &mut UmState, rega:usize, regb:usize, regc:usize) -> Result<(), UmFault> {
+    match state.registers[regb].checked_div(state.registers[regc]) {
+        Some(quotient) => state.registers[rega] = quotient,
+        None => {
+            let (pc, instruction) = site(state);
+            return Err(UmFault::DivideByZero { pc, instruction });
+        }
+    }
     state.inst_count += 1;
+    Ok(())
 }
 
 /// Bitwise NAND instruction.
-/// 
+///
 /// Arguments:
 /// *`state`: A struct containing the registers, memory, instance counter, and memory tracker.
 /// *`rega`: Index of register A
@@ -88,14 +132,25 @@ pub fn nand(state: &mut UmState, rega:usize, regb:usize, regc:usize) {
     state.inst_count += 1;
 }
 
+/// Halt instruction. Delivers any buffered output; the machine stops.
+///
+/// Arguments:
+/// *`state`: A struct containing the registers, memory, instance counter, and memory tracker.
+/// *`io`: The I/O device
+pub fn halt<I: UmIo>(state: &mut UmState, io: &mut I) -> Result<(), UmFault> {
+    io.flush().map_err(|error| io_fault(state, error))?;
+    state.inst_count += 1;
+    Ok(())
+}
+
 /// Map segment instruction.
-/// 
+///
 /// Arguments:
 /// *`state`: A struct containing the registers, memory, instance counter, and memory tracker.
 /// *`rega`: Index of register A
 /// *`regb`: Index of register B
 pub fn map_seg(state: &mut UmState, regb:usize, regc:usize) {
-    if state.memory_tracker.len() == 0 {
+    if state.memory_tracker.is_empty() {
         let init = state.registers[regc] as usize;
         let mem_seg: Vec<u32> = vec![0; init];
         state.memory.push(mem_seg);
@@ -105,69 +160,94 @@ pub fn map_seg(state: &mut UmState, regb:usize, regc:usize) {
         let init = state.registers[regc] as usize;
         let mem_seg: Vec<u32> = vec![0; init];
         let mem_pos = state.memory_tracker.pop();
-        state.memory[mem_pos.unwrap() as usize]= mem_seg; 
+        state.memory[mem_pos.unwrap() as usize]= mem_seg;
         state.registers[regb] = mem_pos.unwrap();
     }
     state.inst_count += 1;
 }
 
-/// Unmap segment instruction.
-/// 
+/// Unmap segment instruction. The words of the segment are freed, and
+/// unmapping $m[0] or a segment that was never mapped fails the machine.
+///
 /// Arguments:
 /// *`state`: A struct containing the registers, memory, instance counter, and memory tracker.
 /// *`rebc`: Index of register C
-pub fn unmap_seg(state: &mut UmState, regc:usize) {
-    state.memory_tracker.push(state.registers[regc]);
+pub fn unmap_seg(state: &mut UmState, regc:usize) -> Result<(), UmFault> {
+    let segment = state.registers[regc];
+    if segment == 0 || segment as usize >= state.memory.len() {
+        let (pc, instruction) = site(state);
+        return Err(UmFault::UnmappedSegment { pc, instruction, segment });
+    }
+    state.memory[segment as usize] = Vec::new();
+    state.memory_tracker.push(segment);
     state.inst_count += 1;
+    Ok(())
 }
 
-/// Output instruction.
-/// 
+/// Output instruction. Fails the machine when register C does not hold a
+/// byte.
+///
 /// Arguments:
 /// *`state`: A struct containing the registers, memory, instance counter, and memory tracker.
 /// *`rebc`: Index of register C
-pub fn output(state: &mut UmState, regc: usize) {
-        let out_word: [u8; 1] = [state.registers[regc] as u8];
-        std::io::stdout().write(&out_word).ok();
-        state.inst_count += 1;
+/// *`io`: The I/O device
+pub fn output<I: UmIo>(state: &mut UmState, regc: usize, io: &mut I) -> Result<(), UmFault> {
+    let value = state.registers[regc];
+    let out_word = u8::try_from(value).map_err(|_| {
+        let (pc, instruction) = site(state);
+        UmFault::OutputOutOfRange { pc, instruction, value }
+    })?;
+    io.output(out_word).map_err(|error| io_fault(state, error))?;
+    state.inst_count += 1;
+    Ok(())
 }
 
-/// Input instruction.
-/// 
+/// Input instruction. Any buffered output is delivered first.
+///
 /// Arguments:
 /// *`state`: A struct containing the registers, memory, instance counter, and memory tracker.
 /// *`rebc`: Index of register C
-pub fn input(state: &mut UmState, regc: usize) {
-    let mut take_in: [u8; 1] = [0; 1];
-    match std::io::stdin().read(&mut take_in) {
-        Ok(0) => {
+/// *`io`: The I/O device
+pub fn input<I: UmIo>(state: &mut UmState, regc: usize, io: &mut I) -> Result<(), UmFault> {
+    io.flush().map_err(|error| io_fault(state, error))?;
+    match io.input().map_err(|error| io_fault(state, error))? {
+        None => {
             state.registers[regc] = u32::MAX;
         }
-        Ok(_) => {
-            state.registers[regc] = take_in[0] as u32;
-        }
-        Err(_) => {
-            panic!();
+        Some(take_in) => {
+            state.registers[regc] = take_in as u32;
         }
     }
     state.inst_count += 1;
+    Ok(())
 }
 
 /// Load program instruction.
-/// 
+///
 /// Arguments:
 /// *`state`: A struct containing the registers, memory, instance counter, and memory tracker.
 /// *`regb`: Index of register B
 /// *`rebc`: Index of register C
-pub fn load_program(state: &mut UmState, regb: usize, regc: usize) {
-    state.inst_count = state.registers[regc];
-    if state.registers[regb] != 0 {
-        state.memory[0] = state.memory[state.registers[regb] as usize].clone();
+pub fn load_program(state: &mut UmState, regb: usize, regc: usize) -> Result<(), UmFault> {
+    let (pc, instruction) = site(state);
+    let segment = state.registers[regb];
+    let target = state.registers[regc];
+    let len = match state.memory.get(segment as usize) {
+        Some(program) => program.len(),
+        None => return Err(UmFault::UnmappedSegment { pc, instruction, segment }),
+    };
+    if target as usize >= len {
+        return Err(UmFault::LoadProgramPcOutOfRange { pc, instruction, target });
+    }
+    if segment != 0 {
+        state.memory[0] = state.memory[segment as usize].clone();
     }
+    state.inst_count = target;
+    Ok(())
 }
 
 /// Load value instruction.
-/// 
+///
 /// Arguments:
 /// *`state`: A struct containing the registers, memory, instance counter, and memory tracker.
 /// *`rega`: Index of register A
@@ -1,14 +1,39 @@
-mod memory;
-pub mod machine;
-pub mod bitpack;
 use std::env;
+use std::process;
+use umcore::io::StdIo;
+use umcore::loader::{self, Encoding};
+use rum::memory::{self, UmState};
 
 /// Main function to run the program.
-/// 
+///
 /// Arguments:
-/// 
+/// * `--dump-registers`: print the final registers to stderr on exit
+/// * an optional `.um` file, otherwise the program is read from stdin
 fn main() {
-    let input = env::args().nth(1);
-    let instructions: Vec<u32> = memory::load(input.as_deref());
-    memory::instructs(instructions.clone());
+    let mut input = None;
+    let mut dump_registers = false;
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--dump-registers" => dump_registers = true,
+            _ if arg.starts_with("--") => {
+                eprintln!("usage: rum [--dump-registers] [program.um]");
+                process::exit(2);
+            }
+            _ => input = Some(arg),
+        }
+    }
+    let instructions = loader::load(input.as_deref(), Encoding::BigEndian).unwrap_or_else(|error| {
+        eprintln!("rum: {}", error);
+        process::exit(1);
+    });
+    let mut state = UmState::new(instructions);
+    let result = memory::instructs(&mut state, &mut StdIo::stdio());
+    if dump_registers {
+        let values: Vec<String> = state.registers.iter().map(|value| value.to_string()).collect();
+        eprintln!("registers: {}", values.join(" "));
+    }
+    if let Err(fault) = result {
+        eprintln!("rum: {}", fault);
+        process::exit(1);
+    }
 }
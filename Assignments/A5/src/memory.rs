@@ -1,21 +1,22 @@
-/// Invariants:
-/// 
-/// Invariant: The instruction set of the machine is consistent and does 
-/// not change regardless of the specific program being executed.
-/// 
-/// Invariant: The semantics of the instructions are consistent and do not 
-/// change. For example, an “add” instruction will always perform an 
-/// addition operation.
-/// 
-/// Invariant: The state of the machine (e.g., the values in registers or 
-/// memory) after executing an instruction sequence starting from a 
-/// certain state is an invariant. It does not depend on the specific 
-/// path taken to reach that state, only on the initial state and the 
-/// sequence of instructions.
+//! Invariants:
+//!
+//! Invariant: The instruction set of the machine is consistent and does
+//! not change regardless of the specific program being executed.
+//!
+//! Invariant: The semantics of the instructions are consistent and do not
+//! change. For example, an “add” instruction will always perform an
+//! addition operation.
+//!
+//! Invariant: The state of the machine (e.g., the values in registers or
+//! memory) after executing an instruction sequence starting from a
+//! certain state is an invariant. It does not depend on the specific
+//! path taken to reach that state, only on the initial state and the
+//! sequence of instructions.
 
-use std::convert::TryInto;
+use umcore::decode::{decode, Instruction};
+use umcore::fault::UmFault;
+use umcore::io::UmIo;
 use crate::machine;
-use crate::bitpack;
 
 pub struct UmState {
     pub inst_count: u32,
@@ -24,75 +25,105 @@ pub struct UmState {
     pub memory_tracker: Vec<u32>,
 }
 
-/// Function to convert binary files into instructioins.
-///
-/// Arguments
-/// * `input`: binary file input.
-pub fn load(input: Option<&str>) -> Vec<u32> {
-    let mut raw_reader: Box<dyn std::io::BufRead> = match input {
-        None => Box::new(std::io::BufReader::new(std::io::stdin())),
-        Some(filename) => Box::new(std::io::BufReader::new(std::fs::File::open(filename).unwrap(),)),
-    };
-    let mut buf = Vec::<u8>::new();
-    raw_reader.read_to_end(&mut buf).unwrap();
-    let instructions: Vec<u32> = buf
-        .chunks_exact(4)
-        .map(|x| u32::from_be_bytes(x.try_into().unwrap()))
-        .collect();
-    instructions
+impl UmState {
+    /// Creates a machine with `instructions` as segment 0.
+    ///
+    /// Arguments:
+    /// * `instructions`: A vector of instructions.
+    pub fn new(instructions: Vec<u32>) -> Self {
+        UmState {
+            inst_count: 0,
+            registers: vec![0; 8],
+            memory: vec![instructions],
+            memory_tracker: Vec::new(),
+        }
+    }
 }
 
-/// Function to perform the desired instructions.
+/// Function to perform the desired instructions until the program halts
+/// or fails. Output is delivered either way.
 ///
 /// Arguments:
-/// * `instructions`: A vector of instructions.
-pub fn instructs(instructions: Vec<u32>) {
-    let inst_count = 0;
-    let registers: Vec<u32> = vec![0; 8];
-    let memory: Vec<Vec<u32>> = Vec::new();
-    let memory_tracker: Vec<u32> = Vec::new();  
+/// * `state`: The machine to run.
+/// * `io`: The device read by Input and written by Output.
+pub fn instructs<I: UmIo>(state: &mut UmState, io: &mut I) -> Result<(), UmFault> {
+    loop {
+        match execute(state, io) {
+            Ok(true) => return Ok(()),
+            Ok(false) => {}
+            Err(fault) => {
+                // The fault is more useful to the caller than a failed flush
+                let _ = io.flush();
+                return Err(fault);
+            }
+        }
+    }
+}
 
-    let mut state = UmState {
-        inst_count,
-        registers,
-        memory,
-        memory_tracker,
+/// Function to perform the next instruction, returning whether it was
+/// the halt.
+///
+/// Arguments:
+/// * `state`: The machine.
+/// * `io`: The device read by Input and written by Output.
+pub fn execute<I: UmIo>(state: &mut UmState, io: &mut I) -> Result<bool, UmFault> {
+    let pc = state.inst_count as usize;
+    let instruction = match state.memory[0].get(pc) {
+        Some(&instruction) => instruction,
+        None => return Err(UmFault::SegmentOutOfBounds { pc, instruction: 0, segment: 0, offset: pc as u32 }),
     };
-    
-    state.memory.push(instructions.clone());
-
-    while state.inst_count < state.memory[0].len() as u32 {
-        let opcode: u32 = bitpack::getu(state.memory[0][state.inst_count as usize] as u64, 4, 28) as u32;
-        let rega;
-        let mut regb = 0;
-        let mut regc = 0;
-        let mut value = 0;
-        
-        if opcode == 13 {
-            rega = bitpack::getu(state.memory[0][state.inst_count as usize] as u64, 3, 25) as u32;
-            value = bitpack::getu(state.memory[0][state.inst_count as usize] as u64, 25, 0) as u32;
-        } 
-        else {
-            rega = bitpack::getu(state.memory[0][state.inst_count as usize] as u64, 3, 6) as u32;
-            regb = bitpack::getu(state.memory[0][state.inst_count as usize] as u64, 3, 3) as u32;
-            regc = bitpack::getu(state.memory[0][state.inst_count as usize] as u64, 3, 0) as u32;
+    match decode(instruction) {
+        Instruction::CMov { a, b, c } => machine::cmov(state, a as usize, b as usize, c as usize),
+        Instruction::Load { a, b, c } => machine::sload(state, a as usize, b as usize, c as usize)?,
+        Instruction::Store { a, b, c } => machine::store(state, a as usize, b as usize, c as usize)?,
+        Instruction::Add { a, b, c } => machine::add(state, a as usize, b as usize, c as usize),
+        Instruction::Mul { a, b, c } => machine::mul(state, a as usize, b as usize, c as usize),
+        Instruction::Div { a, b, c } => machine::div(state, a as usize, b as usize, c as usize)?,
+        Instruction::Nand { a, b, c } => machine::nand(state, a as usize, b as usize, c as usize),
+        Instruction::Halt => {
+            machine::halt(state, io)?;
+            return Ok(true);
         }
-        match opcode {
-            0 => machine::cmov(&mut state, rega as usize, regb as usize, regc as usize),
-            1 => machine::sload(&mut state, rega as usize, regb as usize, regc as usize),
-            2 => machine::store(&mut state, rega as usize, regb as usize, regc as usize),
-            3 => machine::add(&mut state, rega as usize, regb as usize, regc as usize),
-            4 => machine::mul(&mut state, rega as usize, regb as usize, regc as usize),
-            5 => machine::div(&mut state, rega as usize, regb as usize, regc as usize),
-            6 => machine::nand(&mut state, rega as usize, regb as usize, regc as usize),
-            7 => break, //This is the halt opcode.
-            8 => machine::map_seg(&mut state, regb as usize, regc as usize),
-            9 => machine::unmap_seg(&mut state, regc as usize),
-            10 => machine::output(&mut state, regc as usize),
-            11 => machine::input(&mut state, regc as usize),
-            12 => machine::load_program(&mut state, regb as usize, regc as usize),
-            13 => machine::load_value(&mut state, rega as usize, value as usize),
-            _ => panic!("Invalid OpCode {}", opcode)
+        Instruction::MapSegment { b, c } => machine::map_seg(state, b as usize, c as usize),
+        Instruction::UnmapSegment { c } => machine::unmap_seg(state, c as usize)?,
+        Instruction::Output { c } => machine::output(state, c as usize, io)?,
+        Instruction::Input { c } => machine::input(state, c as usize, io)?,
+        Instruction::LoadProgram { b, c } => machine::load_program(state, b as usize, c as usize)?,
+        Instruction::LoadValue { a, value } => machine::load_value(state, a as usize, value as usize),
+        Instruction::Invalid { opcode } => {
+            return Err(UmFault::InvalidOpcode { pc, instruction, opcode: opcode as u32 });
         }
     }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use umcore::fault::UmFault;
+    use umcore::io::StreamIo;
+    use crate::memory::{instructs, UmState};
+
+    fn inst(opcode: u32, a: u32, b: u32, c: u32) -> u32 {
+        (opcode << 28) | (a << 6) | (b << 3) | c
+    }
+
+    #[test]
+    fn halt_stops_the_machine() {
+        // The words after the halt would divide by zero
+        let value = (13 << 28) | (1 << 25) | 65;
+        let mut state = UmState::new(vec![value, inst(10, 0, 0, 1), inst(7, 0, 0, 0), inst(5, 2, 1, 0)]);
+        let mut io = StreamIo::new(&b""[..], Vec::new());
+        assert_eq!(instructs(&mut state, &mut io), Ok(()));
+        assert_eq!(state.inst_count, 3);
+        assert_eq!(io.into_parts().unwrap().1, b"A");
+    }
+    #[test]
+    fn divide_by_zero_fails() {
+        let program = vec![inst(10, 0, 0, 0), inst(5, 2, 1, 0), inst(7, 0, 0, 0)];
+        let mut state = UmState::new(program.clone());
+        let mut io = StreamIo::new(&b""[..], Vec::new());
+        assert_eq!(instructs(&mut state, &mut io), Err(UmFault::DivideByZero { pc: 1, instruction: program[1] }));
+        assert_eq!(state.inst_count, 1);
+        assert_eq!(io.into_parts().unwrap().1, b"\0");
+    }
 }
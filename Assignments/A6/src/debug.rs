@@ -0,0 +1,391 @@
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+use rumdump::rumdis;
+use crate::heap::HeapStats;
+use crate::history::History;
+use crate::io::UmIo;
+use crate::um::{Um, UmEvent};
+
+const HELP: &str = "\
+commands:
+  b ADDR            set a breakpoint at program counter ADDR
+  d ADDR            delete the breakpoint at ADDR
+  w SEG OFF         watch $m[SEG][OFF] for changes
+  u SEG OFF         remove the watchpoint on $m[SEG][OFF]
+  s [N]             execute N instructions (default 1)
+  c                 continue until a breakpoint, watchpoint, halt or fault
+  rs [N]            step back over N instructions (default 1)
+  rc                run backwards until a breakpoint or watchpoint
+  rw SEG OFF        run back to the last write of $m[SEG][OFF]
+  rr REG            run back to the last write of register REG
+  r                 print the registers and program counter
+  m                 print segment heap statistics
+  dis SEG OFF [N]   disassemble N words of $m[SEG] starting at OFF
+  x SEG OFF [N]     dump N words of $m[SEG] starting at OFF as hex
+  h                 print this help
+  q                 quit
+numbers may be decimal or 0x-prefixed hex
+going back replays the recorded input and does not repeat output";
+
+/// A watched memory word and the value it held when last checked
+struct Watchpoint {
+    segment: u32,
+    offset: u32,
+    value: Option<u32>,
+}
+
+/// Why the debugger stopped running the program
+enum Stop {
+    Breakpoint,
+    Watchpoint(usize, Option<u32>),
+    Halted,
+    Faulted,
+    Done,
+}
+
+/// How far to run backwards
+enum Back {
+    Steps(u32),
+    Continue,
+    Word(u32, u32),
+    Register(usize),
+}
+
+/// An interactive debugger driving a Universal Machine.
+///
+/// Commands are read one per line; see `HELP` for the command set. Every
+/// instruction executed is recorded in a `History`, up to
+/// `history::LIMIT` of them, so the program can also be run backwards.
+pub struct Debugger<I: UmIo> {
+    um: Um<I>,
+    breakpoints: BTreeSet<usize>,
+    watchpoints: Vec<Watchpoint>,
+    history: History,
+}
+
+/// Parses a decimal or 0x-prefixed hex number
+fn number(word: &str) -> Option<u32> {
+    match word.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => word.parse().ok(),
+    }
+}
+
+impl<I: UmIo> Debugger<I> {
+    /// Creates a debugger for `um`, stopped before its first instruction
+    ///
+    /// # Arguments:
+    /// * `um`: The machine to debug
+    pub fn new(um: Um<I>) -> Self {
+        Debugger { um, breakpoints: BTreeSet::new(), watchpoints: Vec::new(), history: History::new() }
+    }
+
+    /// The machine being debugged
+    pub fn um(&mut self) -> &mut Um<I> {
+        &mut self.um
+    }
+
+    /// Reads and runs commands until `q` or the end of `commands`
+    ///
+    /// # Arguments:
+    /// * `commands`: Source of debugger commands
+    /// * `out`: Destination of debugger output
+    pub fn repl<R: BufRead, W: Write>(&mut self, mut commands: R, out: W) -> io::Result<()> {
+        self.repl_with(|line| commands.read_line(line), out)
+    }
+
+    /// Like `repl`, but gets each command line from `read_line`, which
+    /// appends a line to its argument and returns its length, or 0 at the
+    /// end. Nothing is held between lines, so the commands can come from
+    /// the same stdin as the program's Input.
+    ///
+    /// # Arguments:
+    /// * `read_line`: Source of debugger commands
+    /// * `out`: Destination of debugger output
+    pub fn repl_with<F: FnMut(&mut String) -> io::Result<usize>, W: Write>(&mut self, mut read_line: F, mut out: W)
+        -> io::Result<()> {
+        self.location(&mut out)?;
+        loop {
+            write!(out, "(umdb) ")?;
+            out.flush()?;
+            let mut line = String::new();
+            if read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.first() == Some(&"q") {
+                return Ok(());
+            }
+            self.command(&words, &mut out)?;
+        }
+    }
+
+    /// Runs a single command
+    fn command<W: Write>(&mut self, words: &[&str], out: &mut W) -> io::Result<()> {
+        let args: Option<Vec<u32>> = words.iter().skip(1).map(|w| number(w)).collect();
+        let args = match args {
+            Some(args) => args,
+            None => return writeln!(out, "arguments must be numbers"),
+        };
+        match (words.first().copied(), args.as_slice()) {
+            (None, _) => Ok(()),
+            (Some("b"), &[addr]) => {
+                self.breakpoints.insert(addr as usize);
+                writeln!(out, "breakpoint at {}", addr)
+            }
+            (Some("d"), &[addr]) => {
+                if !self.breakpoints.remove(&(addr as usize)) {
+                    writeln!(out, "no breakpoint at {}", addr)?;
+                }
+                Ok(())
+            }
+            (Some("w"), &[segment, offset]) => {
+                let value = self.peek(segment, offset);
+                self.watchpoints.push(Watchpoint { segment, offset, value });
+                writeln!(out, "watching $m[{}][{}]", segment, offset)
+            }
+            (Some("u"), &[segment, offset]) => {
+                self.watchpoints.retain(|w| w.segment != segment || w.offset != offset);
+                Ok(())
+            }
+            (Some("s"), &[]) => self.resume(Some(1), out),
+            (Some("s"), &[count]) => self.resume(Some(count as u64), out),
+            (Some("c"), &[]) => self.resume(None, out),
+            (Some("rs"), &[]) => self.reverse(Back::Steps(1), out),
+            (Some("rs"), &[count]) => self.reverse(Back::Steps(count), out),
+            (Some("rc"), &[]) => self.reverse(Back::Continue, out),
+            (Some("rw"), &[segment, offset]) => self.reverse(Back::Word(segment, offset), out),
+            (Some("rr"), &[r]) if r < 8 => self.reverse(Back::Register(r as usize), out),
+            (Some("r"), &[]) => self.registers(out),
+            (Some("m"), &[]) => HeapStats::of(self.um.state()).report(out),
+            (Some("dis"), &[segment, offset]) => self.dump(segment, offset, 1, true, out),
+            (Some("dis"), &[segment, offset, count]) => self.dump(segment, offset, count, true, out),
+            (Some("x"), &[segment, offset]) => self.dump(segment, offset, 1, false, out),
+            (Some("x"), &[segment, offset, count]) => self.dump(segment, offset, count, false, out),
+            (Some("h"), _) => writeln!(out, "{}", HELP),
+            _ => writeln!(out, "unknown command, type h for help"),
+        }
+    }
+
+    /// Reads $m[segment][offset], or None if it is not mapped
+    fn peek(&self, segment: u32, offset: u32) -> Option<u32> {
+        let state = self.um.state();
+        if state.checked && !state.mapped.get(segment as usize).copied().unwrap_or(false) {
+            return None;
+        }
+        state.memory.get(segment as usize)?.get(offset as usize).copied()
+    }
+
+    /// Executes `count` instructions, or until something stops the program
+    /// when `count` is None
+    fn resume<W: Write>(&mut self, count: Option<u64>, out: &mut W) -> io::Result<()> {
+        let mut executed = 0;
+        let stop = loop {
+            if count == Some(executed) {
+                break Stop::Done;
+            }
+            match self.history.step(&mut self.um) {
+                Ok(UmEvent::Halted(_)) => break Stop::Halted,
+                Ok(UmEvent::Running) => executed += 1,
+                Err(fault) => {
+                    self.um.io().flush()?;
+                    writeln!(out, "fault: {}", fault)?;
+                    break Stop::Faulted;
+                }
+            }
+            if let Some(hit) = self.changed_watchpoint() {
+                break Stop::Watchpoint(hit.0, hit.1);
+            }
+            if count.is_none() && self.breakpoints.contains(&self.um.state().program_counter) {
+                break Stop::Breakpoint;
+            }
+        };
+        self.um.io().flush()?;
+        match stop {
+            Stop::Halted => return writeln!(out, "program halted"),
+            Stop::Watchpoint(index, old) => {
+                let watch = &self.watchpoints[index];
+                writeln!(out, "watchpoint $m[{}][{}]: {} -> {}", watch.segment, watch.offset,
+                    show(old), show(watch.value))?;
+            }
+            Stop::Breakpoint => writeln!(out, "breakpoint")?,
+            Stop::Faulted | Stop::Done => {}
+        }
+        self.location(out)
+    }
+
+    /// Undoes instructions until `until` is satisfied or the history runs
+    /// out
+    fn reverse<W: Write>(&mut self, until: Back, out: &mut W) -> io::Result<()> {
+        let mut undone = 0;
+        loop {
+            if let Back::Steps(count) = until {
+                if undone == count {
+                    break;
+                }
+            }
+            let Some(instruction) = self.history.back(&mut self.um) else {
+                writeln!(out, "reached the start of the history")?;
+                break;
+            };
+            undone += 1;
+            let watchpoint = self.changed_watchpoint();
+            match until {
+                Back::Steps(_) => {}
+                Back::Continue => {
+                    if let Some((index, old)) = watchpoint {
+                        let watch = &self.watchpoints[index];
+                        writeln!(out, "watchpoint $m[{}][{}]: {} -> {}", watch.segment, watch.offset,
+                            show(old), show(watch.value))?;
+                        break;
+                    }
+                    if self.breakpoints.contains(&instruction.pc) {
+                        writeln!(out, "breakpoint")?;
+                        break;
+                    }
+                }
+                Back::Word(segment, offset) => {
+                    if instruction.wrote_word(segment, offset) {
+                        writeln!(out, "last write of $m[{}][{}]", segment, offset)?;
+                        break;
+                    }
+                }
+                Back::Register(r) => {
+                    if instruction.wrote_register(r) {
+                        writeln!(out, "last write of r{}", r)?;
+                        break;
+                    }
+                }
+            }
+        }
+        self.location(out)
+    }
+
+    /// Refreshes every watchpoint, returning the first that changed and
+    /// its old value
+    fn changed_watchpoint(&mut self) -> Option<(usize, Option<u32>)> {
+        let mut hit = None;
+        for index in 0..self.watchpoints.len() {
+            let watch = &self.watchpoints[index];
+            let value = self.peek(watch.segment, watch.offset);
+            if value != watch.value {
+                if hit.is_none() {
+                    hit = Some((index, watch.value));
+                }
+                self.watchpoints[index].value = value;
+            }
+        }
+        hit
+    }
+
+    /// Prints the next instruction to be executed
+    fn location<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let state = self.um.state();
+        match state.memory[0].get(state.program_counter) {
+            Some(&word) => writeln!(out, "{:>8}: {}", state.program_counter, rumdis::disassemble(word)),
+            None => writeln!(out, "{:>8}: <outside of $m[0]>", state.program_counter),
+        }
+    }
+
+    /// Prints the registers and program counter
+    fn registers<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let state = self.um.state();
+        for (i, value) in state.registers.iter().enumerate() {
+            writeln!(out, "r{} = 0x{:08x} ({})", i, value, value)?;
+        }
+        writeln!(out, "pc = {}", state.program_counter)
+    }
+
+    /// Prints `count` words of $m[segment] from `offset`
+    fn dump<W: Write>(&self, segment: u32, offset: u32, count: u32, disassemble: bool, out: &mut W)
+        -> io::Result<()> {
+        for at in offset..offset.saturating_add(count) {
+            match self.peek(segment, at) {
+                Some(word) if disassemble => writeln!(out, "{:>8}: {}", at, rumdis::disassemble(word))?,
+                Some(word) => writeln!(out, "{:>8}: 0x{:08x}", at, word)?,
+                None => return writeln!(out, "$m[{}][{}] is not mapped", segment, at),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Formats a watched value, which may be unmapped
+fn show(value: Option<u32>) -> String {
+    match value {
+        Some(value) => value.to_string(),
+        None => "unmapped".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::debug::Debugger;
+    use crate::io::StreamIo;
+    use crate::um::Um;
+
+    fn inst(opcode: u32, a: u32, b: u32, c: u32) -> u32 {
+        (opcode << 28) | (a << 6) | (b << 3) | c
+    }
+
+    fn session(program: Vec<u32>, commands: &str) -> String {
+        session_with_input(program, b"", commands).0
+    }
+
+    /// Returns what the debugger printed and what the program wrote
+    fn session_with_input(program: Vec<u32>, input: &[u8], commands: &str) -> (String, Vec<u8>) {
+        let mut output = Vec::new();
+        let um = Um::with_io(program, StreamIo::new(input, &mut output));
+        let mut out = Vec::new();
+        Debugger::new(um).repl(commands.as_bytes(), &mut out).unwrap();
+        (String::from_utf8(out).unwrap(), output)
+    }
+
+    #[test]
+    fn breakpoint_and_registers() {
+        let program = vec![inst(6, 1, 0, 0), inst(3, 2, 1, 1), inst(7, 0, 0, 0)];
+        let out = session(program, "b 2\nc\nr\nc\n");
+        assert!(out.contains("breakpoint\n       2: halt"));
+        assert!(out.contains("r2 = 0xfffffffe"));
+        assert!(out.contains("program halted"));
+    }
+    #[test]
+    fn watchpoint_stops_on_store() {
+        // r1 := map segment (r2 words); m[r1][r0] := r2
+        let program = vec![inst(13, 0, 0, 0) | (2 << 25) | 4, inst(8, 0, 1, 2),
+            inst(2, 1, 0, 2), inst(7, 0, 0, 0)];
+        let out = session(program, "s 2\nw 1 0\nc\nx 1 0 2\nm\n");
+        assert!(out.contains("watchpoint $m[1][0]: 0 -> 4"));
+        assert!(out.contains("       0: 0x00000004\n       1: 0x00000000"));
+        assert!(out.contains("mapped words                      8\n"));
+    }
+    #[test]
+    fn runs_back_to_writes() {
+        let program = vec![inst(13, 0, 0, 0) | (2 << 25) | 4, inst(8, 0, 1, 2),
+            inst(2, 1, 0, 2), inst(7, 0, 0, 0)];
+        let out = session(program, "c\nrw 1 0\nx 1 0\nrw 1 0\nrs 2\n");
+        assert!(out.contains("last write of $m[1][0]\n       2: "));
+        assert!(out.contains("       0: 0x00000000"));
+        assert!(out.contains("last write of $m[1][0]\n       1: "));
+        assert!(out.contains("reached the start of the history\n       0: "));
+    }
+    #[test]
+    fn replays_input_after_going_back() {
+        let program = vec![inst(13, 0, 0, 0) | (3 << 25) | 7, inst(11, 0, 0, 1), inst(11, 0, 0, 2),
+            inst(10, 0, 0, 1), inst(13, 0, 0, 0) | (3 << 25) | 9, inst(10, 0, 0, 2), inst(7, 0, 0, 0)];
+        let (out, output) = session_with_input(program, b"ab", "c\nrr 3\nrs 3\nr\nc\nr\n");
+        assert!(out.contains("last write of r3\n       4: "));
+        assert!(out.contains("r1 = 0x00000000 (0)\nr2 = 0x00000000 (0)\nr3 = 0x00000007 (7)"));
+        assert!(out.contains("r1 = 0x00000061 (97)\nr2 = 0x00000062 (98)\nr3 = 0x00000009 (9)"));
+        assert_eq!(output, b"ab");
+    }
+    #[test]
+    fn program_reads_input_while_debugged() {
+        // r1 := input; output r1; halt
+        let program = vec![inst(11, 0, 0, 1), inst(10, 0, 0, 1), inst(7, 0, 0, 0)];
+        let (out, output) = session_with_input(program, b"X", "s\nr\nc\n");
+        assert!(out.contains("r1 = 0x00000058 (88)"));
+        assert!(out.contains("program halted"));
+        assert_eq!(output, b"X");
+    }
+}
@@ -0,0 +1,309 @@
+//! Memory statistics for the segment heap, to help spot programs that leak
+//! segments.
+//!
+//! `HeapStats` describes the heap at one moment. `HeapMonitor` watches a
+//! running machine for its peak usage and can write a timeline of every
+//! Map Segment and Unmap Segment as CSV or JSON:
+//!
+//! ```text
+//! executed,pc,event,segment,words,mapped_words,live_segments
+//! 3,2,map,1,5,9,2
+//! ```
+//!
+//! `executed` counts the event's own instruction, `words` is the length of
+//! the segment mapped or unmapped, and the last two columns are the totals
+//! after the event. The JSON form is an array of objects with the same
+//! fields.
+
+use std::io::{self, Write};
+use std::str::FromStr;
+use crate::fault::{HaltReason, UmFault};
+use crate::io::UmIo;
+use crate::memory::{get, UmState, OP, RB, RC};
+use crate::um::{Um, UmEvent};
+
+/// The heap of a machine at one moment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeapStats {
+    /// Mapped segments, $m[0] included
+    pub live_segments: usize,
+    /// Words in all mapped segments
+    pub mapped_words: usize,
+    /// Segment IDs waiting in `unmap_index_values` to be reused
+    pub free_ids: usize,
+    /// Segment IDs ever handed out, $m[0] included
+    pub id_slots: usize,
+    /// Mapped segments by length: bucket 0 counts empty segments and bucket
+    /// i counts lengths from 2^(i-1) up to 2^i - 1
+    pub histogram: Vec<usize>,
+}
+
+impl HeapStats {
+    /// Measures the heap of `state`
+    ///
+    /// # Arguments:
+    /// * `state`: The machine state to measure
+    pub fn of(state: &UmState) -> Self {
+        let mut histogram = Vec::new();
+        for (segment, _) in state.memory.iter().zip(&state.mapped).filter(|&(_, &mapped)| mapped) {
+            let bucket = (usize::BITS - segment.len().leading_zeros()) as usize;
+            if histogram.len() <= bucket {
+                histogram.resize(bucket + 1, 0);
+            }
+            histogram[bucket] += 1;
+        }
+        HeapStats {
+            live_segments: state.live_segments,
+            mapped_words: state.mapped_words,
+            free_ids: state.unmap_index_values.len(),
+            id_slots: state.memory.len(),
+            histogram,
+        }
+    }
+
+    /// The share of segment IDs that are holes left by Unmap Segment, from
+    /// 0 to 1
+    pub fn fragmentation(&self) -> f64 {
+        self.free_ids as f64 / self.id_slots.max(1) as f64
+    }
+
+    /// Prints the statistics
+    ///
+    /// # Arguments:
+    /// * `out`: Destination of the report
+    pub fn report<W: Write>(&self, mut out: W) -> io::Result<()> {
+        writeln!(out, "{:<20} {:>14}", "live segments", self.live_segments)?;
+        writeln!(out, "{:<20} {:>14}", "mapped words", self.mapped_words)?;
+        writeln!(out, "{:<20} {:>14} of {} ({:.2}% fragmentation)", "free segment IDs", self.free_ids,
+            self.id_slots, 100.0 * self.fragmentation())?;
+        writeln!(out, "segment sizes:")?;
+        for (bucket, &count) in self.histogram.iter().enumerate().filter(|&(_, &count)| count > 0) {
+            let sizes = match bucket {
+                0 => "0".to_string(),
+                1 => "1".to_string(),
+                _ => format!("{}-{}", 1_u64 << (bucket - 1), (1_u64 << bucket) - 1),
+            };
+            writeln!(out, "{:>20} {:>14}", sizes, count)?;
+        }
+        Ok(())
+    }
+}
+
+/// How a heap timeline is written
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimelineFormat {
+    #[default]
+    Csv,
+    Json,
+}
+
+impl FromStr for TimelineFormat {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Self, ()> {
+        match name {
+            "csv" => Ok(TimelineFormat::Csv),
+            "json" => Ok(TimelineFormat::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Watches a machine's segment heap for its peak usage and, optionally,
+/// writes a timeline of its map and unmap events.
+///
+/// Like `Tracer`, a failure to write the timeline does not stop the
+/// machine; the timeline stops and the error is returned by `finish`.
+pub struct HeapMonitor<W: Write = io::Sink> {
+    /// Most words mapped at once
+    pub peak_words: usize,
+    /// Most segments mapped at once
+    pub peak_segments: usize,
+    /// Map Segment instructions executed
+    pub maps: u64,
+    /// Unmap Segment instructions executed
+    pub unmaps: u64,
+    timeline: Option<(W, TimelineFormat)>,
+    error: Option<io::Error>,
+}
+
+impl HeapMonitor {
+    /// Creates a monitor that writes no timeline
+    pub fn new() -> Self {
+        HeapMonitor { peak_words: 0, peak_segments: 0, maps: 0, unmaps: 0, timeline: None, error: None }
+    }
+}
+
+impl Default for HeapMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<W: Write> HeapMonitor<W> {
+    /// Creates a monitor and starts a timeline in `out`
+    ///
+    /// # Arguments:
+    /// * `out`: Destination of the timeline
+    /// * `format`: How to write the timeline
+    pub fn with_timeline(mut out: W, format: TimelineFormat) -> io::Result<Self> {
+        match format {
+            TimelineFormat::Csv => writeln!(out, "executed,pc,event,segment,words,mapped_words,live_segments")?,
+            TimelineFormat::Json => write!(out, "[")?,
+        }
+        Ok(HeapMonitor {
+            peak_words: 0,
+            peak_segments: 0,
+            maps: 0,
+            unmaps: 0,
+            timeline: Some((out, format)),
+            error: None,
+        })
+    }
+
+    /// Executes one instruction of `um`, noting what it does to the heap
+    ///
+    /// # Arguments:
+    /// * `um`: The machine to step
+    pub fn step<I: UmIo>(&mut self, um: &mut Um<I>) -> Result<UmEvent, UmFault> {
+        let state = um.state();
+        let pc = state.program_counter;
+        let instruction = state.memory[0].get(pc).copied().unwrap_or(0);
+        let opcode = get(&OP, instruction);
+        let c = state.registers[get(&RC, instruction) as usize];
+        // The length of the segment about to be mapped or unmapped
+        let words = match opcode {
+            8 => c as usize,
+            9 => state.memory.get(c as usize).map_or(0, |segment| segment.len()),
+            _ => 0,
+        };
+        let executed = um.stats().executed;
+
+        let event = um.step()?;
+        let state = um.state();
+        self.peak_words = self.peak_words.max(state.mapped_words);
+        self.peak_segments = self.peak_segments.max(state.live_segments);
+        if um.stats().executed == executed {
+            return Ok(event);
+        }
+        let (name, segment) = match opcode {
+            8 => {
+                self.maps += 1;
+                ("map", state.registers[get(&RB, instruction) as usize])
+            }
+            9 => {
+                self.unmaps += 1;
+                ("unmap", c)
+            }
+            _ => return Ok(event),
+        };
+        if let (Some((out, format)), None) = (&mut self.timeline, &self.error) {
+            let (executed, mapped_words, live_segments) = (executed + 1, state.mapped_words, state.live_segments);
+            let written = match format {
+                TimelineFormat::Csv => writeln!(out, "{},{},{},{},{},{},{}", executed, pc, name, segment, words,
+                    mapped_words, live_segments),
+                TimelineFormat::Json => write!(out, "{}\n{{\"executed\":{},\"pc\":{},\"event\":\"{}\",\"segment\":{},\
+                    \"words\":{},\"mapped_words\":{},\"live_segments\":{}}}",
+                    if self.maps + self.unmaps > 1 { "," } else { "" }, executed, pc, name, segment, words,
+                    mapped_words, live_segments),
+            };
+            if let Err(error) = written {
+                self.error = Some(error);
+            }
+        }
+        Ok(event)
+    }
+
+    /// Executes and watches instructions until the machine halts
+    ///
+    /// # Arguments:
+    /// * `um`: The machine to run
+    pub fn run<I: UmIo>(&mut self, um: &mut Um<I>) -> Result<HaltReason, UmFault> {
+        loop {
+            if let UmEvent::Halted(reason) = self.step(um)? {
+                return Ok(reason);
+            }
+        }
+    }
+
+    /// Prints the peaks and event counts, followed by the statistics of
+    /// the heap as it is now
+    ///
+    /// # Arguments:
+    /// * `state`: The state of the machine that was watched
+    /// * `out`: Destination of the report
+    pub fn report<V: Write>(&self, state: &UmState, mut out: V) -> io::Result<()> {
+        let stats = HeapStats::of(state);
+        writeln!(out, "{:<20} {:>14}", "peak mapped words", self.peak_words.max(stats.mapped_words))?;
+        writeln!(out, "{:<20} {:>14}", "peak live segments", self.peak_segments.max(stats.live_segments))?;
+        writeln!(out, "{:<20} {:>14}", "maps", self.maps)?;
+        writeln!(out, "{:<20} {:>14}", "unmaps", self.unmaps)?;
+        stats.report(out)
+    }
+
+    /// Ends the timeline and returns its writer, or the first write error,
+    /// or None if there is no timeline
+    pub fn finish(self) -> Option<io::Result<W>> {
+        let (mut out, format) = self.timeline?;
+        if let Some(error) = self.error {
+            return Some(Err(error));
+        }
+        let ended = match format {
+            TimelineFormat::Csv => Ok(()),
+            TimelineFormat::Json => writeln!(out, "\n]"),
+        };
+        Some(ended.and_then(|()| out.flush()).map(|()| out))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::heap::{HeapMonitor, HeapStats, TimelineFormat};
+    use crate::io::StreamIo;
+    use crate::um::Um;
+
+    fn inst(opcode: u32, a: u32, b: u32, c: u32) -> u32 {
+        (opcode << 28) | (a << 6) | (b << 3) | c
+    }
+
+    /// Maps segments of 5 and 1 words, unmaps the first and maps 0 words
+    fn program() -> Vec<u32> {
+        vec![(13 << 28) | (1 << 25) | 5, (13 << 28) | (2 << 25) | 1, inst(8, 0, 3, 1), inst(8, 0, 4, 2),
+            inst(9, 0, 0, 3), inst(8, 0, 5, 0), inst(7, 0, 0, 0)]
+    }
+
+    #[test]
+    fn measures_the_heap() {
+        let mut um = Um::with_io(program(), StreamIo::new(&b""[..], Vec::new()));
+        let mut monitor = HeapMonitor::with_timeline(Vec::new(), TimelineFormat::Csv).unwrap();
+        monitor.run(&mut um).unwrap();
+        assert_eq!((monitor.peak_words, monitor.peak_segments, monitor.maps, monitor.unmaps), (13, 3, 3, 1));
+        let stats = HeapStats::of(um.state());
+        assert_eq!((stats.live_segments, stats.mapped_words, stats.free_ids, stats.id_slots), (3, 8, 0, 3));
+        assert_eq!(stats.histogram, vec![1, 1, 0, 1]);
+
+        let mut report = Vec::new();
+        monitor.report(um.state(), &mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(report.contains("peak mapped words                13\n"));
+        assert!(report.contains("0.00% fragmentation"));
+        assert!(report.contains("                 4-7              1\n"));
+
+        let timeline = String::from_utf8(monitor.finish().unwrap().unwrap()).unwrap();
+        assert_eq!(timeline, "executed,pc,event,segment,words,mapped_words,live_segments\n\
+            3,2,map,1,5,12,2\n4,3,map,2,1,13,3\n5,4,unmap,1,5,8,2\n6,5,map,1,0,8,3\n");
+    }
+    #[test]
+    fn writes_json_timelines() {
+        let mut um = Um::with_io(program()[..5].to_vec(), StreamIo::new(&b""[..], Vec::new()));
+        let mut monitor = HeapMonitor::with_timeline(Vec::new(), TimelineFormat::Json).unwrap();
+        for _ in 0..5 {
+            monitor.step(&mut um).unwrap();
+        }
+        assert_eq!(HeapStats::of(um.state()).fragmentation(), 1.0 / 3.0);
+        let timeline = String::from_utf8(monitor.finish().unwrap().unwrap()).unwrap();
+        assert!(timeline.starts_with("[\n{\"executed\":3,\"pc\":2,\"event\":\"map\",\"segment\":1,\"words\":5,"));
+        assert!(timeline.ends_with(",\n{\"executed\":5,\"pc\":4,\"event\":\"unmap\",\"segment\":1,\"words\":5,\
+            \"mapped_words\":6,\"live_segments\":2}\n]\n"));
+    }
+}
@@ -0,0 +1,361 @@
+//! An undo log of everything a machine changes, so the debugger can run it
+//! backwards.
+//!
+//! `History::step` executes one instruction and remembers what it is about
+//! to overwrite: the registers and program counter, the word a Store
+//! replaces, the segment and free-list entry a Map or Unmap takes or
+//! gives back, and the $m[0] a Load Program discards. `History::back`
+//! puts one instruction's worth back.
+//!
+//! Input and Output cannot be taken back, so stepping back over them keeps
+//! the input byte and counts the output byte instead. Stepping forward
+//! again replays the recorded bytes rather than reading new ones, and does
+//! not write the output a second time, so the program sees exactly what it
+//! saw the first time. A replayed instruction still counts as executed and
+//! is subject to the machine's limits.
+
+use std::collections::VecDeque;
+use std::rc::Rc;
+use crate::fault::UmFault;
+use crate::io::UmIo;
+use crate::memory::{get, Segment, OP, RA, RB, RC, RL};
+use crate::um::{Um, UmEvent, UmStats};
+
+/// The most instructions a history remembers; older ones are forgotten
+pub const LIMIT: usize = 1 << 20;
+
+/// What an instruction changed besides the registers and program counter
+enum Change {
+    None,
+    /// A Store replaced this word
+    Word { segment: usize, offset: usize, value: u32 },
+    /// Map Segment took this ID, replacing the unmapped segment in its slot
+    /// if it came from the free list
+    Map { id: usize, old: Option<(Segment, bool)> },
+    /// Unmap Segment dropped this segment and put its ID on the free list
+    Unmap { id: usize, segment: Segment, mapped: bool },
+    /// Load Program replaced this $m[0]
+    Program(Segment),
+    /// Input delivered this byte, or None at the end of input
+    Input(Option<u8>),
+    Output,
+}
+
+/// How to undo one instruction
+struct Undo {
+    pc: usize,
+    registers: [u32; 8],
+    stats: UmStats,
+    mapped_words: usize,
+    live_segments: usize,
+    change: Change,
+}
+
+/// An instruction that `History::back` undid
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Undone {
+    /// Its address, where the program counter now is
+    pub pc: usize,
+    /// The register it wrote, if any
+    register: Option<usize>,
+    /// The segment it wrote, and the offset if it wrote a single word
+    written: Option<(u32, Option<u32>)>,
+}
+
+impl Undone {
+    /// Whether the instruction wrote register `r`
+    pub fn wrote_register(&self, r: usize) -> bool {
+        self.register == Some(r)
+    }
+
+    /// Whether the instruction changed $m[segment][offset], by storing to
+    /// it or by mapping, unmapping or replacing the whole segment
+    pub fn wrote_word(&self, segment: u32, offset: u32) -> bool {
+        matches!(self.written, Some((s, o)) if s == segment && o.is_none_or(|o| o == offset))
+    }
+}
+
+/// The undo log of a machine, most recent instruction last
+pub struct History {
+    undo: VecDeque<Undo>,
+    /// Input to hand out again, next byte last
+    replay: Vec<Option<u8>>,
+    /// Output bytes to swallow because they were already written
+    written: u64,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl History {
+    /// Creates an empty history
+    pub fn new() -> Self {
+        History { undo: VecDeque::new(), replay: Vec::new(), written: 0 }
+    }
+
+    /// Instructions that can be stepped back over
+    pub fn len(&self) -> usize {
+        self.undo.len()
+    }
+
+    /// Whether there is nothing to step back over
+    pub fn is_empty(&self) -> bool {
+        self.undo.is_empty()
+    }
+
+    /// Executes one instruction of `um` and records how to undo it. An
+    /// instruction that faults changes nothing and is not recorded.
+    ///
+    /// # Arguments:
+    /// * `um`: The machine to step
+    pub fn step<I: UmIo>(&mut self, um: &mut Um<I>) -> Result<UmEvent, UmFault> {
+        let state = um.state();
+        let pc = state.program_counter;
+        let word = state.memory[0].get(pc).copied().unwrap_or(0);
+        let mut registers = [0_u32; 8];
+        registers.copy_from_slice(&state.registers);
+        let a = get(&RA, word) as usize;
+        let b = get(&RB, word) as usize;
+        let c = get(&RC, word) as usize;
+        let mut undo = Undo {
+            pc,
+            registers,
+            stats: um.stats(),
+            mapped_words: state.mapped_words,
+            live_segments: state.live_segments,
+            change: Change::None,
+        };
+        let opcode = get(&OP, word);
+        let replayed = match opcode {
+            11 => self.replay.last().map(|&input| Change::Input(input)),
+            10 if self.written > 0 && registers[c] <= u8::MAX as u32 => Some(Change::Output),
+            _ => None,
+        };
+        if let Some(change) = replayed {
+            let event = um.step_replayed(|state, stats| match change {
+                Change::Input(Some(byte)) => {
+                    state.registers[c] = u32::from(byte);
+                    stats.bytes_read += 1;
+                }
+                Change::Input(None) => {
+                    state.registers[c] = u32::MAX;
+                    stats.input_eof = true;
+                }
+                _ => stats.bytes_written += 1,
+            })?;
+            if um.stats().executed == undo.stats.executed {
+                return Ok(event);
+            }
+            match change {
+                Change::Input(_) => {
+                    self.replay.pop();
+                }
+                _ => self.written -= 1,
+            }
+            undo.change = change;
+            self.push(undo);
+            return Ok(event);
+        }
+        undo.change = match opcode {
+            2 => {
+                let (segment, offset) = (registers[a] as usize, registers[b] as usize);
+                let value = state.memory.get(segment).and_then(|s| s.get(offset)).copied().unwrap_or(0);
+                Change::Word { segment, offset, value }
+            }
+            8 => {
+                let id = state.unmap_index_values.last().copied().unwrap_or(state.memory.len());
+                Change::Map { id, old: state.memory.get(id).map(|s| (Rc::clone(s), state.mapped[id])) }
+            }
+            9 => {
+                let id = registers[c] as usize;
+                let segment = state.memory.get(id).cloned().unwrap_or_default();
+                Change::Unmap { id, segment, mapped: state.mapped.get(id).copied().unwrap_or(false) }
+            }
+            12 if registers[b] != 0 => Change::Program(Rc::clone(&state.memory[0])),
+            _ => Change::None,
+        };
+
+        let event = um.step()?;
+        if um.stats().executed == undo.stats.executed {
+            // Already halted, so nothing happened
+            return Ok(event);
+        }
+        match opcode {
+            10 => undo.change = Change::Output,
+            11 => undo.change = Change::Input(u8::try_from(um.state().registers[c]).ok()),
+            _ => {}
+        }
+        self.push(undo);
+        Ok(event)
+    }
+
+    fn push(&mut self, undo: Undo) {
+        if self.undo.len() == LIMIT {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(undo);
+    }
+
+    /// Undoes the last instruction recorded, returning it, or None if the
+    /// history is empty
+    ///
+    /// # Arguments:
+    /// * `um`: The machine the history was recorded from
+    pub fn back<I: UmIo>(&mut self, um: &mut Um<I>) -> Option<Undone> {
+        let undo = self.undo.pop_back()?;
+        let state = um.state_mut();
+        let mut written = None;
+        match undo.change {
+            Change::None => {}
+            Change::Word { segment, offset, value } => {
+                Rc::make_mut(&mut state.memory[segment])[offset] = value;
+                if segment == 0 {
+                    state.code.store(offset, value);
+                }
+                written = Some((segment as u32, Some(offset as u32)));
+            }
+            Change::Map { id, old } => {
+                match old {
+                    Some((segment, mapped)) => {
+                        state.memory[id] = segment;
+                        state.mapped[id] = mapped;
+                        state.unmap_index_values.push(id);
+                    }
+                    None => {
+                        state.memory.pop();
+                        state.mapped.pop();
+                    }
+                }
+                written = Some((id as u32, None));
+            }
+            Change::Unmap { id, segment, mapped } => {
+                state.memory[id] = segment;
+                state.mapped[id] = mapped;
+                // Unmapping an unmapped segment left the free list alone
+                if mapped {
+                    state.unmap_index_values.pop();
+                }
+                written = Some((id as u32, None));
+            }
+            Change::Program(program) => {
+                state.code.load(&state.memory[0], &program);
+                state.memory[0] = program;
+                written = Some((0, None));
+            }
+            Change::Input(input) => self.replay.push(input),
+            Change::Output => self.written += 1,
+        }
+        state.registers.copy_from_slice(&undo.registers);
+        state.program_counter = undo.pc;
+        state.mapped_words = undo.mapped_words;
+        state.live_segments = undo.live_segments;
+        let word = state.memory[0][undo.pc];
+        *um.stats_mut() = undo.stats;
+        um.resume();
+
+        let register = match get(&OP, word) {
+            0 if undo.registers[get(&RC, word) as usize] == 0 => None,
+            0 | 1 | 3..=6 => Some(get(&RA, word) as usize),
+            8 => Some(get(&RB, word) as usize),
+            11 => Some(get(&RC, word) as usize),
+            13 => Some(get(&RL, word) as usize),
+            _ => None,
+        };
+        Some(Undone { pc: undo.pc, register, written })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fault::UmFault;
+    use crate::history::History;
+    use crate::io::StreamIo;
+    use crate::limits::Limits;
+    use crate::um::{Um, UmEvent};
+
+    fn inst(opcode: u32, a: u32, b: u32, c: u32) -> u32 {
+        (opcode << 28) | (a << 6) | (b << 3) | c
+    }
+
+    fn value(register: u32, value: u32) -> u32 {
+        (13 << 28) | (register << 25) | value
+    }
+
+    #[test]
+    fn back_restores_everything() {
+        let program = vec![
+            value(2, 3),
+            inst(8, 0, 1, 2),  // r1 := map 3 words
+            inst(11, 0, 0, 5), // r5 := input
+            inst(2, 1, 0, 5),  // m[r1][0] := r5
+            inst(9, 0, 0, 1),  // unmap r1
+            inst(8, 0, 3, 2),  // r3 := map 3 words, reusing the ID
+            inst(2, 3, 2, 2),  // m[r3][3] faults
+            value(6, 7),
+            inst(2, 0, 6, 5),  // m[0][7] := r5
+            inst(12, 0, 3, 0), // load program m[r3]
+        ];
+        let mut um = Um::with_io(program.clone(), StreamIo::new(&b"x"[..], Vec::new()));
+        let mut history = History::new();
+        for _ in 0..6 {
+            assert_eq!(history.step(&mut um).unwrap(), UmEvent::Running);
+        }
+        assert!(history.step(&mut um).is_err());
+        um.state_mut().program_counter += 1;
+        for _ in 0..3 {
+            history.step(&mut um).unwrap();
+        }
+        assert_eq!(um.state().memory[0].len(), 3);
+        assert_eq!(history.len(), 9);
+
+        let undone = history.back(&mut um).unwrap();
+        assert_eq!(undone.pc, 9);
+        assert!(undone.wrote_word(0, 5));
+        assert_eq!(*um.state().memory[0], {
+            let mut patched = program.clone();
+            patched[7] = u32::from(b'x');
+            patched
+        });
+        assert!(history.back(&mut um).unwrap().wrote_word(0, 7));
+        assert!(history.back(&mut um).unwrap().wrote_register(6));
+        while history.back(&mut um).is_some() {}
+
+        let state = um.state();
+        assert_eq!(*state.memory[0], program);
+        assert_eq!(state.registers, vec![0; 8]);
+        assert_eq!((state.memory.len(), state.mapped.len(), state.unmap_index_values.len()), (1, 1, 0));
+        assert_eq!((state.mapped_words, state.live_segments), (program.len(), 1));
+        assert_eq!(um.stats(), Default::default());
+
+        // Forward again, the input is replayed rather than read
+        for _ in 0..3 {
+            history.step(&mut um).unwrap();
+        }
+        assert_eq!(um.state().registers[5], u32::from(b'x'));
+        assert_eq!(um.stats().bytes_read, 1);
+    }
+    #[test]
+    fn replay_is_limited() {
+        let program = vec![inst(11, 0, 0, 1), inst(10, 0, 0, 1), inst(7, 0, 0, 0)];
+        let mut um = Um::with_io(program, StreamIo::new(&b"x"[..], Vec::new()));
+        let mut history = History::new();
+        for _ in 0..2 {
+            history.step(&mut um).unwrap();
+        }
+        while history.back(&mut um).is_some() {}
+
+        // The replayed Input and Output count towards the limit as they did
+        um.set_limits(Limits { instructions: Some(1), ..Limits::default() });
+        assert_eq!(history.step(&mut um), Ok(UmEvent::Running));
+        assert_eq!(um.stats().executed, 1);
+        assert!(matches!(history.step(&mut um), Err(UmFault::LimitExceeded { pc: 1, .. })));
+        assert_eq!(history.len(), 1);
+        um.set_limits(Limits::default());
+        history.step(&mut um).unwrap();
+        assert_eq!((um.stats().executed, um.stats().bytes_written), (2, 1));
+        assert_eq!(um.into_io().into_parts().unwrap().1, b"x");
+    }
+}
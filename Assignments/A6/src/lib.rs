@@ -1,2 +1,9 @@
-pub mod machine;
-pub mod memory;
+pub mod debug;
+pub mod heap;
+pub mod history;
+pub mod profile;
+pub mod replay;
+pub mod snapshot;
+pub mod trace;
+
+pub use umcore::{decode, fault, io, limits, loader, machine, memory, sched, um};
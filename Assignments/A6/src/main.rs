@@ -1,13 +1,340 @@
-mod memory;
-pub mod machine;
 use std::env;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::process;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::time::{Duration, Instant};
+use rum::debug::Debugger;
+use rum::fault::{HaltReason, UmFault};
+use rum::heap::HeapMonitor;
+use rum::io::{StdIo, UmIo};
+use rum::limits::Limits;
+use rum::loader::{self, Encoding};
+use rum::profile::Profiler;
+use rum::replay::{InputRecorder, InputReplayer, ReplayError};
+use rum::snapshot;
+use rum::trace::Tracer;
+use rum::um::{Engine, Um, UmEvent};
 
-/// Main function to run the program.
+const USAGE: &str = "usage: rum [--checked] [--debug | --trace FILE | --profile | --save-on-signal FILE \
+| --record-input FILE | --replay-input FILE | [--memory-stats] [--heap-timeline FILE]] \
+[--max-words N] [--max-segments N] [--max-instructions N] [--time-limit SECONDS] \
+[--format be|le|hex] [--engine interpreter|threaded] [--dump-registers] [--restore SNAPSHOT | program.um]";
+
+/// Exit status when a resource limit stops the program
+const LIMIT_EXIT: i32 = 3;
+
+/// Command-line options
+#[derive(Default)]
+struct Options {
+    input: Option<String>,
+    checked: bool,
+    debug: bool,
+    trace: Option<String>,
+    profile: bool,
+    save_on_signal: Option<String>,
+    record_input: Option<String>,
+    replay_input: Option<String>,
+    memory_stats: bool,
+    heap_timeline: Option<String>,
+    restore: Option<String>,
+    limits: Limits,
+    format: Encoding,
+    engine: Engine,
+    dump_registers: bool,
+}
+
+/// Prints the usage message and exits
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+/// Reports an error and exits
+fn fail(message: impl Display) -> ! {
+    eprintln!("rum: {}", message);
+    process::exit(1);
+}
+
+/// Parses the argument of a limit option, exiting with the usage message
+/// if it is missing or not a number
+fn limit<T: FromStr>(arg: Option<String>) -> T {
+    arg.and_then(|arg| arg.parse().ok()).unwrap_or_else(|| usage())
+}
+
+/// Parses the command line.
 /// 
 /// Arguments:
-/// 
+/// * `--checked`: fault on any use of an unmapped segment
+/// * `--debug`: run the program under the interactive debugger
+/// * `--trace FILE`: record every executed instruction to FILE
+/// * `--profile`: print an execution profile to stderr on exit
+/// * `--save-on-signal FILE`: on SIGINT or SIGTERM, save a snapshot to FILE
+/// * `--record-input FILE`: record every value Input delivers to FILE
+/// * `--replay-input FILE`: take the program's input from a recording made
+///   with `--record-input` instead of stdin
+/// * `--memory-stats`: print segment heap statistics to stderr on exit
+/// * `--heap-timeline FILE`: write every map and unmap to FILE, as JSON if
+///   its name ends in `.json` and as CSV otherwise
+/// * `--restore SNAPSHOT`: resume a saved snapshot instead of loading a
+///   program; stdin should be the same input the saved run was reading
+/// * `--max-words N`: fault once mapped segments would hold more than N words
+/// * `--max-segments N`: fault once more than N segments would be mapped
+/// * `--max-instructions N`: stop after executing N instructions
+/// * `--time-limit SECONDS`: stop after running for SECONDS
+/// * `--engine interpreter|threaded`: how to execute the program, unless it
+///   is being debugged, traced, profiled, its input recorded or replayed or
+///   its heap watched
+/// * `--format be|le|hex`: how the program's words are stored, big-endian
+///   `.um` by default
+/// * `--dump-registers`: print the final registers to stderr on exit
+/// * an optional `.um` file, otherwise the program is read from stdin
+fn parse_args() -> Options {
+    let mut options = Options::default();
+    // `--flag=value` is the same as `--flag value`
+    let mut args = env::args().skip(1).flat_map(|arg| match arg.split_once('=') {
+        Some((flag, value)) if flag.starts_with("--") => vec![flag.to_string(), value.to_string()],
+        _ => vec![arg],
+    });
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--checked" => options.checked = true,
+            "--debug" => options.debug = true,
+            "--trace" => options.trace = Some(args.next().unwrap_or_else(|| usage())),
+            "--profile" => options.profile = true,
+            "--save-on-signal" => options.save_on_signal = Some(args.next().unwrap_or_else(|| usage())),
+            "--record-input" => options.record_input = Some(args.next().unwrap_or_else(|| usage())),
+            "--replay-input" => options.replay_input = Some(args.next().unwrap_or_else(|| usage())),
+            "--memory-stats" => options.memory_stats = true,
+            "--dump-registers" => options.dump_registers = true,
+            "--heap-timeline" => options.heap_timeline = Some(args.next().unwrap_or_else(|| usage())),
+            "--restore" => options.restore = Some(args.next().unwrap_or_else(|| usage())),
+            "--max-words" => options.limits.mapped_words = Some(limit(args.next())),
+            "--max-segments" => options.limits.live_segments = Some(limit(args.next())),
+            "--max-instructions" => options.limits.instructions = Some(limit(args.next())),
+            "--engine" => options.engine = args.next().and_then(|name| name.parse().ok()).unwrap_or_else(|| usage()),
+            "--format" => options.format = args.next().and_then(|name| name.parse().ok()).unwrap_or_else(|| usage()),
+            "--time-limit" => {
+                let seconds: f64 = limit(args.next());
+                let time = Duration::try_from_secs_f64(seconds).unwrap_or_else(|_| usage());
+                options.limits.wall_clock = Some(time);
+            }
+            _ if arg.starts_with("--") => usage(),
+            _ => options.input = Some(arg),
+        }
+    }
+    let modes = [options.debug, options.trace.is_some(), options.profile, options.save_on_signal.is_some(),
+        options.record_input.is_some(), options.replay_input.is_some(),
+        options.memory_stats || options.heap_timeline.is_some()];
+    if modes.iter().filter(|&&mode| mode).count() > 1 {
+        usage();
+    }
+    if options.restore.is_some() && (options.input.is_some() || options.format != Encoding::BigEndian) {
+        usage();
+    }
+    options
+}
+
+/// Runs `um`, recording every instruction to `filename`
+fn trace(um: &mut Um, filename: &str) -> Result<HaltReason, UmFault> {
+    let file = File::create(filename).unwrap_or_else(|e| fail(format!("{}: {}", filename, e)));
+    let mut tracer = Tracer::new(BufWriter::new(file)).unwrap_or_else(|e| fail(e));
+    let result = tracer.run(um);
+    if let Err(error) = tracer.finish() {
+        fail(format!("{}: {}", filename, error));
+    }
+    result
+}
+
+/// Runs `um` and prints its profile to stderr
+fn profile(um: &mut Um) -> Result<HaltReason, UmFault> {
+    let mut profiler = Profiler::new();
+    let result = profiler.run(um);
+    if let Err(error) = profiler.report(io::stderr().lock(), 20) {
+        fail(error);
+    }
+    result
+}
+
+/// Runs `um`, recording the values its Input instructions deliver to
+/// `filename`
+fn record_input(um: &mut Um, filename: &str) -> Result<HaltReason, UmFault> {
+    let file = File::create(filename).unwrap_or_else(|e| fail(format!("{}: {}", filename, e)));
+    let mut recorder = InputRecorder::new(BufWriter::new(file)).unwrap_or_else(|e| fail(e));
+    let result = recorder.run(um);
+    if let Err(error) = recorder.finish() {
+        fail(format!("{}: {}", filename, error));
+    }
+    result
+}
+
+/// Runs `um` on the input recorded in `filename`, exiting if the program
+/// reads more or less input than was recorded
+fn replay_input(um: &mut Um, filename: &str) -> Result<HaltReason, UmFault> {
+    let file = File::open(filename).unwrap_or_else(|e| fail(format!("{}: {}", filename, e)));
+    let mut replayer = InputReplayer::read(file).unwrap_or_else(|e| fail(format!("{}: {}", filename, e)));
+    match replayer.run(um) {
+        Ok(reason) => Ok(reason),
+        Err(ReplayError::Fault(fault)) => Err(fault),
+        Err(error) => {
+            let _ = um.io().flush();
+            fail(format!("{}: {}", filename, error))
+        }
+    }
+}
+
+/// Runs `um` watching its segment heap, then prints memory statistics to
+/// stderr if `memory_stats` and writes the map and unmap events to
+/// `timeline` if it is given
+fn watch_heap(um: &mut Um, memory_stats: bool, timeline: Option<&str>) -> Result<HaltReason, UmFault> {
+    let Some(filename) = timeline else {
+        return watch(um, HeapMonitor::new(), memory_stats).0;
+    };
+    let file = File::create(filename).unwrap_or_else(|e| fail(format!("{}: {}", filename, e)));
+    let format = Path::new(filename).extension().and_then(|extension| extension.to_str())
+        .and_then(|extension| extension.parse().ok()).unwrap_or_default();
+    let monitor = HeapMonitor::with_timeline(BufWriter::new(file), format)
+        .unwrap_or_else(|e| fail(format!("{}: {}", filename, e)));
+    let (result, monitor) = watch(um, monitor, memory_stats);
+    if let Some(Err(error)) = monitor.finish() {
+        fail(format!("{}: {}", filename, error));
+    }
+    result
+}
+
+/// Runs `um` under `monitor`, printing memory statistics to stderr
+/// afterwards if `memory_stats`
+fn watch<W: Write>(um: &mut Um, mut monitor: HeapMonitor<W>, memory_stats: bool)
+    -> (Result<HaltReason, UmFault>, HeapMonitor<W>) {
+    let result = monitor.run(um);
+    if memory_stats {
+        if let Err(error) = monitor.report(um.state(), io::stderr().lock()) {
+            fail(error);
+        }
+    }
+    (result, monitor)
+}
+
+/// Signal number received by `on_signal`, or 0
+static SIGNAL: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn on_signal(signum: libc::c_int) {
+    SIGNAL.store(signum, Ordering::SeqCst);
+}
+
+/// Makes SIGINT and SIGTERM call `on_signal`. With `SA_RESTART`, a read
+/// the signal interrupts carries on rather than failing the Input.
+fn catch_signals() {
+    // SAFETY: the action is fully initialized before it is installed, and
+    // the handler only stores to an atomic
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        for signum in [libc::SIGINT, libc::SIGTERM] {
+            libc::sigaction(signum, &action, std::ptr::null_mut());
+        }
+    }
+}
+
+/// Runs `um`, saving a snapshot to `filename` and exiting with status
+/// 128 + the signal number if SIGINT or SIGTERM arrives.
+///
+/// The signal is acted on between slices of 65536 instructions. A program
+/// blocked in Input keeps waiting for its byte, so a signal sent then only
+/// saves once the read returns and the slice it was in has finished.
+fn run_until_signal(um: &mut Um, filename: &str) -> Result<HaltReason, UmFault> {
+    catch_signals();
+    loop {
+        if let UmEvent::Halted(reason) = um.run_for(1 << 16)? {
+            return Ok(reason);
+        }
+        let signum = SIGNAL.load(Ordering::SeqCst);
+        if signum != 0 {
+            let file = File::create(filename).unwrap_or_else(|e| fail(format!("{}: {}", filename, e)));
+            if let Err(error) = snapshot::save(um, file) {
+                fail(format!("{}: {}", filename, error));
+            }
+            eprintln!("rum: saved snapshot to {} after {} instructions", filename, um.stats().executed);
+            process::exit(128 + signum);
+        }
+    }
+}
+
+/// Prints `registers` to stderr as `registers: r0 r1 ... r7`, in decimal
+fn dump_registers(registers: &[u32]) {
+    let values: Vec<String> = registers.iter().map(|value| value.to_string()).collect();
+    eprintln!("registers: {}", values.join(" "));
+}
+
+/// Reports the limit that stopped `um` and the resources it had used, then
+/// exits with `LIMIT_EXIT`
+fn limit_exceeded(um: &Um, fault: UmFault, started: Instant) -> ! {
+    let state = um.state();
+    eprintln!("rum: {}", fault);
+    eprintln!("rum: used {} instructions, {} mapped words in {} segments, {:.3} s",
+        um.stats().executed, state.mapped_words, state.live_segments, started.elapsed().as_secs_f64());
+    process::exit(LIMIT_EXIT);
+}
+
+/// Main function to run the program.
 fn main() {
-    let input = env::args().nth(1);
-    let instructions: Vec<u32> = memory::load(input.as_deref());
-    memory::instructs(instructions);
+    let options = parse_args();
+    let mut um = match &options.restore {
+        Some(filename) => {
+            let file = File::open(filename).unwrap_or_else(|e| fail(format!("{}: {}", filename, e)));
+            let mut um = snapshot::restore(file, StdIo::stdio())
+                .unwrap_or_else(|e| fail(format!("{}: {}", filename, e)));
+            // stdin is expected to be the input of the saved run; a replay
+            // does not read it at all
+            if options.replay_input.is_none() {
+                snapshot::resume_input(&mut um).unwrap_or_else(|e| fail(format!("stdin: {}", e)));
+            }
+            um
+        }
+        None => Um::new(loader::load(options.input.as_deref(), options.format).unwrap_or_else(|e| fail(e))),
+    };
+    if options.checked {
+        um.set_checked(true);
+    }
+    let started = Instant::now();
+    um.set_limits(options.limits);
+    um.set_engine(options.engine);
+
+    if options.debug {
+        // Commands and the program's Input share stdin, so it is locked only
+        // while a command line is read
+        if let Err(error) = Debugger::new(um).repl_with(|line| io::stdin().read_line(line), io::stdout()) {
+            fail(error);
+        }
+        return;
+    }
+
+    let result = if let Some(filename) = &options.trace {
+        trace(&mut um, filename)
+    } else if options.profile {
+        profile(&mut um)
+    } else if let Some(filename) = &options.record_input {
+        record_input(&mut um, filename)
+    } else if let Some(filename) = &options.replay_input {
+        replay_input(&mut um, filename)
+    } else if options.memory_stats || options.heap_timeline.is_some() {
+        watch_heap(&mut um, options.memory_stats, options.heap_timeline.as_deref())
+    } else if let Some(filename) = &options.save_on_signal {
+        run_until_signal(&mut um, filename)
+    } else {
+        um.run()
+    };
+    if options.dump_registers {
+        dump_registers(&um.state().registers);
+    }
+    match result {
+        Ok(_) => {}
+        Err(fault @ UmFault::LimitExceeded { .. }) => limit_exceeded(&um, fault, started),
+        Err(fault) => fail(fault),
+    }
 }
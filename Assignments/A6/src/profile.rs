@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use rumdump::rumdis;
+use crate::fault::{HaltReason, UmFault};
+use crate::io::UmIo;
+use crate::memory::{get, OP, RA};
+use crate::um::{Um, UmEvent};
+
+/// Names of the fourteen opcodes, indexed by opcode
+pub const OPCODE_NAMES: [&str; 14] = [
+    "cmov", "load", "store", "add", "mul", "div", "nand",
+    "halt", "map", "unmap", "output", "input", "loadprog", "loadval",
+];
+
+/// Counts what a machine executes and times its segment operations.
+#[derive(Default)]
+pub struct Profiler {
+    /// Executions of each opcode
+    pub opcode_counts: [u64; 16],
+    /// Executions of each (program counter, instruction word) in $m[0]
+    pub pc_counts: HashMap<(usize, u32), u64>,
+    /// Time spent in Map Segment and Unmap Segment
+    pub segment_time: Duration,
+    /// Time spent in Load Program
+    pub load_time: Duration,
+    /// Segments copied because a Store hit a segment shared by Load Program
+    pub load_copies: u64,
+}
+
+impl Profiler {
+    /// Creates a profiler with every count at zero
+    pub fn new() -> Self {
+        Profiler::default()
+    }
+
+    /// Executes one instruction of `um` and counts it
+    ///
+    /// # Arguments:
+    /// * `um`: The machine to step
+    pub fn step<I: UmIo>(&mut self, um: &mut Um<I>) -> Result<UmEvent, UmFault> {
+        let state = um.state();
+        let pc = state.program_counter;
+        let instruction = state.memory[0].get(pc).copied().unwrap_or(0);
+        let opcode = get(&OP, instruction) as usize;
+        let copies = opcode == 2 && state.memory.get(state.registers[get(&RA, instruction) as usize] as usize)
+            .is_some_and(|segment| Rc::strong_count(segment) > 1);
+
+        let event = match opcode {
+            8 | 9 | 12 => {
+                let start = Instant::now();
+                let event = um.step()?;
+                if opcode == 12 {
+                    self.load_time += start.elapsed();
+                } else {
+                    self.segment_time += start.elapsed();
+                }
+                event
+            }
+            _ => um.step()?,
+        };
+        self.opcode_counts[opcode] += 1;
+        *self.pc_counts.entry((pc, instruction)).or_insert(0) += 1;
+        if copies {
+            self.load_copies += 1;
+        }
+        Ok(event)
+    }
+
+    /// Executes and counts instructions until the machine halts
+    ///
+    /// # Arguments:
+    /// * `um`: The machine to run
+    pub fn run<I: UmIo>(&mut self, um: &mut Um<I>) -> Result<HaltReason, UmFault> {
+        loop {
+            if let UmEvent::Halted(reason) = self.step(um)? {
+                return Ok(reason);
+            }
+        }
+    }
+
+    /// Prints executions per opcode, segment operation times and the `top`
+    /// most executed instructions with their disassembly
+    ///
+    /// # Arguments:
+    /// * `out`: Destination of the report
+    /// * `top`: Number of hot spots to list
+    pub fn report<W: Write>(&self, mut out: W, top: usize) -> io::Result<()> {
+        let total: u64 = self.opcode_counts.iter().sum();
+        let percent = |count: u64| 100.0 * count as f64 / total.max(1) as f64;
+        writeln!(out, "{} instructions executed", total)?;
+        writeln!(out, "{:<10} {:>14} {:>7}", "opcode", "count", "%")?;
+        for (opcode, name) in OPCODE_NAMES.iter().enumerate() {
+            let count = self.opcode_counts[opcode];
+            if count > 0 {
+                writeln!(out, "{:<10} {:>14} {:>6.2}%", name, count, percent(count))?;
+            }
+        }
+        let segment_ops = self.opcode_counts[8] + self.opcode_counts[9];
+        writeln!(out, "map/unmap: {} calls, {:.3} ms", segment_ops,
+            self.segment_time.as_secs_f64() * 1000.0)?;
+        writeln!(out, "load program: {} calls, {} copy-on-write copies, {:.3} ms", self.opcode_counts[12],
+            self.load_copies, self.load_time.as_secs_f64() * 1000.0)?;
+
+        let mut hot: Vec<(&(usize, u32), &u64)> = self.pc_counts.iter().collect();
+        hot.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        writeln!(out, "hot spots:")?;
+        for (&(pc, instruction), &count) in hot.into_iter().take(top) {
+            writeln!(out, "{:>14} {:>6.2}% {:>8}: {}", count, percent(count), pc,
+                rumdis::disassemble(instruction))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use crate::io::StreamIo;
+    use crate::profile::Profiler;
+    use crate::um::Um;
+
+    fn inst(opcode: u32, a: u32, b: u32, c: u32) -> u32 {
+        (opcode << 28) | (a << 6) | (b << 3) | c
+    }
+
+    #[test]
+    fn counts_opcodes_and_copies() {
+        // Map a segment, fill it with a copy of $m[0] that stores into the
+        // segment itself, and jump into it
+        let program = vec![(13 << 28) | (1 << 25) | 5, inst(8, 0, 2, 1), (13 << 28) | (3 << 25) | 3,
+            inst(12, 0, 2, 3), inst(7, 0, 0, 0)];
+        let mut um = Um::with_io(program.clone(), StreamIo::new(&b""[..], Vec::new()));
+        let mut copy = program.clone();
+        copy[3] = inst(2, 2, 0, 1);
+        let mut profiler = Profiler::new();
+        profiler.step(&mut um).unwrap();
+        profiler.step(&mut um).unwrap();
+        um.state_mut().memory[1] = Rc::new(copy);
+        profiler.run(&mut um).unwrap();
+        assert_eq!(profiler.opcode_counts[13], 2);
+        assert_eq!(profiler.opcode_counts[12], 1);
+        assert_eq!(profiler.opcode_counts[2], 1);
+        assert_eq!(profiler.load_copies, 1);
+        assert_eq!(profiler.pc_counts[&(4, inst(7, 0, 0, 0))], 1);
+        assert_eq!(um.state().memory[0][0], program[0]);
+        assert_eq!(um.state().memory[1][0], 5);
+        let mut report = Vec::new();
+        profiler.report(&mut report, 3).unwrap();
+        assert!(String::from_utf8(report).unwrap().starts_with("6 instructions executed"));
+    }
+}
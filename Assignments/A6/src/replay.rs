@@ -0,0 +1,299 @@
+//! Recording and replaying the input a program reads, so that a run fed
+//! from an interactive terminal can be reproduced exactly.
+//!
+//! A recording holds one record per Input instruction executed, with the
+//! number of instructions executed before it. All numbers are big-endian:
+//!
+//! ```text
+//! "UMIN" version:u32
+//! per Input: executed:u64 value:u32
+//! ```
+//!
+//! The value is the byte delivered, or `u32::MAX` at the end of input.
+//! Replaying hands the recorded values to the program in place of its
+//! input device and stops with an error as soon as the program reads input
+//! anywhere the recording does not have it, or leaves recorded input
+//! unread.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, BufReader, Read, Write};
+use crate::fault::{HaltReason, UmFault};
+use crate::io::UmIo;
+use crate::memory::{get, OP, RC};
+use crate::um::{Um, UmEvent};
+
+pub const MAGIC: &[u8; 4] = b"UMIN";
+pub const VERSION: u32 = 1;
+
+/// The opcode of Input
+const INPUT: u32 = 11;
+
+/// The opcode and c register of the next instruction of `um`
+fn next_instruction<I: UmIo>(um: &Um<I>) -> (u32, usize) {
+    let state = um.state();
+    let word = state.memory[0].get(state.program_counter).copied().unwrap_or(0);
+    (get(&OP, word), get(&RC, word) as usize)
+}
+
+/// Records every value a machine's Input instructions deliver.
+///
+/// Like `Tracer`, a failure to write the recording does not stop the
+/// machine; recording stops and the error is returned by `finish`.
+pub struct InputRecorder<W: Write> {
+    out: W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> InputRecorder<W> {
+    /// Creates a recorder and writes the recording header to `out`
+    ///
+    /// # Arguments:
+    /// * `out`: Destination of the recording
+    pub fn new(mut out: W) -> io::Result<Self> {
+        out.write_all(MAGIC)?;
+        out.write_all(&VERSION.to_be_bytes())?;
+        Ok(InputRecorder { out, error: None })
+    }
+
+    /// Executes one instruction of `um`, recording it if it is an Input
+    ///
+    /// # Arguments:
+    /// * `um`: The machine to step
+    pub fn step<I: UmIo>(&mut self, um: &mut Um<I>) -> Result<UmEvent, UmFault> {
+        let (opcode, c) = next_instruction(um);
+        let executed = um.stats().executed;
+        let event = um.step()?;
+        if opcode == INPUT && um.stats().executed > executed && self.error.is_none() {
+            let value = um.state().registers[c];
+            let written = self.out.write_all(&executed.to_be_bytes())
+                .and_then(|()| self.out.write_all(&value.to_be_bytes()));
+            if let Err(error) = written {
+                self.error = Some(error);
+            }
+        }
+        Ok(event)
+    }
+
+    /// Executes and records instructions until the machine halts
+    ///
+    /// # Arguments:
+    /// * `um`: The machine to run
+    pub fn run<I: UmIo>(&mut self, um: &mut Um<I>) -> Result<HaltReason, UmFault> {
+        loop {
+            if let UmEvent::Halted(reason) = self.step(um)? {
+                return Ok(reason);
+            }
+        }
+    }
+
+    /// Flushes the recording and returns its writer, or the first write
+    /// error
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Why a replay stopped before the program halted as recorded
+#[derive(Debug)]
+pub enum ReplayError {
+    /// The machine faulted
+    Fault(UmFault),
+    /// The program read input after `executed` instructions, where the
+    /// recording's next input is after `recorded` instructions, or where
+    /// it has no more input
+    Unexpected { executed: u64, recorded: Option<u64> },
+    /// The program did not read the input recorded after `recorded`
+    /// instructions, leaving `remaining` recorded inputs unread
+    Missed { recorded: u64, remaining: usize },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Fault(fault) => write!(f, "{}", fault),
+            ReplayError::Unexpected { executed, recorded: Some(recorded) } => write!(f,
+                "program read input after {} instructions, but the recording has the next input after {}",
+                executed, recorded),
+            ReplayError::Unexpected { executed, recorded: None } => write!(f,
+                "program read input after {} instructions, past the end of the recording", executed),
+            ReplayError::Missed { recorded, remaining } => write!(f,
+                "program did not read the input recorded after {} instructions ({} recorded inputs unread)",
+                recorded, remaining),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<UmFault> for ReplayError {
+    fn from(fault: UmFault) -> Self {
+        ReplayError::Fault(fault)
+    }
+}
+
+/// Feeds a recording back to a machine in place of its input device.
+pub struct InputReplayer {
+    /// The recorded instruction counts and values, next first
+    inputs: VecDeque<(u64, u32)>,
+}
+
+impl InputReplayer {
+    /// Reads a recording made by `InputRecorder`
+    ///
+    /// # Arguments:
+    /// * `input`: Source of the recording
+    pub fn read<R: Read>(input: R) -> io::Result<Self> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+        let mut input = BufReader::new(input);
+        let mut header = [0_u8; 8];
+        input.read_exact(&mut header).map_err(|_| invalid("not an input recording"))?;
+        if &header[..4] != MAGIC {
+            return Err(invalid("not an input recording"));
+        }
+        if header[4..] != VERSION.to_be_bytes() {
+            return Err(invalid("unsupported input recording version"));
+        }
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes)?;
+        if bytes.len() % 12 != 0 {
+            return Err(invalid("truncated input recording"));
+        }
+        let mut inputs = VecDeque::new();
+        for record in bytes.chunks_exact(12) {
+            let executed = u64::from_be_bytes(record[..8].try_into().unwrap());
+            let value = u32::from_be_bytes(record[8..].try_into().unwrap());
+            if value > u8::MAX as u32 && value != u32::MAX {
+                return Err(invalid("input recording holds a value that is not a byte"));
+            }
+            if inputs.back().is_some_and(|&(last, _)| last >= executed) {
+                return Err(invalid("input recording is out of order"));
+            }
+            inputs.push_back((executed, value));
+        }
+        Ok(InputReplayer { inputs })
+    }
+
+    /// Executes one instruction of `um`, taking the value of an Input from
+    /// the recording. The Input is counted and limited like any other
+    /// instruction.
+    ///
+    /// # Arguments:
+    /// * `um`: The machine to step
+    pub fn step<I: UmIo>(&mut self, um: &mut Um<I>) -> Result<UmEvent, ReplayError> {
+        let (opcode, c) = next_instruction(um);
+        let executed = um.stats().executed;
+        let next = self.inputs.front().map(|&(recorded, _)| recorded);
+        if opcode != INPUT {
+            if next == Some(executed) {
+                return Err(ReplayError::Missed { recorded: executed, remaining: self.inputs.len() });
+            }
+            return Ok(um.step()?);
+        }
+        if next != Some(executed) {
+            return Err(ReplayError::Unexpected { executed, recorded: next });
+        }
+        // Executes the Input as the machine would, minus the device
+        let (_, value) = self.inputs[0];
+        if let Err(error) = um.io().flush() {
+            let pc = um.state().program_counter;
+            return Err(UmFault::Io { pc, instruction: um.state().memory[0][pc], kind: error.kind() }.into());
+        }
+        let event = um.step_replayed(|state, stats| {
+            state.registers[c] = value;
+            match value {
+                u32::MAX => stats.input_eof = true,
+                _ => stats.bytes_read += 1,
+            }
+        })?;
+        self.inputs.pop_front();
+        Ok(event)
+    }
+
+    /// Replays instructions until the machine halts, which must be after
+    /// every recorded input was read
+    ///
+    /// # Arguments:
+    /// * `um`: The machine to run
+    pub fn run<I: UmIo>(&mut self, um: &mut Um<I>) -> Result<HaltReason, ReplayError> {
+        loop {
+            if let UmEvent::Halted(reason) = self.step(um)? {
+                return match self.inputs.front() {
+                    Some(&(recorded, _)) => Err(ReplayError::Missed { recorded, remaining: self.inputs.len() }),
+                    None => Ok(reason),
+                };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fault::UmFault;
+    use crate::io::StreamIo;
+    use crate::limits::Limits;
+    use crate::replay::{InputRecorder, InputReplayer, ReplayError};
+    use crate::um::Um;
+
+    fn inst(opcode: u32, a: u32, b: u32, c: u32) -> u32 {
+        (opcode << 28) | (a << 6) | (b << 3) | c
+    }
+
+    /// Echoes its input up to and including the end of input
+    fn echo() -> Vec<u32> {
+        let value = |r: u32, value: u32| (13 << 28) | (r << 25) | value;
+        vec![value(2, 1), value(5, 8), value(6, 3),
+            inst(11, 0, 0, 1), inst(3, 3, 1, 2), value(4, 10), inst(0, 4, 5, 3), inst(12, 0, 0, 4),
+            inst(10, 0, 0, 1), inst(12, 0, 0, 6), inst(7, 0, 0, 0)]
+    }
+
+    #[test]
+    fn replays_what_was_recorded() {
+        let mut um = Um::with_io(echo(), StreamIo::new(&b"hi"[..], Vec::new()));
+        let mut recorder = InputRecorder::new(Vec::new()).unwrap();
+        recorder.run(&mut um).unwrap();
+        let recording = recorder.finish().unwrap();
+        assert_eq!(recording.len(), 8 + 3 * 12);
+
+        let mut replayer = InputReplayer::read(&recording[..]).unwrap();
+        let mut replayed = Um::with_io(echo(), StreamIo::new(&b"other input"[..], Vec::new()));
+        replayer.run(&mut replayed).unwrap();
+        assert_eq!(replayed.stats(), um.stats());
+        assert_eq!(replayed.into_io().into_parts().unwrap().1, b"hi");
+    }
+    #[test]
+    fn stops_when_the_program_diverges() {
+        let mut recorder = InputRecorder::new(Vec::new()).unwrap();
+        recorder.run(&mut Um::with_io(echo(), StreamIo::new(&b"hi"[..], Vec::new()))).unwrap();
+        let recording = recorder.finish().unwrap();
+
+        // One more Input than recorded
+        let mut program = echo();
+        program[10] = inst(11, 0, 0, 1);
+        let mut um = Um::with_io(program, StreamIo::new(&b""[..], Vec::new()));
+        let error = InputReplayer::read(&recording[..]).unwrap().run(&mut um).unwrap_err();
+        assert!(matches!(error, ReplayError::Unexpected { recorded: None, .. }));
+
+        // Halts before the end of input
+        let mut program = echo();
+        program[4] = inst(7, 0, 0, 0);
+        let mut um = Um::with_io(program, StreamIo::new(&b""[..], Vec::new()));
+        let error = InputReplayer::read(&recording[..]).unwrap().run(&mut um).unwrap_err();
+        assert_eq!(error.to_string(), "program did not read the input recorded after 10 instructions \
+            (2 recorded inputs unread)");
+
+        // The replayed Input is limited like any other instruction
+        let mut um = Um::with_io(echo(), StreamIo::new(&b""[..], Vec::new()));
+        um.set_limits(Limits { instructions: Some(3), ..Limits::default() });
+        let error = InputReplayer::read(&recording[..]).unwrap().run(&mut um).unwrap_err();
+        assert!(matches!(error, ReplayError::Fault(UmFault::LimitExceeded { pc: 3, .. })));
+        assert_eq!(um.stats().bytes_read, 0);
+
+        assert!(InputReplayer::read(&recording[..recording.len() - 1]).is_err());
+        assert!(InputReplayer::read(&b"UMSS\0\0\0\x01"[..]).is_err());
+    }
+}
@@ -0,0 +1,256 @@
+//! Snapshot files hold the complete state of a running machine so a long
+//! run can be checkpointed and resumed later.
+//!
+//! All numbers are big-endian, like the words of a `.um` file:
+//!
+//! ```text
+//! "UMSS" version:u32
+//! program_counter:u32 registers:8*u32 checked:u8
+//! executed:u64 bytes_read:u64 bytes_written:u64 input_eof:u8
+//! segment_count:u32, then per segment ID: mapped:u8 [length:u32 words...]
+//! free_count:u32 free_ids:u32...
+//! ```
+//!
+//! Output is flushed before a snapshot is taken, so no output is pending.
+//! Input that the program has not yet read stays with the input device; the
+//! saved byte count says where a resumed run should pick up, and
+//! `resume_input` skips that far into the same input.
+
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::rc::Rc;
+use crate::decode::CodeCache;
+use crate::io::{StreamIo, UmIo};
+use crate::limits::Limits;
+use crate::memory::UmState;
+use crate::um::{Um, UmStats};
+
+pub const MAGIC: &[u8; 4] = b"UMSS";
+pub const VERSION: u32 = 1;
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn write_u32<W: Write>(out: &mut W, value: u32) -> io::Result<()> {
+    out.write_all(&value.to_be_bytes())
+}
+
+fn write_u64<W: Write>(out: &mut W, value: u64) -> io::Result<()> {
+    out.write_all(&value.to_be_bytes())
+}
+
+fn read_u8<R: Read>(input: &mut R) -> io::Result<u8> {
+    let mut buf = [0_u8; 1];
+    input.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u32<R: Read>(input: &mut R) -> io::Result<u32> {
+    let mut buf = [0_u8; 4];
+    input.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+fn read_u64<R: Read>(input: &mut R) -> io::Result<u64> {
+    let mut buf = [0_u8; 8];
+    input.read_exact(&mut buf)?;
+    Ok(u64::from_be_bytes(buf))
+}
+
+/// Flushes the machine's output and writes a snapshot of it to `out`
+///
+/// # Arguments:
+/// * `um`: The machine to save
+/// * `out`: Destination of the snapshot
+pub fn save<I: UmIo, W: Write>(um: &mut Um<I>, out: W) -> io::Result<()> {
+    um.io().flush()?;
+    let mut out = BufWriter::new(out);
+    let state = um.state();
+    let stats = um.stats();
+    out.write_all(MAGIC)?;
+    write_u32(&mut out, VERSION)?;
+
+    write_u32(&mut out, state.program_counter as u32)?;
+    for &register in &state.registers {
+        write_u32(&mut out, register)?;
+    }
+    out.write_all(&[state.checked as u8])?;
+
+    write_u64(&mut out, stats.executed)?;
+    write_u64(&mut out, stats.bytes_read)?;
+    write_u64(&mut out, stats.bytes_written)?;
+    out.write_all(&[stats.input_eof as u8])?;
+
+    write_u32(&mut out, state.memory.len() as u32)?;
+    for (segment, &mapped) in state.memory.iter().zip(&state.mapped) {
+        out.write_all(&[mapped as u8])?;
+        if mapped {
+            write_u32(&mut out, segment.len() as u32)?;
+            for &word in segment.iter() {
+                write_u32(&mut out, word)?;
+            }
+        }
+    }
+
+    write_u32(&mut out, state.unmap_index_values.len() as u32)?;
+    for &id in &state.unmap_index_values {
+        write_u32(&mut out, id as u32)?;
+    }
+    out.flush()
+}
+
+/// Reads a snapshot and returns a machine that resumes from it
+///
+/// # Arguments:
+/// * `input`: Source of the snapshot
+/// * `io`: The device the resumed machine uses for Input and Output
+pub fn restore<I: UmIo, R: Read>(input: R, io: I) -> io::Result<Um<I>> {
+    let mut input = BufReader::new(input);
+    let mut magic = [0_u8; 4];
+    input.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid("not a UM snapshot"));
+    }
+    let version = read_u32(&mut input)?;
+    if version != VERSION {
+        return Err(invalid(&format!("unsupported snapshot version {}", version)));
+    }
+
+    let program_counter = read_u32(&mut input)? as usize;
+    let mut registers = vec![0_u32; 8];
+    for register in registers.iter_mut() {
+        *register = read_u32(&mut input)?;
+    }
+    let checked = read_u8(&mut input)? != 0;
+
+    let stats = UmStats {
+        executed: read_u64(&mut input)?,
+        bytes_read: read_u64(&mut input)?,
+        bytes_written: read_u64(&mut input)?,
+        input_eof: read_u8(&mut input)? != 0,
+    };
+
+    let count = read_u32(&mut input)? as usize;
+    let mut memory = Vec::new();
+    let mut mapped = Vec::new();
+    for _ in 0..count {
+        let is_mapped = read_u8(&mut input)? != 0;
+        let mut segment = Vec::new();
+        if is_mapped {
+            let length = read_u32(&mut input)?;
+            for _ in 0..length {
+                segment.push(read_u32(&mut input)?);
+            }
+        }
+        memory.push(Rc::new(segment));
+        mapped.push(is_mapped);
+    }
+    if mapped.first() != Some(&true) {
+        return Err(invalid("snapshot has no $m[0]"));
+    }
+
+    let free = read_u32(&mut input)?;
+    let mut unmap_index_values = Vec::new();
+    for _ in 0..free {
+        let id = read_u32(&mut input)? as usize;
+        if mapped.get(id) != Some(&false) {
+            return Err(invalid("snapshot frees a mapped segment"));
+        }
+        unmap_index_values.push(id);
+    }
+
+    let code = CodeCache::new(&memory[0]);
+    let mapped_words = memory.iter().map(|segment| segment.len()).sum();
+    let live_segments = mapped.iter().filter(|&&is_mapped| is_mapped).count();
+    let state = UmState { registers, memory, unmap_index_values, program_counter, mapped, checked, code,
+        limits: Limits::default(), mapped_words, live_segments };
+    Ok(Um::from_state(state, stats, io))
+}
+
+/// Brings the input of a restored machine back to where it was when it was
+/// saved: skips the bytes the program had already read from the same input
+/// and, if it had seen the end of input, keeps reporting the end
+///
+/// # Arguments:
+/// * `um`: The restored machine, reading the input it was first run with
+pub fn resume_input<R: Read, W: Write>(um: &mut Um<StreamIo<R, W>>) -> io::Result<()> {
+    let stats = um.stats();
+    for skipped in 0..stats.bytes_read {
+        if um.io().input()?.is_none() {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!(
+                "input ended after {} bytes, but the program had read {}", skipped, stats.bytes_read)));
+        }
+    }
+    if stats.input_eof {
+        um.io().end_input();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::io::StreamIo;
+    use crate::snapshot::{restore, resume_input, save};
+    use crate::um::{Um, UmEvent};
+
+    fn inst(opcode: u32, a: u32, b: u32, c: u32) -> u32 {
+        (opcode << 28) | (a << 6) | (b << 3) | c
+    }
+
+    #[test]
+    fn resume_from_snapshot() {
+        // Map two segments, free the first, then read a byte and echo it
+        let program = vec![(13 << 28) | (1 << 25) | 2, inst(8, 0, 2, 1), inst(8, 0, 3, 1),
+            inst(2, 3, 0, 1), inst(9, 0, 0, 2), inst(11, 0, 0, 4), inst(10, 0, 0, 4), inst(7, 0, 0, 0)];
+        let mut um = Um::with_io(program, StreamIo::new(&b"xy"[..], Vec::new()));
+        assert_eq!(um.run_for(6), Ok(UmEvent::Running));
+        let mut snapshot = Vec::new();
+        save(&mut um, &mut snapshot).unwrap();
+
+        let mut resumed = restore(&snapshot[..], StreamIo::new(&b""[..], Vec::new())).unwrap();
+        assert_eq!(resumed.state().registers, um.state().registers);
+        assert_eq!(resumed.state().memory, um.state().memory);
+        assert_eq!(resumed.state().mapped, vec![true, false, true]);
+        assert_eq!(resumed.state().unmap_index_values, vec![1]);
+        assert_eq!(resumed.stats(), um.stats());
+        assert_eq!(resumed.stats().bytes_read, 1);
+        resumed.run().unwrap();
+        assert_eq!(resumed.into_io().into_parts().unwrap().1, b"x");
+    }
+    #[test]
+    fn resumes_the_same_input() {
+        // Echo input until its end, then read once more into r2
+        let value = |r: u32, value: u32| (13 << 28) | (r << 25) | value;
+        let program = vec![value(2, 1), value(5, 8), value(6, 3),
+            inst(11, 0, 0, 1), inst(3, 3, 1, 2), value(4, 10), inst(0, 4, 5, 3), inst(12, 0, 0, 4),
+            inst(10, 0, 0, 1), inst(12, 0, 0, 6), inst(11, 0, 0, 2), inst(7, 0, 0, 0)];
+        let mut um = Um::with_io(program.clone(), StreamIo::new(&b"echo"[..], Vec::new()));
+        assert_eq!(um.run_for(3 + 7 * 2), Ok(UmEvent::Running));
+        let mut snapshot = Vec::new();
+        save(&mut um, &mut snapshot).unwrap();
+        let mut resumed = restore(&snapshot[..], StreamIo::new(&b"echo"[..], Vec::new())).unwrap();
+        resume_input(&mut resumed).unwrap();
+        resumed.run().unwrap();
+        assert_eq!(resumed.into_io().into_parts().unwrap().1, b"ho");
+
+        // Once the program has seen the end, fresh input stays unread
+        um.run().unwrap();
+        assert!(um.stats().input_eof);
+        let mut snapshot = Vec::new();
+        save(&mut um, &mut snapshot).unwrap();
+        let mut resumed = restore(&snapshot[..], StreamIo::new(&b"echo and more"[..], Vec::new())).unwrap();
+        resume_input(&mut resumed).unwrap();
+        resumed.state_mut().program_counter = 10;
+        resumed.resume();
+        resumed.run().unwrap();
+        assert_eq!(resumed.state().registers[2], u32::MAX);
+
+        let mut resumed = restore(&snapshot[..], StreamIo::new(&b"ec"[..], Vec::new())).unwrap();
+        assert!(resume_input(&mut resumed).is_err());
+    }
+    #[test]
+    fn rejects_other_files() {
+        let io = StreamIo::new(&b""[..], Vec::new());
+        assert!(restore(&b"\x00\x00\x00\x07"[..], io).is_err());
+    }
+}
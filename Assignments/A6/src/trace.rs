@@ -0,0 +1,140 @@
+use std::io::{self, Write};
+use rumdump::rumtrace::{self, TraceRecord};
+use crate::fault::{HaltReason, UmFault};
+use crate::io::UmIo;
+use crate::memory::{get, OP, RA, RB, RC};
+use crate::um::{Um, UmEvent};
+
+/// Records every instruction a machine executes in the binary trace format
+/// of `rumdump::rumtrace`, which `rumdump --trace` renders.
+///
+/// A failure to write the trace does not stop the machine; tracing stops
+/// and the error is returned by `finish`.
+pub struct Tracer<W: Write> {
+    out: W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> Tracer<W> {
+    /// Creates a tracer and writes the trace header to `out`
+    ///
+    /// # Arguments:
+    /// * `out`: Destination of the trace
+    pub fn new(mut out: W) -> io::Result<Self> {
+        rumtrace::write_header(&mut out)?;
+        Ok(Tracer { out, error: None })
+    }
+
+    /// Executes one instruction of `um` and records it
+    ///
+    /// # Arguments:
+    /// * `um`: The machine to step
+    pub fn step<I: UmIo>(&mut self, um: &mut Um<I>) -> Result<UmEvent, UmFault> {
+        let state = um.state();
+        let mut before = [0_u32; 8];
+        before.copy_from_slice(&state.registers);
+        let pc = state.program_counter;
+        let instruction = state.memory[0].get(pc).copied().unwrap_or(0);
+
+        let event = match um.step() {
+            Ok(event) => event,
+            Err(fault) => {
+                // Marks where the run went wrong, so a trace ends at the fault
+                if self.error.is_none() {
+                    let record = TraceRecord { pc: pc as u32, instruction, fault: true, ..Default::default() };
+                    if let Err(error) = rumtrace::write_record(&mut self.out, &record) {
+                        self.error = Some(error);
+                    }
+                }
+                return Err(fault);
+            }
+        };
+        if self.error.is_some() {
+            return Ok(event);
+        }
+
+        let after = &um.state().registers;
+        let mut record = TraceRecord { pc: pc as u32, instruction, ..Default::default() };
+        for r in 0..8 {
+            if before[r] != after[r] {
+                record.registers.push((r as u8, after[r]));
+            }
+        }
+        let a = get(&RA, instruction) as usize;
+        let b = get(&RB, instruction) as usize;
+        let c = get(&RC, instruction) as usize;
+        match get(&OP, instruction) {
+            2 => record.stores.push((before[a], before[b], before[c])),
+            10 => record.output = Some(before[c] as u8),
+            11 => record.input = Some(u8::try_from(after[c]).ok()),
+            _ => {}
+        }
+        if let Err(error) = rumtrace::write_record(&mut self.out, &record) {
+            self.error = Some(error);
+        }
+        Ok(event)
+    }
+
+    /// Executes and records instructions until the machine halts
+    ///
+    /// # Arguments:
+    /// * `um`: The machine to run
+    pub fn run<I: UmIo>(&mut self, um: &mut Um<I>) -> Result<HaltReason, UmFault> {
+        loop {
+            if let UmEvent::Halted(reason) = self.step(um)? {
+                return Ok(reason);
+            }
+        }
+    }
+
+    /// Flushes the trace and returns its writer, or the first write error
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rumdump::rumtrace::{TraceReader, TraceRecord};
+    use crate::io::StreamIo;
+    use crate::trace::Tracer;
+    use crate::um::Um;
+
+    fn inst(opcode: u32, a: u32, b: u32, c: u32) -> u32 {
+        (opcode << 28) | (a << 6) | (b << 3) | c
+    }
+
+    #[test]
+    fn records_effects() {
+        let program = vec![inst(6, 1, 0, 0), (13 << 28) | (4 << 25) | 1, inst(8, 0, 2, 4),
+            inst(2, 2, 0, 1), inst(11, 0, 0, 3), inst(10, 0, 0, 3), inst(7, 0, 0, 0)];
+        let mut um = Um::with_io(program.clone(), StreamIo::new(&b"A"[..], Vec::new()));
+        let mut tracer = Tracer::new(Vec::new()).unwrap();
+        tracer.run(&mut um).unwrap();
+        let trace = tracer.finish().unwrap();
+        let records: Vec<TraceRecord> = TraceReader::new(&trace[..]).unwrap().map(|r| r.unwrap()).collect();
+        assert_eq!(records.len(), 7);
+        assert_eq!(records[0].registers, vec![(1, u32::MAX)]);
+        assert_eq!(records[2].registers, vec![(2, 1)]);
+        assert_eq!(records[3].stores, vec![(1, 0, u32::MAX)]);
+        assert_eq!(records[4].input, Some(Some(b'A')));
+        assert_eq!(records[5].output, Some(b'A'));
+        assert_eq!(records[6], TraceRecord { pc: 6, instruction: program[6], ..Default::default() });
+    }
+    #[test]
+    fn records_the_fault() {
+        // r1 := 1; r2 := r1 / r0
+        let program = vec![(13 << 28) | (1 << 25) | 1, inst(5, 2, 1, 0), inst(7, 0, 0, 0)];
+        let mut um = Um::with_io(program.clone(), StreamIo::new(&b""[..], Vec::new()));
+        let mut tracer = Tracer::new(Vec::new()).unwrap();
+        assert!(tracer.run(&mut um).is_err());
+        let trace = tracer.finish().unwrap();
+        let records: Vec<TraceRecord> = TraceReader::new(&trace[..]).unwrap().map(|r| r.unwrap()).collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1], TraceRecord { pc: 1, instruction: program[1], fault: true, ..Default::default() });
+    }
+}
@@ -0,0 +1,191 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+use rumdump::rumasm;
+use umcore::fault::HaltReason;
+use umcore::io::StreamIo;
+use umcore::um::{Engine, Um, UmEvent};
+use crate::reference;
+
+/// Instructions any corpus program may execute before it is stopped
+pub const BUDGET: u64 = 1 << 20;
+
+/// What running a program produced
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    pub output: Vec<u8>,
+    /// The final register file, when the implementation exposes it
+    pub registers: Option<[u32; 8]>,
+    /// Whether the program halted, rather than faulting or running out of
+    /// budget
+    pub halted: bool,
+}
+
+/// A corpus program and the input it is run with
+pub struct Case {
+    pub name: String,
+    pub program: Vec<u32>,
+    pub input: Vec<u8>,
+}
+
+/// Assembles every `NAME.ums` in `dir`, with `NAME.in` as its input if
+/// that file exists
+pub fn corpus(dir: &Path) -> Vec<Case> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "ums"))
+        .collect();
+    paths.sort();
+    paths.iter().map(|path| {
+        let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+        let source = fs::read_to_string(path).unwrap();
+        let program = rumasm::assemble(&source)
+            .unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+        let input = fs::read(path.with_extension("in")).unwrap_or_default();
+        Case { name, program, input }
+    }).collect()
+}
+
+/// Runs `program` on the umcore machine with the given engine
+pub fn umcore(program: &[u32], input: &[u8], engine: Engine) -> Outcome {
+    let mut um = Um::with_io(program.to_vec(), StreamIo::new(input, Vec::new()));
+    um.set_engine(engine);
+    let halted = um.run_for(BUDGET) == Ok(UmEvent::Halted(HaltReason::Halted));
+    let registers = um.state().registers.clone().try_into().ok();
+    let output = um.into_io().into_parts().unwrap().1;
+    Outcome { output, registers, halted }
+}
+
+/// Builds the `rum` binary of the crate in `manifest_dir` into a target
+/// directory of its own and returns the path of the executable
+fn build(manifest_dir: &Path, target_dir: &Path) -> PathBuf {
+    let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let status = Command::new(cargo)
+        .args(["build", "--quiet", "--manifest-path"])
+        .arg(manifest_dir.join("Cargo.toml"))
+        .arg("--target-dir")
+        .arg(target_dir)
+        .status()
+        .unwrap();
+    assert!(status.success(), "building {} failed", manifest_dir.display());
+    target_dir.join("debug").join("rum")
+}
+
+/// The A5 and A6 `rum` binaries, built once per test run
+pub fn binaries() -> &'static [(&'static str, PathBuf)] {
+    static BINARIES: OnceLock<Vec<(&'static str, PathBuf)>> = OnceLock::new();
+    BINARIES.get_or_init(|| {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        ["A5", "A6"].iter().map(|&name| {
+            let target = root.join("target").join("implementations").join(name);
+            (name, build(&root.join("..").join(name), &target))
+        }).collect()
+    })
+}
+
+/// The registers a `rum` binary printed to stderr for `--dump-registers`
+fn dumped_registers(stderr: &[u8]) -> Option<[u32; 8]> {
+    let stderr = String::from_utf8_lossy(stderr);
+    let line = stderr.lines().find_map(|line| line.strip_prefix("registers: "))?;
+    let values: Vec<u32> = line.split(' ').map(|value| value.parse().ok()).collect::<Option<_>>()?;
+    values.try_into().ok()
+}
+
+/// Runs `program` as a file on the `rum` binary at `binary`, passing it
+/// `--dump-registers` and `args` first. Output, the exit status and the
+/// dumped registers are observed.
+pub fn binary(binary: &Path, args: &[&str], case: &Case) -> Outcome {
+    let file = std::env::temp_dir()
+        .join(format!("conformance-{}-{}.um", std::process::id(), case.name));
+    let bytes: Vec<u8> = case.program.iter().flat_map(|word| word.to_be_bytes()).collect();
+    fs::write(&file, bytes).unwrap();
+    let mut child = Command::new(binary)
+        .arg("--dump-registers")
+        .args(args)
+        .arg(&file)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(&case.input).unwrap();
+    let result = child.wait_with_output().unwrap();
+    fs::remove_file(&file).ok();
+    let registers = dumped_registers(&result.stderr);
+    Outcome { output: result.stdout, registers, halted: result.status.success() }
+}
+
+/// Describes how `actual` differs from `expected`, comparing registers only
+/// when both are known
+pub fn diverges(expected: &Outcome, actual: &Outcome) -> Option<String> {
+    let mut report = String::new();
+    if actual.output != expected.output {
+        write!(report, " output {:?}, expected {:?};", String::from_utf8_lossy(&actual.output),
+            String::from_utf8_lossy(&expected.output)).unwrap();
+    }
+    if let (Some(actual), Some(expected)) = (actual.registers, expected.registers) {
+        if actual != expected {
+            write!(report, " registers {:?}, expected {:?};", actual, expected).unwrap();
+        }
+    }
+    if actual.halted != expected.halted {
+        write!(report, " halted {}, expected {};", actual.halted, expected.halted).unwrap();
+    }
+    (!report.is_empty()).then_some(report)
+}
+
+/// Runs one case everywhere and lists every divergence from the reference
+pub fn check(case: &Case) -> Vec<String> {
+    let expected = reference::run(&case.program, &case.input, BUDGET);
+    let mut runs = vec![
+        ("umcore".to_string(), umcore(&case.program, &case.input, Engine::Interpreter)),
+        ("umcore threaded".to_string(), umcore(&case.program, &case.input, Engine::Threaded)),
+    ];
+    for (name, path) in binaries() {
+        runs.push((format!("{} rum", name), binary(path, &[], case)));
+    }
+    let (_, a6) = binaries().iter().find(|(name, _)| *name == "A6").unwrap();
+    runs.push(("A6 rum --engine=threaded".to_string(), binary(a6, &["--engine=threaded"], case)));
+    runs.iter()
+        .filter_map(|(name, actual)| diverges(&expected, actual)
+            .map(|report| format!("{}: {}:{}", case.name, name, report)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::harness::{binaries, binary, check, corpus, diverges, Case, Outcome};
+
+    #[test]
+    fn corpus_conforms() {
+        let cases = corpus(&Path::new(env!("CARGO_MANIFEST_DIR")).join("corpus"));
+        assert!(!cases.is_empty());
+        let failures: Vec<String> = cases.iter().flat_map(check).collect();
+        assert!(failures.is_empty(), "implementations diverge:\n{}", failures.join("\n"));
+    }
+    #[test]
+    fn binaries_dump_registers() {
+        // r3 := 7; r5 := r3 * r3; halt
+        let program = vec![(13 << 28) | (3 << 25) | 7, (4 << 28) | (5 << 6) | (3 << 3) | 3, 7 << 28];
+        let case = Case { name: "dump_registers".to_string(), program, input: Vec::new() };
+        for (_, path) in binaries() {
+            assert_eq!(binary(path, &[], &case).registers, Some([0, 0, 0, 7, 0, 49, 0, 0]));
+        }
+    }
+    #[test]
+    fn reports_divergence() {
+        let expected = Outcome { output: b"A".to_vec(), registers: Some([0; 8]), halted: true };
+        let mut actual = expected.clone();
+        assert_eq!(diverges(&expected, &actual), None);
+        actual.registers = Some([1, 0, 0, 0, 0, 0, 0, 0]);
+        actual.halted = false;
+        let report = diverges(&expected, &actual).unwrap();
+        assert!(report.contains("registers") && report.contains("halted"));
+        actual.registers = None;
+        assert!(!diverges(&expected, &actual).unwrap().contains("registers"));
+    }
+}
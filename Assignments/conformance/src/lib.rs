@@ -0,0 +1,17 @@
+//! Differential conformance testing of the UM implementations.
+//!
+//! Every program in `corpus/` is run on a small reference model of the UM
+//! specification, on the umcore machine with each of its engines, and as a
+//! process on the A5 and A6 `rum` binaries. Output bytes, whether the
+//! machine halted or faulted and the final register file must agree
+//! everywhere; the binaries print their registers for `--dump-registers`.
+//!
+//! The reference model does not use umcore at all, and the A5 machine
+//! shares only its decoder, faults, I/O devices and loader. The A6 binary
+//! runs umcore itself, so it checks the command line and the engines
+//! rather than a second implementation. A bug in the shared decoder only
+//! shows against the reference model, so `registers.ums` and
+//! `load_value_fields.ums` put every register in every field.
+
+pub mod harness;
+pub mod reference;
@@ -0,0 +1,101 @@
+//! A direct transcription of the UM specification, kept deliberately
+//! simple so it can serve as the oracle for the other implementations.
+//! Segments are plain vectors and every instruction is decoded as it runs.
+
+use crate::harness::Outcome;
+
+/// Runs `program` on the reference machine for at most `budget`
+/// instructions
+///
+/// # Arguments:
+/// * `program`: The words of $m[0]
+/// * `input`: Bytes read by the Input instruction
+/// * `budget`: The largest number of instructions to execute
+pub fn run(program: &[u32], input: &[u8], budget: u64) -> Outcome {
+    let mut registers = [0_u32; 8];
+    let mut segments: Vec<Option<Vec<u32>>> = vec![Some(program.to_vec())];
+    let mut free: Vec<u32> = Vec::new();
+    let mut input = input.iter();
+    let mut output = Vec::new();
+    let mut pc = 0_usize;
+
+    let mut halted = false;
+    for _ in 0..budget {
+        let word = match segments[0].as_ref().and_then(|code| code.get(pc)) {
+            Some(&word) => word,
+            None => break,
+        };
+        pc += 1;
+        let a = ((word >> 6) & 7) as usize;
+        let b = ((word >> 3) & 7) as usize;
+        let c = (word & 7) as usize;
+        match word >> 28 {
+            0 => {
+                if registers[c] != 0 {
+                    registers[a] = registers[b];
+                }
+            }
+            1 => match segments.get(registers[b] as usize).and_then(|s| s.as_ref())
+                .and_then(|s| s.get(registers[c] as usize)) {
+                Some(&value) => registers[a] = value,
+                None => break,
+            },
+            2 => match segments.get_mut(registers[a] as usize).and_then(|s| s.as_mut())
+                .and_then(|s| s.get_mut(registers[b] as usize)) {
+                Some(slot) => *slot = registers[c],
+                None => break,
+            },
+            3 => registers[a] = registers[b].wrapping_add(registers[c]),
+            4 => registers[a] = registers[b].wrapping_mul(registers[c]),
+            5 => match registers[b].checked_div(registers[c]) {
+                Some(value) => registers[a] = value,
+                None => break,
+            },
+            6 => registers[a] = !(registers[b] & registers[c]),
+            7 => {
+                halted = true;
+                break;
+            }
+            8 => {
+                let segment = Some(vec![0; registers[c] as usize]);
+                registers[b] = match free.pop() {
+                    Some(id) => {
+                        segments[id as usize] = segment;
+                        id
+                    }
+                    None => {
+                        segments.push(segment);
+                        (segments.len() - 1) as u32
+                    }
+                };
+            }
+            9 => {
+                let id = registers[c];
+                if id == 0 || !matches!(segments.get(id as usize), Some(Some(_))) {
+                    break;
+                }
+                segments[id as usize] = None;
+                free.push(id);
+            }
+            10 => match u8::try_from(registers[c]) {
+                Ok(byte) => output.push(byte),
+                Err(_) => break,
+            },
+            11 => registers[c] = input.next().map_or(u32::MAX, |&byte| byte as u32),
+            12 => {
+                let code = match segments.get(registers[b] as usize).and_then(|s| s.clone()) {
+                    Some(code) => code,
+                    None => break,
+                };
+                if registers[c] as usize >= code.len() {
+                    break;
+                }
+                segments[0] = Some(code);
+                pc = registers[c] as usize;
+            }
+            13 => registers[((word >> 25) & 7) as usize] = word & 0x1ff_ffff,
+            _ => break,
+        }
+    }
+    Outcome { output, registers: Some(registers), halted }
+}
@@ -0,0 +1,61 @@
+//! The syntax tree of a umc program.
+
+/// A unary operator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnOp {
+    /// `-x`, the two's complement
+    Neg,
+    /// `!x`, 1 if x is 0 and 0 otherwise
+    Not,
+}
+
+/// A binary operator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    /// `&&`, which only evaluates its right side if the left is nonzero
+    And,
+    /// `||`, which only evaluates its right side if the left is 0
+    Or,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Num(u32),
+    Var { name: String, line: usize },
+    Unary(UnOp, Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    /// A call of a function or a builtin
+    Call { name: String, args: Vec<Expr>, line: usize },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stmt {
+    /// `var name = value;`, declaring a variable for the rest of the block
+    Var { name: String, value: Expr, line: usize },
+    Assign { name: String, value: Expr, line: usize },
+    If { cond: Expr, then: Vec<Stmt>, otherwise: Vec<Stmt> },
+    While { cond: Expr, body: Vec<Stmt> },
+    /// `return;` returns 0
+    Return(Option<Expr>),
+    Expr(Expr),
+    Block(Vec<Stmt>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Function {
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: Vec<Stmt>,
+    pub line: usize,
+}
@@ -0,0 +1,478 @@
+//! Translation of the syntax tree into `rumasm` assembly.
+//!
+//! Expressions are evaluated into r4, with intermediate values pushed on
+//! the stack; see the crate documentation for the register and stack
+//! conventions. Every jump is spelled `r6 := label; goto r6 in program
+//! m[r0];`, and every branch as a CMov between two loaded labels, so that
+//! `rumdump` can follow them. A return address is the index of a call site
+//! rather than a label, and each function returns through a dispatch that
+//! chooses between the labels of its call sites with CMovs, so returns can
+//! be followed too and no label is ever stored.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use crate::ast::{BinOp, Expr, Function, Stmt, UnOp};
+use crate::{CompileError, STACK_WORDS};
+
+/// Builtins and the number of arguments each takes
+const BUILTINS: [(&str, usize); 7] =
+    [("putc", 1), ("getc", 0), ("alloc", 1), ("free", 1), ("load", 2), ("store", 3), ("halt", 0)];
+
+/// Generates the code of one function
+struct Generator<'a> {
+    /// Parameter count of every function
+    arities: &'a HashMap<String, usize>,
+    out: String,
+    /// Variables in scope, innermost block last, with their offsets from
+    /// the frame pointer
+    scopes: Vec<Vec<(String, i32)>>,
+    /// Local variable slots in use, and the most ever in use at once
+    slots: i32,
+    frame: i32,
+    labels: &'a mut usize,
+    /// The function being generated
+    name: &'a str,
+    /// Labels of the call sites of every function, in the order of their
+    /// return indexes
+    returns: &'a mut HashMap<String, Vec<String>>,
+}
+
+fn error<T>(line: usize, message: String) -> Result<T, CompileError> {
+    Err(CompileError { line, message })
+}
+
+impl Generator<'_> {
+    fn emit(&mut self, text: &str) {
+        writeln!(self.out, "    {}", text).unwrap();
+    }
+
+    fn label(&mut self) -> String {
+        *self.labels += 1;
+        format!("L{}", self.labels)
+    }
+
+    fn place(&mut self, label: &str) {
+        writeln!(self.out, "{}:", label).unwrap();
+    }
+
+    /// Loads any 32-bit constant into `reg`, which is not r7
+    fn constant(&mut self, reg: u32, value: u32) {
+        self.emit(&format!("loadval r{}, {}, r7", reg, value));
+    }
+
+    fn push(&mut self, reg: u32) {
+        self.emit(&format!("m[r1][r2] := r{};", reg));
+        self.emit("r6 := 1");
+        self.emit("r2 := r2 + r6;");
+    }
+
+    fn pop(&mut self, reg: u32) {
+        self.constant(6, u32::MAX);
+        self.emit("r2 := r2 + r6;");
+        self.emit(&format!("r{} := m[r1][r2];", reg));
+    }
+
+    fn jump(&mut self, label: &str) {
+        self.emit(&format!("r6 := {}", label));
+        self.emit("goto r6 in program m[r0];");
+    }
+
+    /// Jumps to `then` if r4 is nonzero and to `otherwise` if it is 0
+    fn branch(&mut self, then: &str, otherwise: &str) {
+        self.emit(&format!("r6 := {}", otherwise));
+        self.emit(&format!("r7 := {}", then));
+        self.emit("if (r4 != 0) r6 := r7;");
+        self.emit("goto r6 in program m[r0];");
+    }
+
+    /// Sets r6 to the stack index of the variable at `offset` from the frame
+    fn address(&mut self, offset: i32) {
+        self.constant(6, offset as u32);
+        self.emit("r6 := r3 + r6;");
+    }
+
+    fn lookup(&self, name: &str, line: usize) -> Result<i32, CompileError> {
+        self.scopes.iter().rev().flatten().find(|(var, _)| var == name).map(|&(_, offset)| offset)
+            .ok_or(CompileError { line, message: format!("'{}' is not declared", name) })
+    }
+
+    /// Sets r4 to 1 if it is 0 and to 0 otherwise
+    fn not(&mut self) {
+        self.emit("r5 := 1");
+        self.emit("if (r4 != 0) r5 := r0;");
+        self.emit("r4 := r5 + r0;");
+    }
+
+    /// Sets r4 to 1 if it is nonzero
+    fn truth(&mut self) {
+        self.emit("r5 := 1");
+        self.emit("if (r4 != 0) r4 := r5;");
+    }
+
+    /// Sets r4 to r4 - r5
+    fn subtract(&mut self) {
+        self.emit("r5 := r5 nand r5;");
+        self.emit("r6 := 1");
+        self.emit("r5 := r5 + r6;");
+        self.emit("r4 := r4 + r5;");
+    }
+
+    /// Sets r4 to 1 if r4 < r5 and to 0 otherwise. The UM cannot compare,
+    /// but for a nonzero r5, r4 < r5 exactly when r4 / r5 is 0.
+    fn less(&mut self) {
+        self.emit("r6 := 1");
+        self.emit("if (r5 != 0) r6 := r5;");
+        self.emit("r6 := r4 / r6;");
+        self.emit("r4 := 1");
+        self.emit("if (r6 != 0) r4 := r0;");
+        self.emit("r6 := 0");
+        self.emit("if (r5 != 0) r6 := r4;");
+        self.emit("r4 := r6 + r0;");
+    }
+
+    fn swap(&mut self) {
+        self.emit("r7 := r4 + r0;");
+        self.emit("r4 := r5 + r0;");
+        self.emit("r5 := r7 + r0;");
+    }
+
+    /// Evaluates the arguments of a builtin, leaving the last in r4 and the
+    /// others on the stack
+    fn arguments(&mut self, args: &[Expr]) -> Result<(), CompileError> {
+        for (i, arg) in args.iter().enumerate() {
+            if i > 0 {
+                self.push(4);
+            }
+            self.expr(arg)?;
+        }
+        Ok(())
+    }
+
+    fn call(&mut self, name: &str, args: &[Expr], line: usize) -> Result<(), CompileError> {
+        let arity = BUILTINS.iter().find(|(builtin, _)| *builtin == name).map(|&(_, arity)| arity)
+            .or(self.arities.get(name).copied());
+        match arity {
+            None => return error(line, format!("there is no function '{}'", name)),
+            Some(arity) if arity != args.len() => {
+                return error(line, format!("'{}' takes {} arguments, not {}", name, arity, args.len()))
+            }
+            _ => {}
+        }
+        match name {
+            "putc" => {
+                self.expr(&args[0])?;
+                self.emit("output r4;");
+                self.emit("r4 := 0");
+            }
+            "getc" => self.emit("r4 := input();"),
+            "alloc" => {
+                self.expr(&args[0])?;
+                self.emit("r4 := map segment (r4 words);");
+            }
+            "free" => {
+                self.expr(&args[0])?;
+                self.emit("unmap r4;");
+                self.emit("r4 := 0");
+            }
+            "load" => {
+                self.arguments(args)?;
+                self.emit("r5 := r4 + r0;");
+                self.pop(4);
+                self.emit("r4 := m[r4][r5];");
+            }
+            "store" => {
+                self.arguments(args)?;
+                self.emit("r7 := r4 + r0;");
+                self.pop(5);
+                self.pop(4);
+                self.emit("m[r4][r5] := r7;");
+                self.emit("r4 := 0");
+            }
+            "halt" => self.emit("halt"),
+            _ => {
+                for arg in args {
+                    self.expr(arg)?;
+                    self.push(4);
+                }
+                let back = self.label();
+                let sites = self.returns.get_mut(name).unwrap();
+                let index = sites.len() as u32;
+                sites.push(back.clone());
+                self.constant(4, index);
+                self.push(4);
+                self.jump(&format!("fn_{}", name));
+                self.place(&back);
+                if !args.is_empty() {
+                    self.constant(6, (args.len() as u32).wrapping_neg());
+                    self.emit("r2 := r2 + r6;");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Result<(), CompileError> {
+        match expr {
+            Expr::Num(value) => self.constant(4, *value),
+            Expr::Var { name, line } => {
+                let offset = self.lookup(name, *line)?;
+                self.address(offset);
+                self.emit("r4 := m[r1][r6];");
+            }
+            Expr::Unary(op, operand) => {
+                self.expr(operand)?;
+                match op {
+                    UnOp::Neg => {
+                        self.emit("r4 := r4 nand r4;");
+                        self.emit("r6 := 1");
+                        self.emit("r4 := r4 + r6;");
+                    }
+                    UnOp::Not => self.not(),
+                }
+            }
+            Expr::Binary(op @ (BinOp::And | BinOp::Or), left, right) => {
+                let (rest, end) = (self.label(), self.label());
+                self.expr(left)?;
+                match op {
+                    BinOp::And => self.branch(&rest, &end),
+                    _ => self.branch(&end, &rest),
+                }
+                self.place(&rest);
+                self.expr(right)?;
+                self.place(&end);
+                self.truth();
+            }
+            Expr::Binary(op, left, right) => {
+                self.expr(left)?;
+                self.push(4);
+                self.expr(right)?;
+                self.emit("r5 := r4 + r0;");
+                self.pop(4);
+                match op {
+                    BinOp::Add => self.emit("r4 := r4 + r5;"),
+                    BinOp::Sub => self.subtract(),
+                    BinOp::Mul => self.emit("r4 := r4 * r5;"),
+                    BinOp::Div => self.emit("r4 := r4 / r5;"),
+                    BinOp::Mod => {
+                        self.emit("r6 := r4 / r5;");
+                        self.emit("r5 := r6 * r5;");
+                        self.subtract();
+                    }
+                    BinOp::Eq => {
+                        self.subtract();
+                        self.not();
+                    }
+                    BinOp::Ne => {
+                        self.subtract();
+                        self.truth();
+                    }
+                    BinOp::Lt => self.less(),
+                    BinOp::Ge => {
+                        self.less();
+                        self.not();
+                    }
+                    BinOp::Gt => {
+                        self.swap();
+                        self.less();
+                    }
+                    BinOp::Le => {
+                        self.swap();
+                        self.less();
+                        self.not();
+                    }
+                    BinOp::And | BinOp::Or => unreachable!("short-circuit operators are handled above"),
+                }
+            }
+            Expr::Call { name, args, line } => self.call(name, args, *line)?,
+        }
+        Ok(())
+    }
+
+    fn block(&mut self, statements: &[Stmt]) -> Result<(), CompileError> {
+        self.scopes.push(Vec::new());
+        let slots = self.slots;
+        for statement in statements {
+            self.statement(statement)?;
+        }
+        self.slots = slots;
+        self.scopes.pop();
+        Ok(())
+    }
+
+    fn ret(&mut self) {
+        self.emit("r2 := r3 + r0;");
+        self.pop(3);
+        self.pop(7);
+        self.jump(&format!("ret_{}", self.name));
+    }
+
+    fn statement(&mut self, statement: &Stmt) -> Result<(), CompileError> {
+        match statement {
+            Stmt::Var { name, value, line } => {
+                if self.scopes.last().unwrap().iter().any(|(var, _)| var == name) {
+                    return error(*line, format!("'{}' is already declared in this block", name));
+                }
+                self.expr(value)?;
+                let offset = self.slots;
+                self.slots += 1;
+                self.frame = self.frame.max(self.slots);
+                self.scopes.last_mut().unwrap().push((name.clone(), offset));
+                self.address(offset);
+                self.emit("m[r1][r6] := r4;");
+            }
+            Stmt::Assign { name, value, line } => {
+                let offset = self.lookup(name, *line)?;
+                self.expr(value)?;
+                self.address(offset);
+                self.emit("m[r1][r6] := r4;");
+            }
+            Stmt::If { cond, then, otherwise } => {
+                let (then_label, else_label, end) = (self.label(), self.label(), self.label());
+                self.expr(cond)?;
+                self.branch(&then_label, &else_label);
+                self.place(&then_label);
+                self.block(then)?;
+                // The jump would never run after a return
+                if !matches!(then.last(), Some(Stmt::Return(_))) {
+                    self.jump(&end);
+                }
+                self.place(&else_label);
+                self.block(otherwise)?;
+                self.place(&end);
+            }
+            Stmt::While { cond, body } => {
+                let (top, body_label, end) = (self.label(), self.label(), self.label());
+                self.place(&top);
+                self.expr(cond)?;
+                self.branch(&body_label, &end);
+                self.place(&body_label);
+                self.block(body)?;
+                if !matches!(body.last(), Some(Stmt::Return(_))) {
+                    self.jump(&top);
+                }
+                self.place(&end);
+            }
+            Stmt::Return(value) => {
+                match value {
+                    Some(value) => self.expr(value)?,
+                    None => self.emit("r4 := 0"),
+                }
+                self.ret();
+            }
+            Stmt::Expr(value) => self.expr(value)?,
+            Stmt::Block(statements) => self.block(statements)?,
+        }
+        Ok(())
+    }
+}
+
+/// Generates the dispatch through which `name` returns to the call site
+/// whose index is in r7. Step k loads the label of site k and keeps the
+/// label chosen so far unless r7 - k is 0; r1, the stack, is never 0.
+///
+/// # Arguments:
+/// * `out`: The assembly so far
+/// * `name`: The function
+/// * `sites`: Labels of the call sites of `name`
+fn dispatch(out: &mut String, name: &str, sites: &[String]) {
+    writeln!(out, "\n# return from {}", name).unwrap();
+    writeln!(out, "ret_{}:", name).unwrap();
+    let Some((first, rest)) = sites.split_first() else {
+        // Nothing calls the function, so it never returns
+        writeln!(out, "    halt").unwrap();
+        return;
+    };
+    writeln!(out, "    r6 := {}", first).unwrap();
+    for site in rest {
+        writeln!(out, "    loadval r5, {}", u32::MAX).unwrap();
+        writeln!(out, "    r7 := r7 + r5;").unwrap();
+        writeln!(out, "    r5 := {}", site).unwrap();
+        writeln!(out, "    if (r7 != 0) r5 := r6;").unwrap();
+        writeln!(out, "    if (r1 != 0) r6 := r5;").unwrap();
+    }
+    writeln!(out, "    goto r6 in program m[r0];").unwrap();
+}
+
+/// Generates the assembly of a whole program: the startup code, every
+/// function, then the dispatch each of them returns through
+///
+/// # Arguments:
+/// * `functions`: The parsed program
+pub fn generate(functions: &[Function]) -> Result<String, CompileError> {
+    let mut arities = HashMap::new();
+    for function in functions {
+        if BUILTINS.iter().any(|(name, _)| *name == function.name) {
+            return error(function.line, format!("'{}' is a builtin", function.name));
+        }
+        if arities.insert(function.name.clone(), function.params.len()).is_some() {
+            return error(function.line, format!("'{}' is defined twice", function.name));
+        }
+    }
+    match functions.iter().find(|function| function.name == "main") {
+        None => return error(1, "there is no function 'main'".to_string()),
+        Some(main) if !main.params.is_empty() => return error(main.line, "'main' takes no arguments".to_string()),
+        _ => {}
+    }
+
+    let mut returns: HashMap<String, Vec<String>> =
+        functions.iter().map(|function| (function.name.clone(), Vec::new())).collect();
+    returns.get_mut("main").unwrap().push("exit".to_string());
+
+    let mut out = String::new();
+    writeln!(out, "# startup: map the stack, call main, halt").unwrap();
+    writeln!(out, "    r0 := 0").unwrap();
+    writeln!(out, "    r2 := 0").unwrap();
+    writeln!(out, "    r3 := 0").unwrap();
+    writeln!(out, "    loadval r7, {}, r6", STACK_WORDS).unwrap();
+    writeln!(out, "    r1 := map segment (r7 words);").unwrap();
+    writeln!(out, "    r4 := 0").unwrap();
+    writeln!(out, "    m[r1][r2] := r4;").unwrap();
+    writeln!(out, "    r6 := 1").unwrap();
+    writeln!(out, "    r2 := r2 + r6;").unwrap();
+    writeln!(out, "    r6 := fn_main").unwrap();
+    writeln!(out, "    goto r6 in program m[r0];").unwrap();
+    writeln!(out, "exit:").unwrap();
+    writeln!(out, "    halt").unwrap();
+
+    let mut labels = 0;
+    for function in functions {
+        let arity = function.params.len() as i32;
+        // Argument i is below the return address and the saved frame pointer
+        let params = function.params.iter().enumerate().map(|(i, name)| (name.clone(), i as i32 - arity - 2));
+        let mut generator = Generator {
+            arities: &arities,
+            out: String::new(),
+            scopes: vec![params.collect()],
+            slots: 0,
+            frame: 0,
+            labels: &mut labels,
+            name: &function.name,
+            returns: &mut returns,
+        };
+        for (i, name) in function.params.iter().enumerate() {
+            if function.params[..i].contains(name) {
+                return error(function.line, format!("parameter '{}' is repeated", name));
+            }
+        }
+        generator.block(&function.body)?;
+        if !matches!(function.body.last(), Some(Stmt::Return(_))) {
+            generator.emit("r4 := 0");
+            generator.ret();
+        }
+        let body = generator.out;
+
+        writeln!(out, "\n# fn {}({})", function.name, function.params.join(", ")).unwrap();
+        writeln!(out, "fn_{}:", function.name).unwrap();
+        writeln!(out, "    m[r1][r2] := r3;").unwrap();
+        writeln!(out, "    r6 := 1").unwrap();
+        writeln!(out, "    r2 := r2 + r6;").unwrap();
+        writeln!(out, "    r3 := r2 + r0;").unwrap();
+        writeln!(out, "    loadval r6, {}, r7", generator.frame).unwrap();
+        writeln!(out, "    r2 := r2 + r6;").unwrap();
+        out.push_str(&body);
+    }
+    for function in functions {
+        dispatch(&mut out, &function.name, &returns[&function.name]);
+    }
+    Ok(out)
+}
@@ -0,0 +1,98 @@
+//! Splits umc source into tokens.
+
+use crate::CompileError;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    Num(u32),
+    Ident(String),
+    Sym(&'static str),
+}
+
+/// Longer symbols come first so that `<=` is not read as `<` and `=`
+const SYMBOLS: [&str; 21] = [
+    "==", "!=", "<=", ">=", "&&", "||", "(", ")", "{", "}", ",", ";", "=", "<", ">", "+", "-", "*", "/", "%", "!",
+];
+
+/// Reads a character literal after its opening quote, returning its value
+/// and the number of bytes it took, closing quote included
+fn character(rest: &str) -> Option<(u32, usize)> {
+    let mut chars = rest.chars();
+    let (value, len) = match chars.next()? {
+        '\\' => {
+            let escaped = match chars.next()? {
+                'n' => '\n',
+                't' => '\t',
+                'r' => '\r',
+                '0' => '\0',
+                ch @ ('\\' | '\'') => ch,
+                _ => return None,
+            };
+            (escaped as u32, 2)
+        }
+        '\'' => return None,
+        ch => (ch as u32, ch.len_utf8()),
+    };
+    (chars.next()? == '\'').then_some((value, len + 1))
+}
+
+/// Splits `source` into tokens, each with the 1-based line it is on
+///
+/// # Arguments:
+/// * `source`: The program text
+pub fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, CompileError> {
+    let mut tokens = Vec::new();
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let error = |message: String| CompileError { line, message };
+        let mut rest = text.split("//").next().unwrap().trim_start();
+        while !rest.is_empty() {
+            if let Some(literal) = rest.strip_prefix('\'') {
+                let (value, len) = character(literal).ok_or(error("bad character literal".to_string()))?;
+                tokens.push((Token::Num(value), line));
+                rest = &literal[len..];
+            } else if let Some(sym) = SYMBOLS.iter().find(|sym| rest.starts_with(*sym)) {
+                tokens.push((Token::Sym(sym), line));
+                rest = &rest[sym.len()..];
+            } else {
+                let end = rest.find(|ch: char| !(ch.is_ascii_alphanumeric() || ch == '_')).unwrap_or(rest.len());
+                if end == 0 {
+                    return Err(error(format!("unexpected character '{}'", rest.chars().next().unwrap())));
+                }
+                let word = &rest[..end];
+                let token = if word.starts_with(|ch: char| ch.is_ascii_digit()) {
+                    let number = match word.strip_prefix("0x") {
+                        Some(hex) => u32::from_str_radix(hex, 16),
+                        None => word.parse(),
+                    };
+                    Token::Num(number.map_err(|_| error(format!("bad number '{}'", word)))?)
+                } else {
+                    Token::Ident(word.to_string())
+                };
+                tokens.push((token, line));
+                rest = &rest[end..];
+            }
+            rest = rest.trim_start();
+        }
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer::{tokenize, Token};
+
+    #[test]
+    fn splits_tokens() {
+        let tokens = tokenize("if (x<=0x1f) // comment\n  putc('\\n');").unwrap();
+        let expected = [
+            (Token::Ident("if".to_string()), 1), (Token::Sym("("), 1), (Token::Ident("x".to_string()), 1),
+            (Token::Sym("<="), 1), (Token::Num(31), 1), (Token::Sym(")"), 1),
+            (Token::Ident("putc".to_string()), 2), (Token::Sym("("), 2), (Token::Num(10), 2),
+            (Token::Sym(")"), 2), (Token::Sym(";"), 2),
+        ];
+        assert_eq!(tokens, expected);
+        assert_eq!(tokenize("x = 4294967296;").unwrap_err().line, 1);
+        assert!(tokenize("\n'ab'").unwrap_err().message.contains("character"));
+    }
+}
@@ -0,0 +1,236 @@
+//! umc, a compiler from a small imperative language to UM programs.
+//!
+//! # The language
+//!
+//! A program is a list of functions, one of which is `main()`:
+//!
+//! ```text
+//! fn square(n) { return n * n; }
+//!
+//! fn main() {
+//!     var i = 0;
+//!     while (i < 10) {
+//!         putc('0' + square(i) % 10);
+//!         i = i + 1;
+//!     }
+//!     putc('\n');
+//! }
+//! ```
+//!
+//! Every value is a 32-bit unsigned word and arithmetic wraps, as on the
+//! UM; `-x` is the two's complement. Operators, loosest first, are `||`,
+//! `&&`, `== !=`, `< <= > >=`, `+ -`, `* / %` and unary `- !`.
+//! Comparisons and the logical operators give 0 or 1, and `&&` and `||`
+//! only evaluate their right side when they need it. Division or `%` by 0
+//! faults. Statements are `var x = e;`, which declares `x` until the end
+//! of its block, `x = e;`, `if (e) {...} else {...}`, `while (e) {...}`,
+//! `return e;` and `e;`. A function that ends without `return` returns 0.
+//! Numbers are decimal, `0x` hex or character literals such as `'\n'`;
+//! `//` starts a comment.
+//!
+//! The builtins are:
+//!
+//! * `putc(c)`: Output of c, which faults if c is above 255
+//! * `getc()`: the next input byte, or 0xffffffff at the end of input
+//! * `alloc(n)`: the id of a new segment of n zeroed words
+//! * `free(p)`: unmaps segment p
+//! * `load(p, i)` and `store(p, i, v)`: read and write word i of segment p
+//! * `halt()`: stops the machine
+//!
+//! # Runtime conventions
+//!
+//! * r0 is set to 0 at startup and never written again, so `m[r0]` is the
+//!   program.
+//! * r1 holds the id of the stack segment, of `STACK_WORDS` words, which
+//!   the startup code maps before it calls `main`. Running out of stack
+//!   faults on a Store out of bounds.
+//! * r2 is the stack pointer, the index of the first free word of the
+//!   stack; r3 is the frame pointer. Both start at 0.
+//! * r4 is the accumulator: every expression leaves its value there, and a
+//!   function returns its result in it. r5 holds the right operand of a
+//!   binary operator, and r6 and r7 are scratch.
+//! * A call pushes the arguments in order, then the return index, which
+//!   numbers the call sites of the function, and jumps to the function
+//!   with Load Program from segment 0. The function pushes the caller's
+//!   r3, points r3 at the next word and reserves its local variables
+//!   there, so argument i of n is at `r3 - (n + 2) + i` and local j at
+//!   `r3 + j`. It returns by resetting r2 to r3, popping r3 and the return
+//!   index into r7, and jumping to its return dispatch, which jumps on to
+//!   the call site. The caller then pops the arguments.
+//! * Intermediate values are pushed above the locals while the other
+//!   operand is evaluated.
+//! * The label of function `f` is `fn_f` and that of its return dispatch
+//!   `ret_f`; other labels are `L` and a number, except `exit`, where
+//!   `main` returns to halt the machine.
+
+pub mod ast;
+pub mod codegen;
+pub mod lexer;
+pub mod parser;
+
+use std::fmt;
+use rumdump::rumasm;
+
+/// Words in the stack segment mapped at startup
+pub const STACK_WORDS: u32 = 1 << 18;
+
+/// An error in a program and the 1-based line it was found on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompileError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+/// Compiles a program to `rumasm` assembly
+///
+/// # Arguments:
+/// * `source`: The program text
+pub fn compile_to_asm(source: &str) -> Result<String, CompileError> {
+    let functions = parser::parse(lexer::tokenize(source)?)?;
+    codegen::generate(&functions)
+}
+
+/// Compiles a program to the words of $m[0]
+///
+/// # Arguments:
+/// * `source`: The program text
+pub fn compile(source: &str) -> Result<Vec<u32>, CompileError> {
+    let asm = compile_to_asm(source)?;
+    rumasm::assemble(&asm).map_err(|e| CompileError {
+        line: 0,
+        message: format!("generated assembly does not assemble: {}", e),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use umcore::fault::{HaltReason, UmFault};
+    use umcore::io::StreamIo;
+    use umcore::um::Um;
+    use rumdump::rumcfg::Analysis;
+    use rumdump::rumopt::optimize;
+    use crate::compile;
+
+    /// Runs `program` with `input`, returning its output
+    fn execute(program: Vec<u32>, input: &[u8]) -> Result<Vec<u8>, UmFault> {
+        let mut um = Um::with_io(program, StreamIo::new(input, Vec::new()));
+        assert_eq!(um.run()?, HaltReason::Halted);
+        Ok(um.into_io().into_parts().unwrap().1)
+    }
+
+    /// Compiles and runs `source` with `input`, returning its output
+    fn run(source: &str, input: &[u8]) -> Result<Vec<u8>, UmFault> {
+        execute(compile(source).unwrap_or_else(|e| panic!("{}", e)), input)
+    }
+
+    const PRINT: &str = "
+        fn print(n) {
+            if (n >= 10) { print(n / 10); }
+            putc('0' + n % 10);
+        }
+        fn println(n) { print(n); putc('\\n'); }";
+
+    #[test]
+    fn arithmetic_and_comparisons() {
+        let source = format!("{}
+            fn main() {{
+                println(7 + 6 * 5 - 4 / 2);
+                println(100 % 7);
+                println(-1);
+                println(0 - 1 == 4294967295);
+                println((3 < 4) + (4 < 4) * 10 + (5 <= 5) * 100 + (6 > 7) * 1000 + (7 >= 8) * 10000);
+                println((0 < 0) + !0 * 10 + !7 * 100 + (2 != 3) * 1000);
+                println(123456789 * 3);
+            }}", PRINT);
+        assert_eq!(run(&source, b"").unwrap(), b"35\n2\n4294967295\n1\n101\n1010\n370370367\n");
+    }
+    #[test]
+    fn control_flow_and_recursion() {
+        let source = format!("{}
+            fn fib(n) {{
+                if (n < 2) {{ return n; }}
+                return fib(n - 1) + fib(n - 2);
+            }}
+            fn main() {{
+                var i = 0;
+                while (i < 12) {{
+                    var f = fib(i);
+                    if (f % 2 == 0) {{ print(f); }} else if (f > 20) {{ putc('*'); }} else {{ putc('.'); }}
+                    i = i + 1;
+                }}
+                putc('\\n');
+                if (0 && halt()) {{}}
+                if (1 || halt()) {{ putc('!'); }}
+            }}", PRINT);
+        assert_eq!(run(&source, b"").unwrap(), b"0..2..8.*34**\n!");
+    }
+    #[test]
+    fn heap_and_io() {
+        // Reads the input into a growable array, then writes it reversed
+        let source = "
+            fn main() {
+                var size = 2;
+                var data = alloc(size);
+                var len = 0;
+                var c = getc();
+                while (c != 0xffffffff) {
+                    if (len == size) {
+                        var bigger = alloc(size * 2);
+                        var i = 0;
+                        while (i < len) { store(bigger, i, load(data, i)); i = i + 1; }
+                        free(data);
+                        data = bigger;
+                        size = size * 2;
+                    }
+                    store(data, len, c);
+                    len = len + 1;
+                    c = getc();
+                }
+                while (len > 0) { len = len - 1; putc(load(data, len)); }
+            }";
+        assert_eq!(run(source, b"hello, world").unwrap(), b"dlrow ,olleh");
+    }
+    #[test]
+    fn jumps_and_returns_are_followed() {
+        let source = format!("{}
+            fn fib(n) {{
+                if (n < 2) {{ return n; }}
+                return fib(n - 1) + fib(n - 2);
+            }}
+            fn main() {{
+                var i = 0;
+                while (i < 15) {{ println(fib(i)); i = i + 1; }}
+                putc(getc());
+            }}", PRINT);
+        let program = compile(&source).unwrap();
+        let analysis = Analysis::new(&program);
+        assert!(analysis.unresolved.is_empty());
+        assert!((0..program.len()).all(|pc| analysis.reachable[pc]));
+        let optimized = optimize(&program).unwrap();
+        assert!(optimized.len() < program.len());
+        assert_eq!(execute(optimized, b"x").unwrap(), execute(program, b"x").unwrap());
+    }
+    #[test]
+    fn faults_surface() {
+        assert!(matches!(run("fn main() { putc(256); }", b""), Err(UmFault::OutputOutOfRange { .. })));
+        assert!(matches!(run("fn f(n) { return 1 / n; } fn main() { f(0); }", b""),
+            Err(UmFault::DivideByZero { .. })));
+    }
+    #[test]
+    fn reports_errors() {
+        let error = |source: &str| compile(source).unwrap_err().to_string();
+        assert_eq!(error("fn f() {}"), "line 1: there is no function 'main'");
+        assert_eq!(error("fn main() {\n x = 1;\n}"), "line 2: 'x' is not declared");
+        assert_eq!(error("fn main() {\n putc(1, 2);\n}"), "line 2: 'putc' takes 1 arguments, not 2");
+        assert_eq!(error("fn main() {}\nfn main() {}"), "line 2: 'main' is defined twice");
+        assert_eq!(error("fn main() { var x = 1; var x = 2; }"), "line 1: 'x' is already declared in this block");
+    }
+}
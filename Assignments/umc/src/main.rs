@@ -0,0 +1,58 @@
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::process;
+use rumdump::rumasm;
+
+const USAGE: &str = "usage: umc [-S] [-o OUT] [FILE]";
+
+fn main() {
+    let mut input = None;
+    let mut output = None;
+    let mut asm = false;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = args.next(),
+            "-S" => asm = true,
+            _ if arg.starts_with('-') => {
+                eprintln!("{}", USAGE);
+                process::exit(2);
+            }
+            _ => input = Some(arg),
+        }
+    }
+
+    let mut source = String::new();
+    let read = match &input {
+        Some(filename) => fs::read_to_string(filename).map(|text| source = text),
+        None => io::stdin().read_to_string(&mut source).map(|_| ()),
+    };
+    let name = input.as_deref().unwrap_or("<stdin>");
+    if let Err(error) = read {
+        eprintln!("umc: {}: {}", name, error);
+        process::exit(1);
+    }
+
+    // -S writes the assembly, which rumasm turns into the same program
+    let compiled = if asm {
+        umc::compile_to_asm(&source).map(String::into_bytes)
+    } else {
+        umc::compile(&source).map(|words| rumasm::to_bytes(&words))
+    };
+    let bytes = match compiled {
+        Ok(bytes) => bytes,
+        Err(error) => {
+            eprintln!("umc: {}: {}", name, error);
+            process::exit(1);
+        }
+    };
+    let written = match &output {
+        Some(filename) => fs::write(filename, bytes),
+        None => io::stdout().write_all(&bytes),
+    };
+    if let Err(error) = written {
+        eprintln!("umc: {}", error);
+        process::exit(1);
+    }
+}
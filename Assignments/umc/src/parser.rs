@@ -0,0 +1,268 @@
+//! Recursive-descent parser from tokens to the syntax tree.
+
+use crate::ast::{BinOp, Expr, Function, Stmt, UnOp};
+use crate::lexer::Token;
+use crate::CompileError;
+
+/// Binary operators by precedence level, loosest first
+const LEVELS: [&[(&str, BinOp)]; 6] = [
+    &[("||", BinOp::Or)],
+    &[("&&", BinOp::And)],
+    &[("==", BinOp::Eq), ("!=", BinOp::Ne)],
+    &[("<", BinOp::Lt), ("<=", BinOp::Le), (">", BinOp::Gt), (">=", BinOp::Ge)],
+    &[("+", BinOp::Add), ("-", BinOp::Sub)],
+    &[("*", BinOp::Mul), ("/", BinOp::Div), ("%", BinOp::Mod)],
+];
+
+const KEYWORDS: [&str; 6] = ["fn", "var", "if", "else", "while", "return"];
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    next: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(token, _)| token)
+    }
+
+    /// The line of the next token, or of the last one at the end
+    fn line(&self) -> usize {
+        self.tokens.get(self.next).or(self.tokens.last()).map_or(1, |&(_, line)| line)
+    }
+
+    fn error<T>(&self, message: String) -> Result<T, CompileError> {
+        Err(CompileError { line: self.line(), message })
+    }
+
+    fn describe(&self) -> String {
+        match self.peek() {
+            Some(Token::Num(n)) => n.to_string(),
+            Some(Token::Ident(name)) => format!("'{}'", name),
+            Some(Token::Sym(sym)) => format!("'{}'", sym),
+            None => "the end of the program".to_string(),
+        }
+    }
+
+    /// Consumes the symbol or keyword `text` if it is next
+    fn eat(&mut self, text: &str) -> bool {
+        let found = match self.peek() {
+            Some(Token::Sym(sym)) => *sym == text,
+            Some(Token::Ident(word)) => word == text && KEYWORDS.contains(&text),
+            _ => false,
+        };
+        self.next += found as usize;
+        found
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), CompileError> {
+        if self.eat(text) {
+            Ok(())
+        } else {
+            self.error(format!("expected '{}', found {}", text, self.describe()))
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, CompileError> {
+        match self.peek() {
+            Some(Token::Ident(name)) if !KEYWORDS.contains(&name.as_str()) => {
+                let name = name.clone();
+                self.next += 1;
+                Ok(name)
+            }
+            _ => self.error(format!("expected a name, found {}", self.describe())),
+        }
+    }
+
+    fn function(&mut self) -> Result<Function, CompileError> {
+        let line = self.line();
+        self.expect("fn")?;
+        let name = self.ident()?;
+        self.expect("(")?;
+        let mut params = Vec::new();
+        if !self.eat(")") {
+            loop {
+                params.push(self.ident()?);
+                if self.eat(")") {
+                    break;
+                }
+                self.expect(",")?;
+            }
+        }
+        let body = self.block()?;
+        Ok(Function { name, params, body, line })
+    }
+
+    fn block(&mut self) -> Result<Vec<Stmt>, CompileError> {
+        self.expect("{")?;
+        let mut statements = Vec::new();
+        while !self.eat("}") {
+            if self.peek().is_none() {
+                return self.error("expected '}', found the end of the program".to_string());
+            }
+            statements.push(self.statement()?);
+        }
+        Ok(statements)
+    }
+
+    fn statement(&mut self) -> Result<Stmt, CompileError> {
+        let line = self.line();
+        if self.eat("var") {
+            let name = self.ident()?;
+            self.expect("=")?;
+            let value = self.expr()?;
+            self.expect(";")?;
+            return Ok(Stmt::Var { name, value, line });
+        }
+        if self.eat("if") {
+            self.expect("(")?;
+            let cond = self.expr()?;
+            self.expect(")")?;
+            let then = self.block()?;
+            let otherwise = match (self.eat("else"), self.peek()) {
+                (false, _) => Vec::new(),
+                (true, Some(Token::Ident(word))) if word == "if" => vec![self.statement()?],
+                (true, _) => self.block()?,
+            };
+            return Ok(Stmt::If { cond, then, otherwise });
+        }
+        if self.eat("while") {
+            self.expect("(")?;
+            let cond = self.expr()?;
+            self.expect(")")?;
+            return Ok(Stmt::While { cond, body: self.block()? });
+        }
+        if self.eat("return") {
+            let value = if self.eat(";") {
+                None
+            } else {
+                let value = self.expr()?;
+                self.expect(";")?;
+                Some(value)
+            };
+            return Ok(Stmt::Return(value));
+        }
+        if let Some(Token::Sym("{")) = self.peek() {
+            return Ok(Stmt::Block(self.block()?));
+        }
+        if let (Some((Token::Ident(name), _)), Some((Token::Sym("="), _))) =
+            (self.tokens.get(self.next), self.tokens.get(self.next + 1)) {
+            let name = name.clone();
+            self.next += 2;
+            let value = self.expr()?;
+            self.expect(";")?;
+            return Ok(Stmt::Assign { name, value, line });
+        }
+        let value = self.expr()?;
+        self.expect(";")?;
+        Ok(Stmt::Expr(value))
+    }
+
+    fn expr(&mut self) -> Result<Expr, CompileError> {
+        self.binary(0)
+    }
+
+    /// Parses a left-associative chain of the operators at `level`
+    fn binary(&mut self, level: usize) -> Result<Expr, CompileError> {
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        'chain: loop {
+            for &(sym, op) in LEVELS[level] {
+                if self.eat(sym) {
+                    let right = self.binary(level + 1)?;
+                    left = Expr::Binary(op, Box::new(left), Box::new(right));
+                    continue 'chain;
+                }
+            }
+            return Ok(left);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, CompileError> {
+        if self.eat("-") {
+            return Ok(Expr::Unary(UnOp::Neg, Box::new(self.unary()?)));
+        }
+        if self.eat("!") {
+            return Ok(Expr::Unary(UnOp::Not, Box::new(self.unary()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, CompileError> {
+        let line = self.line();
+        if let Some(&Token::Num(n)) = self.peek() {
+            self.next += 1;
+            return Ok(Expr::Num(n));
+        }
+        if self.eat("(") {
+            let value = self.expr()?;
+            self.expect(")")?;
+            return Ok(value);
+        }
+        let name = match self.ident() {
+            Ok(name) => name,
+            Err(_) => return self.error(format!("expected an expression, found {}", self.describe())),
+        };
+        if !self.eat("(") {
+            return Ok(Expr::Var { name, line });
+        }
+        let mut args = Vec::new();
+        if !self.eat(")") {
+            loop {
+                args.push(self.expr()?);
+                if self.eat(")") {
+                    break;
+                }
+                self.expect(",")?;
+            }
+        }
+        Ok(Expr::Call { name, args, line })
+    }
+}
+
+/// Parses a whole program, which is a sequence of functions
+///
+/// # Arguments:
+/// * `tokens`: The tokens of the program and their lines
+pub fn parse(tokens: Vec<(Token, usize)>) -> Result<Vec<Function>, CompileError> {
+    let mut parser = Parser { tokens, next: 0 };
+    let mut functions = Vec::new();
+    while parser.peek().is_some() {
+        functions.push(parser.function()?);
+    }
+    Ok(functions)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::{BinOp, Expr, Stmt};
+    use crate::lexer::tokenize;
+    use crate::parser::parse;
+
+    fn var(name: &str) -> Box<Expr> {
+        Box::new(Expr::Var { name: name.to_string(), line: 1 })
+    }
+
+    #[test]
+    fn parses_precedence() {
+        let functions = parse(tokenize("fn f(a, b) { return a + b * 2 < b || a == 1 && b; }").unwrap()).unwrap();
+        assert_eq!(functions[0].params, vec!["a", "b"]);
+        let sum = Expr::Binary(BinOp::Add, var("a"), Box::new(Expr::Binary(BinOp::Mul, var("b"), Box::new(Expr::Num(2)))));
+        let less = Expr::Binary(BinOp::Lt, Box::new(sum), var("b"));
+        let and = Expr::Binary(BinOp::And, Box::new(Expr::Binary(BinOp::Eq, var("a"), Box::new(Expr::Num(1)))), var("b"));
+        let expected = Stmt::Return(Some(Expr::Binary(BinOp::Or, Box::new(less), Box::new(and))));
+        assert_eq!(functions[0].body, vec![expected]);
+    }
+    #[test]
+    fn parses_statements() {
+        let source = "fn main() {\n var x = 1;\n while (x) { x = x - 1; }\n if (x) {} else if (1) { putc(65); }\n}";
+        let body = &parse(tokenize(source).unwrap()).unwrap()[0].body;
+        assert!(matches!(&body[0], Stmt::Var { name, line: 2, .. } if name == "x"));
+        assert!(matches!(&body[1], Stmt::While { body, .. } if matches!(body[0], Stmt::Assign { line: 3, .. })));
+        assert!(matches!(&body[2], Stmt::If { otherwise, .. } if matches!(otherwise[0], Stmt::If { .. })));
+        let error = parse(tokenize("fn main() {\n var = 1;\n}").unwrap()).unwrap_err();
+        assert_eq!((error.line, error.message.as_str()), (2, "expected a name, found '='"));
+    }
+}
@@ -0,0 +1,89 @@
+//! Compares the pre-decoded dispatch and the threaded engine against
+//! decoding every instruction word as it is executed.
+//!
+//! Run with `cargo bench --bench decode`.
+
+use std::time::{Duration, Instant};
+use umcore::io::StreamIo;
+use umcore::machine;
+use umcore::memory::{get, UmState, OP, RA, RB, RC, RL, VL};
+use umcore::um::{Engine, Um};
+
+const BODY_WORDS: u32 = 1 << 16;
+const ITERATIONS: u32 = 200;
+
+fn inst(opcode: u32, a: u32, b: u32, c: u32) -> u32 {
+    (opcode << 28) | (a << 6) | (b << 3) | c
+}
+
+fn load_value(a: u32, value: u32) -> u32 {
+    (13 << 28) | (a << 25) | value
+}
+
+/// A loop whose body is `BODY_WORDS` Add and Nand instructions
+fn program() -> Vec<u32> {
+    let start = 4;
+    let exit = start + BODY_WORDS + 4;
+    let mut words = vec![load_value(1, 1), load_value(7, ITERATIONS), load_value(3, start), inst(6, 6, 0, 0)];
+    for i in 0..BODY_WORDS {
+        words.push(if i % 2 == 0 { inst(3, 2, 2, 1) } else { inst(6, 5, 2, 1) });
+    }
+    // r7 := r7 - 1; if (r7 != 0) goto start else goto exit
+    words.extend([inst(3, 7, 7, 6), load_value(4, exit), inst(0, 4, 3, 7), inst(12, 0, 0, 4), inst(7, 0, 0, 0)]);
+    words
+}
+
+/// Runs `um` the way the interpreter did before decoding was cached
+fn undecoded(um: &mut UmState) {
+    loop {
+        let instruction = um.memory[0][um.program_counter];
+        let opcode = get(&OP, instruction);
+        let a = get(&RA, instruction) as usize;
+        let b = get(&RB, instruction) as usize;
+        let c = get(&RC, instruction) as usize;
+        um.program_counter += 1;
+        if opcode == 0 { machine::cmov(um, a, b, c); }
+        if opcode == 1 { machine::sload(um, a, b, c).unwrap(); }
+        if opcode == 2 { machine::store(um, a, b, c).unwrap(); }
+        if opcode == 3 { machine::add(um, a, b, c); }
+        if opcode == 4 { machine::mult(um, a, b, c); }
+        if opcode == 5 { machine::div(um, a, b, c).unwrap(); }
+        if opcode == 6 { machine::nand(um, a, b, c); }
+        if opcode == 7 { break; }
+        if opcode == 8 { machine::map_seg(um, b, c).unwrap(); }
+        if opcode == 9 { machine::unmap_seg(um, c).unwrap(); }
+        if opcode == 12 { machine::load_program(um, b, c).unwrap(); }
+        if opcode == 13 { machine::load_value(um, get(&RL, instruction) as usize, get(&VL, instruction)); }
+    }
+}
+
+fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+fn main() {
+    let words = program();
+
+    let mut state = UmState::new(words.clone());
+    let start = Instant::now();
+    undecoded(&mut state);
+    let baseline = start.elapsed();
+
+    let mut um = Um::with_io(words.clone(), StreamIo::new(&b""[..], Vec::new()));
+    let start = Instant::now();
+    um.run().unwrap();
+    let decoded = start.elapsed();
+    assert_eq!(um.state().registers, state.registers);
+
+    let mut threaded = Um::with_io(words, StreamIo::new(&b""[..], Vec::new()));
+    threaded.set_engine(Engine::Threaded);
+    let start = Instant::now();
+    threaded.run().unwrap();
+    let threaded_time = start.elapsed();
+    assert_eq!(threaded.state().registers, state.registers);
+
+    println!("{} instructions", um.stats().executed);
+    println!("decode on every step: {:>10.3} ms", ms(baseline));
+    println!("pre-decoded:          {:>10.3} ms", ms(decoded));
+    println!("threaded:             {:>10.3} ms", ms(threaded_time));
+}
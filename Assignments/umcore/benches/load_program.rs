@@ -0,0 +1,38 @@
+//! Compares Load Program against the full segment copy it used to make.
+//!
+//! Run with `cargo bench --bench load_program`.
+
+use std::hint::black_box;
+use std::rc::Rc;
+use std::time::Instant;
+use umcore::io::StreamIo;
+use umcore::um::Um;
+
+const SEGMENT_WORDS: usize = 1 << 20;
+const JUMPS: u64 = 1000;
+
+fn main() {
+    // $m[1] holds a 4 MB program whose first word jumps back to its own
+    // start: goto r4 in program m[r2], with r2 = 1 and r4 = 0
+    let mut code = vec![0_u32; SEGMENT_WORDS];
+    code[0] = (12 << 28) | (2 << 3) | 4;
+    let mut um = Um::with_io(code.clone(), StreamIo::new(&b""[..], Vec::new()));
+    um.state_mut().memory.push(Rc::new(code));
+    um.state_mut().mapped.push(true);
+    um.state_mut().registers[2] = 1;
+
+    let start = Instant::now();
+    um.run_for(JUMPS).unwrap();
+    let shared = start.elapsed();
+
+    let segment = um.state().memory[1].clone();
+    let start = Instant::now();
+    for _ in 0..JUMPS {
+        black_box(segment.to_vec());
+    }
+    let copied = start.elapsed();
+
+    println!("{} jumps into a {}-word segment", JUMPS, SEGMENT_WORDS);
+    println!("copy-on-write load program: {:>10.3} ms", shared.as_secs_f64() * 1000.0);
+    println!("copying load program:       {:>10.3} ms", copied.as_secs_f64() * 1000.0);
+}
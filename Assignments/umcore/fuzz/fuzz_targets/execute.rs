@@ -0,0 +1,10 @@
+//! Run with `cargo +nightly fuzz run execute` from `Assignments/umcore`.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Halts, faults and exhausted budgets are all fine; panics are not
+    let _ = umcore::fuzz::run(data);
+});
@@ -0,0 +1,136 @@
+use std::rc::{Rc, Weak};
+use crate::memory::{get, Segment, OP, RA, RB, RC, RL, VL};
+
+/// A decoded UM instruction. Register fields are register numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    CMov { a: u8, b: u8, c: u8 },
+    Load { a: u8, b: u8, c: u8 },
+    Store { a: u8, b: u8, c: u8 },
+    Add { a: u8, b: u8, c: u8 },
+    Mul { a: u8, b: u8, c: u8 },
+    Div { a: u8, b: u8, c: u8 },
+    Nand { a: u8, b: u8, c: u8 },
+    Halt,
+    MapSegment { b: u8, c: u8 },
+    UnmapSegment { c: u8 },
+    Output { c: u8 },
+    Input { c: u8 },
+    LoadProgram { b: u8, c: u8 },
+    LoadValue { a: u8, value: u32 },
+    /// A word whose opcode is 14 or 15
+    Invalid { opcode: u8 },
+}
+
+/// Decodes one instruction word
+///
+/// # Arguments:
+/// * `word`: The instruction word
+pub fn decode(word: u32) -> Instruction {
+    let a = get(&RA, word) as u8;
+    let b = get(&RB, word) as u8;
+    let c = get(&RC, word) as u8;
+    match get(&OP, word) {
+        0 => Instruction::CMov { a, b, c },
+        1 => Instruction::Load { a, b, c },
+        2 => Instruction::Store { a, b, c },
+        3 => Instruction::Add { a, b, c },
+        4 => Instruction::Mul { a, b, c },
+        5 => Instruction::Div { a, b, c },
+        6 => Instruction::Nand { a, b, c },
+        7 => Instruction::Halt,
+        8 => Instruction::MapSegment { b, c },
+        9 => Instruction::UnmapSegment { c },
+        10 => Instruction::Output { c },
+        11 => Instruction::Input { c },
+        12 => Instruction::LoadProgram { b, c },
+        13 => Instruction::LoadValue { a: get(&RL, word) as u8, value: get(&VL, word) },
+        opcode => Instruction::Invalid { opcode: opcode as u8 },
+    }
+}
+
+/// Decodes every word of a segment
+pub fn decode_all(words: &[u32]) -> Vec<Instruction> {
+    words.iter().map(|&word| decode(word)).collect()
+}
+
+/// Number of previously loaded programs whose decoding is kept
+const RECENT: usize = 4;
+
+/// Decoded instructions shared between $m[0] and the cache
+type Code = Rc<Vec<Instruction>>;
+
+/// The decoded instructions of $m[0].
+///
+/// Stores to $m[0] re-decode the stored word, and Load Program switches to
+/// the decoding of the new $m[0]. Decodings of the last few programs are
+/// kept, keyed weakly by their segment, so jumping back and forth between
+/// code segments does not decode them again. A Store to one of those
+/// segments moves it to a new allocation, which drops it from the cache.
+#[derive(Debug, Clone)]
+pub struct CodeCache {
+    current: Code,
+    recent: Vec<(Weak<Vec<u32>>, Code)>,
+}
+
+impl CodeCache {
+    /// Decodes `program`, the initial $m[0]
+    pub fn new(program: &[u32]) -> Self {
+        CodeCache { current: Rc::new(decode_all(program)), recent: Vec::new() }
+    }
+
+    /// The instruction at `pc`, if it lies within $m[0]
+    #[inline]
+    pub fn get(&self, pc: usize) -> Option<Instruction> {
+        self.current.get(pc).copied()
+    }
+
+    /// Records that `word` was stored at `offset` in $m[0]
+    pub fn store(&mut self, offset: usize, word: u32) {
+        Rc::make_mut(&mut self.current)[offset] = decode(word);
+    }
+
+    /// Records that Load Program replaced $m[0] `old` with `new`
+    pub fn load(&mut self, old: &Segment, new: &Segment) {
+        if Rc::ptr_eq(old, new) {
+            return;
+        }
+        self.recent.retain(|(segment, _)| segment.strong_count() > 0);
+        let found = self.recent.iter().position(|(segment, _)| segment.as_ptr() == Rc::as_ptr(new));
+        let code = match found {
+            Some(index) => self.recent.remove(index).1,
+            None => Rc::new(decode_all(new)),
+        };
+        let previous = std::mem::replace(&mut self.current, code);
+        self.recent.push((Rc::downgrade(old), previous));
+        if self.recent.len() > RECENT {
+            self.recent.remove(0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use crate::decode::{decode, CodeCache, Instruction};
+
+    #[test]
+    fn decodes_fields() {
+        assert_eq!(decode(0x300000d1), Instruction::Add { a: 3, b: 2, c: 1 });
+        assert_eq!(decode((13 << 28) | (5 << 25) | 1234), Instruction::LoadValue { a: 5, value: 1234 });
+        assert_eq!(decode(0xf0000000), Instruction::Invalid { opcode: 15 });
+    }
+    #[test]
+    fn reuses_recent_programs() {
+        let first = Rc::new(vec![7 << 28]);
+        let second = Rc::new(vec![0xe0000000, 7 << 28]);
+        let mut cache = CodeCache::new(&first);
+        cache.load(&first, &second);
+        assert_eq!(cache.get(0), Some(Instruction::Invalid { opcode: 14 }));
+        let decoded = Rc::clone(&cache.recent[0].1);
+        cache.load(&second, &first);
+        assert!(Rc::ptr_eq(&cache.current, &decoded));
+        cache.store(0, 0x300000d1);
+        assert_eq!(cache.get(0), Some(Instruction::Add { a: 3, b: 2, c: 1 }));
+    }
+}
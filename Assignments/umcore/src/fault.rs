@@ -0,0 +1,107 @@
+use std::fmt;
+use std::io;
+use crate::limits::Limit;
+
+/// Why a Universal Machine stopped running without faulting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HaltReason {
+    /// The program executed a Halt instruction (opcode 7).
+    Halted,
+}
+
+/// A fault raised by a Universal Machine program.
+///
+/// Every variant carries the program counter of the faulting instruction
+/// (`pc`) and the instruction word found there (`instruction`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UmFault {
+    /// Division (opcode 5) with $r[C] equal to 0.
+    DivideByZero { pc: usize, instruction: u32 },
+    /// An instruction named a segment that is not mapped, including an
+    /// attempt to unmap $m[0].
+    UnmappedSegment { pc: usize, instruction: u32, segment: u32 },
+    /// An offset outside of a mapped segment. Running off the end of $m[0]
+    /// is reported with `segment` 0 and an `instruction` of 0, since there
+    /// is no word to fetch.
+    SegmentOutOfBounds { pc: usize, instruction: u32, segment: u32, offset: u32 },
+    /// The word at `pc` does not code for one of the fourteen instructions.
+    InvalidOpcode { pc: usize, instruction: u32, opcode: u32 },
+    /// Output (opcode 10) of a value larger than 255.
+    OutputOutOfRange { pc: usize, instruction: u32, value: u32 },
+    /// Unmap Segment (opcode 9) of a segment that was already unmapped.
+    /// Only raised in checked mode.
+    DoubleUnmap { pc: usize, instruction: u32, segment: u32 },
+    /// Map Segment (opcode 8) when all 2^32 segment IDs are in use.
+    SegmentIdsExhausted { pc: usize, instruction: u32 },
+    /// Load Program (opcode 12) to a program counter outside the new $m[0].
+    LoadProgramPcOutOfRange { pc: usize, instruction: u32, target: u32 },
+    /// The I/O device failed during Input, Output or the flush on halt.
+    Io { pc: usize, instruction: u32, kind: io::ErrorKind },
+    /// The instruction would take the machine past one of its resource
+    /// limits.
+    LimitExceeded { pc: usize, instruction: u32, limit: Limit },
+}
+
+impl UmFault {
+    /// Program counter of the faulting instruction.
+    pub fn pc(&self) -> usize {
+        match *self {
+            UmFault::DivideByZero { pc, .. }
+            | UmFault::UnmappedSegment { pc, .. }
+            | UmFault::SegmentOutOfBounds { pc, .. }
+            | UmFault::InvalidOpcode { pc, .. }
+            | UmFault::OutputOutOfRange { pc, .. }
+            | UmFault::DoubleUnmap { pc, .. }
+            | UmFault::SegmentIdsExhausted { pc, .. }
+            | UmFault::LoadProgramPcOutOfRange { pc, .. }
+            | UmFault::Io { pc, .. }
+            | UmFault::LimitExceeded { pc, .. } => pc,
+        }
+    }
+
+    /// Instruction word of the faulting instruction.
+    pub fn instruction(&self) -> u32 {
+        match *self {
+            UmFault::DivideByZero { instruction, .. }
+            | UmFault::UnmappedSegment { instruction, .. }
+            | UmFault::SegmentOutOfBounds { instruction, .. }
+            | UmFault::InvalidOpcode { instruction, .. }
+            | UmFault::OutputOutOfRange { instruction, .. }
+            | UmFault::DoubleUnmap { instruction, .. }
+            | UmFault::SegmentIdsExhausted { instruction, .. }
+            | UmFault::LoadProgramPcOutOfRange { instruction, .. }
+            | UmFault::Io { instruction, .. }
+            | UmFault::LimitExceeded { instruction, .. } => instruction,
+        }
+    }
+}
+
+impl fmt::Display for UmFault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            UmFault::DivideByZero { .. } => write!(f, "divide by zero")?,
+            UmFault::UnmappedSegment { segment, .. } => {
+                write!(f, "segment {} is not mapped", segment)?
+            }
+            UmFault::SegmentOutOfBounds { segment, offset, .. } => {
+                write!(f, "offset {} is outside of segment {}", offset, segment)?
+            }
+            UmFault::InvalidOpcode { opcode, .. } => write!(f, "invalid opcode {}", opcode)?,
+            UmFault::OutputOutOfRange { value, .. } => {
+                write!(f, "output value {} is larger than 255", value)?
+            }
+            UmFault::DoubleUnmap { segment, .. } => {
+                write!(f, "segment {} was already unmapped", segment)?
+            }
+            UmFault::SegmentIdsExhausted { .. } => write!(f, "all segment IDs are in use")?,
+            UmFault::LoadProgramPcOutOfRange { target, .. } => {
+                write!(f, "load program to pc {} outside of the new $m[0]", target)?
+            }
+            UmFault::Io { kind, .. } => write!(f, "I/O error: {}", kind)?,
+            UmFault::LimitExceeded { limit, .. } => write!(f, "{} exceeded", limit)?,
+        }
+        write!(f, " at pc {} (instruction 0x{:08x})", self.pc(), self.instruction())
+    }
+}
+
+impl std::error::Error for UmFault {}
@@ -0,0 +1,90 @@
+//! Entry point for fuzzing the decoder and executor.
+//!
+//! `run` accepts arbitrary bytes and executes them on a machine with an
+//! instruction budget and a cap on mapped words, so every input terminates
+//! with a halt, a typed fault or an exhausted budget. The cargo-fuzz target
+//! in `fuzz/` calls it; any panic or allocation failure there is a bug.
+
+use crate::fault::UmFault;
+use crate::io::StreamIo;
+use crate::limits::Limits;
+use crate::um::{Um, UmEvent};
+
+/// Instructions executed before a fuzzed program is stopped
+pub const BUDGET: u64 = 1 << 16;
+
+/// Largest total of mapped words a fuzzed program may reach
+pub const MAPPED_WORDS: usize = 1 << 22;
+
+/// Runs fuzzer input as a UM program
+///
+/// The first byte selects checked mode with its low bit. The following
+/// bytes are the big-endian words of $m[0], and the 0 to 3 bytes left
+/// over are the program's input.
+///
+/// # Arguments:
+/// * `data`: The fuzzer input
+pub fn run(data: &[u8]) -> Result<UmEvent, UmFault> {
+    let (checked, rest) = match data.split_first() {
+        Some((&flags, rest)) => (flags & 1 != 0, rest),
+        None => (false, data),
+    };
+    let words = rest.chunks_exact(4);
+    let input = words.remainder();
+    let program: Vec<u32> = words.map(|word| u32::from_be_bytes([word[0], word[1], word[2], word[3]])).collect();
+
+    let mut um = Um::with_io(program, StreamIo::new(input, Vec::new()));
+    um.set_checked(checked);
+    um.set_limits(Limits { mapped_words: Some(MAPPED_WORDS), ..Limits::default() });
+    um.run_for(BUDGET)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fault::UmFault;
+    use crate::fuzz::{run, MAPPED_WORDS};
+    use crate::limits::Limit;
+    use crate::um::UmEvent;
+
+    /// xorshift64, so the inputs are the same on every run
+    fn next(state: &mut u64) -> u64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+
+    #[test]
+    fn random_programs_terminate() {
+        let mut state = 0x2545_f491_4f6c_dd1d;
+        for _ in 0..2000 {
+            let len = 1 + next(&mut state) as usize % 256;
+            let data: Vec<u8> = (0..len).map(|_| next(&mut state) as u8).collect();
+            let _ = run(&data);
+            // Mostly valid opcodes, so that programs get further
+            let mut data = vec![next(&mut state) as u8];
+            for _ in 0..len / 4 {
+                let word = ((next(&mut state) % 14) << 28) as u32 | (next(&mut state) as u32 & 0x0fff_ffff);
+                data.extend(word.to_be_bytes());
+            }
+            let _ = run(&data);
+        }
+    }
+    #[test]
+    fn huge_map_hits_limit() {
+        // r1 := ~0; r2 := map segment (r1 words)
+        let mut data = vec![0];
+        data.extend((0x6000_0040_u32).to_be_bytes());
+        data.extend((0x8000_0011_u32).to_be_bytes());
+        let fault = run(&data).unwrap_err();
+        assert_eq!(fault, UmFault::LimitExceeded { pc: 1, instruction: 0x8000_0011,
+            limit: Limit::MappedWords(MAPPED_WORDS) });
+    }
+    #[test]
+    fn infinite_loop_stops() {
+        // goto r0 in program m[r0]
+        let mut data = vec![0];
+        data.extend((0xc000_0000_u32).to_be_bytes());
+        assert_eq!(run(&data), Ok(UmEvent::Running));
+    }
+}
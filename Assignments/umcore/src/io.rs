@@ -0,0 +1,94 @@
+use std::io::{self, BufWriter, Read, Stdin, Stdout, Write};
+
+/// A device for the UM's Input (opcode 11) and Output (opcode 10)
+/// instructions.
+///
+/// The machine flushes the device before every Input instruction and when
+/// it halts or faults, so implementations are free to buffer output.
+pub trait UmIo {
+    /// Reads one byte, or returns `None` at the end of input
+    fn input(&mut self) -> io::Result<Option<u8>>;
+
+    /// Writes one byte
+    fn output(&mut self, byte: u8) -> io::Result<()>;
+
+    /// Delivers any buffered output
+    fn flush(&mut self) -> io::Result<()>;
+}
+
+/// A `UmIo` device built from any reader and writer, such as in-memory
+/// buffers, files or pipes. Output is buffered until the machine flushes it.
+pub struct StreamIo<R: Read, W: Write> {
+    input: R,
+    output: BufWriter<W>,
+    /// Input reports the end whatever the reader holds
+    ended: bool,
+}
+
+/// The standard input and output device used by `rum`
+pub type StdIo = StreamIo<Stdin, Stdout>;
+
+impl<R: Read, W: Write> StreamIo<R, W> {
+    /// Creates a device reading from `input` and writing to `output`
+    ///
+    /// # Arguments:
+    /// * `input`: Source of the bytes returned by Input
+    /// * `output`: Destination of the bytes written by Output
+    pub fn new(input: R, output: W) -> Self {
+        StreamIo { input, output: BufWriter::with_capacity(1 << 16, output), ended: false }
+    }
+
+    /// Makes every later Input report the end of input, as it did for a
+    /// machine before it was saved in a snapshot
+    pub fn end_input(&mut self) {
+        self.ended = true;
+    }
+
+    /// Flushes any buffered output and returns the reader and writer
+    pub fn into_parts(self) -> io::Result<(R, W)> {
+        let output = self.output.into_inner().map_err(|e| e.into_error())?;
+        Ok((self.input, output))
+    }
+}
+
+impl StdIo {
+    /// Creates a device on the process's standard input and output
+    pub fn stdio() -> Self {
+        StreamIo::new(io::stdin(), io::stdout())
+    }
+}
+
+impl<R: Read, W: Write> UmIo for StreamIo<R, W> {
+    fn input(&mut self) -> io::Result<Option<u8>> {
+        if self.ended {
+            return Ok(None);
+        }
+        let mut byte = [0_u8; 1];
+        match self.input.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    fn output(&mut self, byte: u8) -> io::Result<()> {
+        self.output.write_all(&[byte])
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+impl<T: UmIo + ?Sized> UmIo for &mut T {
+    fn input(&mut self) -> io::Result<Option<u8>> {
+        (**self).input()
+    }
+
+    fn output(&mut self, byte: u8) -> io::Result<()> {
+        (**self).output(byte)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (**self).flush()
+    }
+}
@@ -0,0 +1,18 @@
+//! The Universal Machine of the A6 `rum` binary: the instruction decoder,
+//! machine state and segment management, the opcode implementations,
+//! program loading, resource limits, the `Um` driver and a scheduler that
+//! runs several machines connected by byte channels. The A5 `rum` keeps a
+//! machine of its own and shares the decoder, faults, I/O devices and
+//! loader.
+
+pub mod decode;
+pub mod fault;
+pub mod fuzz;
+pub mod io;
+pub mod limits;
+pub mod loader;
+pub mod machine;
+pub mod memory;
+pub mod sched;
+mod threaded;
+pub mod um;
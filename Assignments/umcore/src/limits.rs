@@ -0,0 +1,45 @@
+use std::fmt;
+use std::time::Duration;
+
+/// A resource limit that stopped a machine, with the configured maximum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    /// Total words in mapped segments, including $m[0]
+    MappedWords(usize),
+    /// Segments mapped at the same time, including $m[0]
+    LiveSegments(usize),
+    /// Instructions executed
+    Instructions(u64),
+    /// Time since the limits were set
+    WallClock(Duration),
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Limit::MappedWords(max) => write!(f, "limit of {} mapped words", max),
+            Limit::LiveSegments(max) => write!(f, "limit of {} live segments", max),
+            Limit::Instructions(max) => write!(f, "limit of {} instructions", max),
+            Limit::WallClock(max) => write!(f, "time limit of {:.3} s", max.as_secs_f64()),
+        }
+    }
+}
+
+/// Resource limits for running untrusted programs. `None` means unlimited,
+/// which is the default.
+///
+/// Mapped words are counted as the program sees them: $m[0] counts as a
+/// copy of the segment it was loaded from even while the two are shared.
+/// The segment limits are enforced by Map Segment and Load Program, the
+/// others by `Um::step` before each instruction.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// Largest total of words in mapped segments, including $m[0]
+    pub mapped_words: Option<usize>,
+    /// Most segments mapped at once, including $m[0]
+    pub live_segments: Option<usize>,
+    /// Most instructions the machine executes
+    pub instructions: Option<u64>,
+    /// Longest the machine may run, measured from `Um::set_limits`
+    pub wall_clock: Option<Duration>,
+}
@@ -0,0 +1,190 @@
+//! Loading programs into $m[0].
+//!
+//! A `.um` file is a sequence of big-endian 32-bit words. Hand-written test
+//! programs may instead be little-endian, or hex text: whitespace-separated
+//! words of up to eight hex digits, optionally prefixed with `0x`, with `#`
+//! starting a comment that runs to the end of the line.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::str::FromStr;
+use memmap2::Mmap;
+
+/// Files at least this large are memory-mapped rather than read
+pub const MMAP_THRESHOLD: u64 = 1 << 20;
+
+/// How the words of a program are stored
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Encoding {
+    /// Big-endian words, the `.um` format
+    #[default]
+    BigEndian,
+    /// Little-endian words
+    LittleEndian,
+    /// Hex text
+    Hex,
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "be" => Ok(Encoding::BigEndian),
+            "le" => Ok(Encoding::LittleEndian),
+            "hex" => Ok(Encoding::Hex),
+            _ => Err(format!("unknown encoding '{}', expected be, le or hex", name)),
+        }
+    }
+}
+
+/// Why a program could not be loaded. `path` is `<stdin>` for a program
+/// read from standard input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoadError {
+    /// The program file does not exist
+    NotFound { path: String },
+    /// Opening, mapping or reading the program failed
+    Io { path: String, kind: io::ErrorKind },
+    /// A binary program whose length is not a whole number of words
+    PartialWord { path: String, len: usize },
+    /// A hex-text program with something other than a word on `line`
+    InvalidHex { path: String, line: usize, token: String },
+}
+
+impl LoadError {
+    fn io(path: &str, error: io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::NotFound => LoadError::NotFound { path: path.to_string() },
+            kind => LoadError::Io { path: path.to_string(), kind },
+        }
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::NotFound { path } => write!(f, "{}: no such file", path),
+            LoadError::Io { path, kind } => write!(f, "{}: I/O error: {}", path, kind),
+            LoadError::PartialWord { path, len } => {
+                write!(f, "{}: length of {} bytes is not a multiple of 4", path, len)
+            }
+            LoadError::InvalidHex { path, line, token } => {
+                write!(f, "{}:{}: '{}' is not a hex word", path, line, token)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+/// Decodes the words of a program
+///
+/// # Arguments:
+/// * `bytes`: The contents of the program file
+/// * `encoding`: How the words are stored
+/// * `path`: Name of the program, for errors
+pub fn parse(bytes: &[u8], encoding: Encoding, path: &str) -> Result<Vec<u32>, LoadError> {
+    let word = match encoding {
+        Encoding::BigEndian => u32::from_be_bytes,
+        Encoding::LittleEndian => u32::from_le_bytes,
+        Encoding::Hex => return parse_hex(bytes, path),
+    };
+    if !bytes.len().is_multiple_of(4) {
+        return Err(LoadError::PartialWord { path: path.to_string(), len: bytes.len() });
+    }
+    Ok(bytes.chunks_exact(4).map(|x| word([x[0], x[1], x[2], x[3]])).collect())
+}
+
+fn parse_hex(bytes: &[u8], path: &str) -> Result<Vec<u32>, LoadError> {
+    let mut words = Vec::new();
+    for (number, line) in String::from_utf8_lossy(bytes).lines().enumerate() {
+        let code = line.split('#').next().unwrap_or("");
+        for token in code.split_whitespace() {
+            let digits = token.strip_prefix("0x").unwrap_or(token);
+            match u32::from_str_radix(digits, 16) {
+                Ok(word) if !digits.starts_with('+') => words.push(word),
+                _ => return Err(LoadError::InvalidHex {
+                    path: path.to_string(), line: number + 1, token: token.to_string()
+                }),
+            }
+        }
+    }
+    Ok(words)
+}
+
+/// Loads a program from a file, memory-mapping it if it is at least
+/// `MMAP_THRESHOLD` bytes. The file must not be truncated while it is
+/// being loaded: reading a mapped page past the new end of the file raises
+/// SIGBUS, which kills the process.
+///
+/// # Arguments:
+/// * `path`: The program file
+/// * `encoding`: How the words are stored
+pub fn load_file(path: &str, encoding: Encoding) -> Result<Vec<u32>, LoadError> {
+    let mut file = File::open(path).map_err(|e| LoadError::io(path, e))?;
+    let len = file.metadata().map_err(|e| LoadError::io(path, e))?.len();
+    if len >= MMAP_THRESHOLD {
+        // SAFETY: the mapping lives only while the words are copied out of
+        // it. Another process writing the file meanwhile only garbles the
+        // words, as with a concurrent read, but truncating it makes the
+        // copy fault with SIGBUS instead of returning an error; programs
+        // are not expected to shrink while they are loaded
+        let map = unsafe { Mmap::map(&file) }.map_err(|e| LoadError::io(path, e))?;
+        return parse(&map, encoding, path);
+    }
+    let mut bytes = Vec::with_capacity(len as usize);
+    file.read_to_end(&mut bytes).map_err(|e| LoadError::io(path, e))?;
+    parse(&bytes, encoding, path)
+}
+
+/// Loads a program from a file, or from standard input if there is none
+///
+/// # Arguments:
+/// * `input`: The program file
+/// * `encoding`: How the words are stored
+pub fn load(input: Option<&str>, encoding: Encoding) -> Result<Vec<u32>, LoadError> {
+    match input {
+        Some(path) => load_file(path, encoding),
+        None => {
+            let mut bytes = Vec::new();
+            io::stdin().lock().read_to_end(&mut bytes).map_err(|e| LoadError::io("<stdin>", e))?;
+            parse(&bytes, encoding, "<stdin>")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::loader::{load_file, parse, Encoding, LoadError, MMAP_THRESHOLD};
+
+    #[test]
+    fn parses_encodings() {
+        let bytes = [0x70, 0, 0, 0, 0xd2, 0, 0, 0x48];
+        assert_eq!(parse(&bytes, Encoding::BigEndian, "p"), Ok(vec![0x7000_0000, 0xd200_0048]));
+        assert_eq!(parse(&bytes, Encoding::LittleEndian, "p"), Ok(vec![0x70, 0x4800_00d2]));
+        let text = b"0xd2000048 a0000001  # output r1\n\n70000000\n";
+        assert_eq!(parse(text, Encoding::Hex, "p"), Ok(vec![0xd200_0048, 0xa000_0001, 0x7000_0000]));
+    }
+    #[test]
+    fn rejects_bad_programs() {
+        assert_eq!(parse(&[0, 0, 0, 0, 7], Encoding::BigEndian, "p"),
+            Err(LoadError::PartialWord { path: "p".to_string(), len: 5 }));
+        assert_eq!(parse(b"70000000\nhalt\n", Encoding::Hex, "p"),
+            Err(LoadError::InvalidHex { path: "p".to_string(), line: 2, token: "halt".to_string() }));
+        assert_eq!(parse(b"+7", Encoding::Hex, "p").unwrap_err().to_string(), "p:1: '+7' is not a hex word");
+        assert_eq!(load_file("/nonexistent/program.um", Encoding::BigEndian),
+            Err(LoadError::NotFound { path: "/nonexistent/program.um".to_string() }));
+    }
+    #[test]
+    fn maps_large_files() {
+        let path = std::env::temp_dir().join(format!("loader-{}.um", std::process::id()));
+        let words: Vec<u32> = (0..MMAP_THRESHOLD as u32 / 4 + 1).collect();
+        fs::write(&path, words.iter().flat_map(|word| word.to_be_bytes()).collect::<Vec<u8>>()).unwrap();
+        let loaded = load_file(path.to_str().unwrap(), Encoding::BigEndian);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, Ok(words));
+    }
+}
@@ -0,0 +1,320 @@
+use std::io;
+use std::rc::Rc;
+use crate::memory::{Segment, UmState};
+use crate::fault::{HaltReason, UmFault};
+use crate::io::UmIo;
+use crate::limits::Limit;
+
+/// Returns the program counter and word of the instruction being executed.
+/// The program counter has already been advanced past it.
+fn site(um: &UmState) -> (usize, u32) {
+    let pc = um.program_counter - 1;
+    (pc, um.memory[0][pc])
+}
+
+/// Returns the mapped segment `id`, or an UnmappedSegment fault
+/// In checked mode, segments that have been unmapped are rejected too
+fn segment(um: &UmState, id: u32) -> Result<&Segment, UmFault> {
+    let mapped = !um.checked || um.mapped.get(id as usize).copied().unwrap_or(false);
+    match um.memory.get(id as usize) {
+        Some(segment) if mapped => Ok(segment),
+        _ => {
+            let (pc, instruction) = site(um);
+            Err(UmFault::UnmappedSegment { pc, instruction, segment: id })
+        }
+    }
+}
+
+/// Converts a device error into an Io fault
+fn io_fault(um: &UmState, error: io::Error) -> UmFault {
+    let (pc, instruction) = site(um);
+    UmFault::Io { pc, instruction, kind: error.kind() }
+}
+
+/// Checks that `offset` lies within segment `id`
+fn check_bounds(um: &UmState, id: u32, offset: u32) -> Result<(), UmFault> {
+    if offset as usize >= segment(um, id)?.len() {
+        let (pc, instruction) = site(um);
+        return Err(UmFault::SegmentOutOfBounds { pc, instruction, segment: id, offset });
+    }
+    Ok(())
+}
+
+/// Returns a LimitExceeded fault if replacing `old` mapped words with
+/// `new` ones would exceed the mapped word limit
+fn check_words(um: &UmState, old: usize, new: usize) -> Result<(), UmFault> {
+    match um.limits.mapped_words {
+        Some(max) if um.mapped_words - old + new > max => {
+            let (pc, instruction) = site(um);
+            Err(UmFault::LimitExceeded { pc, instruction, limit: Limit::MappedWords(max) })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Returns a LimitExceeded fault if one more mapped segment would exceed
+/// the live segment limit
+fn check_segments(um: &UmState) -> Result<(), UmFault> {
+    match um.limits.live_segments {
+        Some(max) if um.live_segments >= max => {
+            let (pc, instruction) = site(um);
+            Err(UmFault::LimitExceeded { pc, instruction, limit: Limit::LiveSegments(max) })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Performs a Conditional Move if $r[C] != 0
+/// Modifies the a register in the VM object
+/// 
+/// # Arguments:
+/// * um: A Virtual Machine object
+/// * a: The a register
+/// * b: The b register
+/// * c: The c register
+pub fn cmov(um: &mut UmState, a: usize, b: usize, c: usize){
+    if um.registers[c] != 0{
+        um.registers[a] = um.registers[b];
+    }
+}
+
+/// Performs a Segmented Load
+/// Modifies the a register in the UmState object
+/// 
+/// # Arguments:
+/// * um: A Virtual Machine object
+/// * a: The a register
+/// * b: The b register
+/// * c: The c register
+pub fn sload(um: &mut UmState, a: usize, b: usize, c: usize) -> Result<(), UmFault>{
+    check_bounds(um, um.registers[b], um.registers[c])?;
+    um.registers[a] = um.memory[um.registers[b] as usize][um.registers[c] as usize];
+    Ok(())
+}
+
+/// Performs a Segmented Store
+/// Modifies the memory address at the $m[$r[a]][$r[b]] index, first
+/// copying the segment if it is shared with $m[0] or the segment $m[0] was
+/// loaded from
+/// 
+/// # Arguments:
+/// * um: A Virtual Machine object
+/// * a: The a register
+/// * b: The b register
+/// * c: The c register
+pub fn store(um: &mut UmState, a: usize, b: usize, c: usize) -> Result<(), UmFault>{
+    check_bounds(um, um.registers[a], um.registers[b])?;
+    let segment = Rc::make_mut(&mut um.memory[um.registers[a] as usize]);
+    segment[um.registers[b] as usize] = um.registers[c];
+    if um.registers[a] == 0{
+        um.code.store(um.registers[b] as usize, um.registers[c]);
+    }
+    Ok(())
+}
+
+/// Performs an Addition operation
+/// Modifies the a register in the UmState object
+/// 
+/// # Arguments:
+/// * um: A Virtual Machine object
+/// * a: The a register
+/// * b: The b register
+/// * c: The c register
+pub fn add(um: &mut UmState, a: usize, b: usize, c: usize){
+    um.registers[a] = um.registers[b].wrapping_add(um.registers[c]);
+}
+
+/// Performs a Multiplication operation
+/// Modifies the a register in the UmState object
+/// 
+/// # Arguments:
+/// * um: A Virtual Machine object
+/// * a: The a register
+/// * b: The b register
+/// * c: The c register
+pub fn mult(um: &mut UmState, a: usize, b: usize, c: usize){
+    um.registers[a] = um.registers[b].wrapping_mul(um.registers[c]);
+}
+
+/// Performs integer division
+/// Modifies the a register in the UmState object
+/// 
+/// # Arguments:
+/// * um: A Virtual Machine object
+/// * a: The a register
+/// * b: The b register
+/// * c: The c register
+pub fn div(um: &mut UmState, a: usize, b: usize, c: usize) -> Result<(), UmFault>{
+    if um.registers[c] == 0{
+        let (pc, instruction) = site(um);
+        return Err(UmFault::DivideByZero { pc, instruction });
+    }
+    um.registers[a] = um.registers[b] / um.registers[c];
+    Ok(())
+}
+
+/// Performs Bitwise NAND
+/// Modifies the a register in the UmState object
+/// 
+/// # Arguments:
+/// * um: A Virtual Machine object
+/// * a: The a register
+/// * b: The b register
+/// * c: The c register
+pub fn nand(um: &mut UmState, a: usize, b: usize, c: usize){
+    um.registers[a] = !(um.registers[b] & um.registers[c]);
+}
+
+/// Ends the program
+/// Flushes the I/O device and returns the reason the machine stopped
+/// 
+/// # Arguments:
+/// * um: A Virtual Machine object
+/// * io: The I/O device
+pub fn halt<I: UmIo + ?Sized>(um: &UmState, io: &mut I) -> Result<HaltReason, UmFault>{
+    io.flush().map_err(|e| io_fault(um, e))?;
+    Ok(HaltReason::Halted)
+}
+
+/// Maps a segment
+/// The new segment is mapped as $m[$r[b]]
+/// Unmapped IDs are reused before new ones are handed out
+/// Faults if the new segment would exceed the mapped word or live
+/// segment limit
+/// 
+/// # Arguments:
+/// * um: A Virtual Machine object
+/// * b: The b register
+/// * c: The c register
+pub fn map_seg(um: &mut UmState, b: usize, c: usize) -> Result<(), UmFault>{
+    let length = um.registers[c] as usize;
+    let id = match um.unmap_index_values.last(){
+        Some(&id) => id,
+        None if um.memory.len() > u32::MAX as usize => {
+            let (pc, instruction) = site(um);
+            return Err(UmFault::SegmentIdsExhausted { pc, instruction });
+        }
+        None => um.memory.len(),
+    };
+    let old = um.memory.get(id).map_or(0, |segment| segment.len());
+    let newly_mapped = !um.mapped.get(id).copied().unwrap_or(false);
+    check_words(um, old, length)?;
+    if newly_mapped{
+        check_segments(um)?;
+        um.live_segments += 1;
+    }
+    um.mapped_words = um.mapped_words - old + length;
+
+    let segment = Rc::new(vec![0_u32; length]);
+    if id < um.memory.len(){
+        um.unmap_index_values.pop();
+        um.memory[id] = segment;
+        um.mapped[id] = true;
+    }else {
+        um.memory.push(segment); // Removed the .clone() call
+        um.mapped.push(true);
+    }
+    um.registers[b] = id as u32;
+    Ok(())
+}
+
+/// Unmaps a segment
+/// The segment $m[$r[c]] is unmapped and its words are freed
+/// In checked mode, unmapping a segment twice is a DoubleUnmap fault;
+/// otherwise it does nothing, so the ID is not freed a second time
+/// 
+/// # Arguments:
+/// * um: A Virtual Machine object
+/// * c: The c register
+pub fn unmap_seg(um: &mut UmState, c: usize) -> Result<(), UmFault>{
+    let id = um.registers[c];
+    if id == 0{
+        let (pc, instruction) = site(um);
+        return Err(UmFault::UnmappedSegment { pc, instruction, segment: id });
+    }
+    if um.checked && um.mapped.get(id as usize) == Some(&false){
+        let (pc, instruction) = site(um);
+        return Err(UmFault::DoubleUnmap { pc, instruction, segment: id });
+    }
+    um.mapped_words -= segment(um, id)?.len();
+    if !um.mapped[id as usize]{
+        return Ok(());
+    }
+    um.live_segments -= 1;
+    um.memory[id as usize] = Rc::default();
+    um.mapped[id as usize] = false;
+    um.unmap_index_values.push(id as usize);
+    Ok(())
+}
+
+/// Outputs a specified value
+/// Only valid values to output between 0 and 255
+/// 
+/// # Arguments:
+/// * um: A Virtual Machine object
+/// * c: The c register
+/// * io: The I/O device
+pub fn output<I: UmIo + ?Sized>(um: &mut UmState, c: usize, io: &mut I) -> Result<(), UmFault>{
+    let value = u8::try_from(um.registers[c]).map_err(|_| {
+        let (pc, instruction) = site(um);
+        UmFault::OutputOutOfRange { pc, instruction, value: um.registers[c] }
+    })?;
+    io.output(value).map_err(|e| io_fault(um, e))
+}
+
+/// Reads an input from the I/O device, flushing any pending output first
+/// When the input arrives, $r[c] is loaded with the input, or u32::MAX
+/// at the end of input
+/// 
+/// # Arguments:
+/// * um: A Virtual Machine object
+/// * c: The c register
+/// * io: The I/O device
+pub fn input<I: UmIo + ?Sized>(um: &mut UmState, c: usize, io: &mut I) -> Result<(), UmFault>{
+    io.flush().map_err(|e| io_fault(um, e))?;
+    um.registers[c] = match io.input().map_err(|e| io_fault(um, e))? {
+        Some(byte) =>{
+            byte as u32
+        },
+        None => {
+            u32::MAX
+        }
+    };
+    Ok(())
+}
+
+/// Performs the load program
+/// Segment $m[$r[b]] is duplicated, and the duplicate replaces $m[0]
+/// The two share their words until one of them is stored to, so this
+/// takes constant time
+/// 
+/// # Arguments:
+/// * um: A Virtual Machine object
+/// * b: The b register
+/// * c: The c register
+pub fn load_program(um: &mut UmState, b: usize, c: usize) -> Result<(), UmFault>{
+    let target = um.registers[c];
+    if target as usize >= segment(um, um.registers[b])?.len(){
+        let (pc, instruction) = site(um);
+        return Err(UmFault::LoadProgramPcOutOfRange { pc, instruction, target });
+    }
+    if um.registers[b] != 0{
+        let program = Rc::clone(&um.memory[um.registers[b] as usize]);
+        check_words(um, um.memory[0].len(), program.len())?;
+        um.mapped_words = um.mapped_words - um.memory[0].len() + program.len();
+        um.code.load(&um.memory[0], &program);
+        um.memory[0] = program;
+    }
+    um.program_counter = target as usize;
+    Ok(())
+}
+
+/// Loads a value
+/// 
+/// # Arguments:
+/// * um: A Virtual Machine object
+/// * rl: The a register
+/// * vl: The value
+pub fn load_value(um: &mut UmState, rl: usize, vl: u32){
+    um.registers[rl] = vl;
+}
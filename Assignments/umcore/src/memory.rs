@@ -0,0 +1,197 @@
+//! Invariants:
+//! 
+//! Invariant: The instruction set of the machine is consistent and does 
+//! not change regardless of the specific program being executed.
+//! 
+//! Invariant: The semantics of the instructions are consistent and do not 
+//! change. For example, an “add” instruction will always perform an 
+//! addition operation.
+//! 
+//! Invariant: The state of the machine (e.g., the values in registers or 
+//! memory) after executing an instruction sequence starting from a 
+//! certain state is an invariant. It does not depend on the specific 
+//! path taken to reach that state, only on the initial state and the 
+//! sequence of instructions.
+
+use std::rc::Rc;
+use crate::decode::CodeCache;
+use crate::fault::{HaltReason, UmFault};
+use crate::io::UmIo;
+use crate::limits::Limits;
+use crate::um::Um;
+
+/// A memory segment. Segments are shared between $m[0] and the segment it
+/// was loaded from until either is stored to, so Load Program never copies.
+pub type Segment = Rc<Vec<u32>>;
+
+pub struct UmState{
+    pub registers: Vec<u32>,
+    pub memory: Vec<Segment>,
+    pub unmap_index_values: Vec<usize>,
+    pub program_counter: usize,
+    /// Whether each segment ID in `memory` is currently mapped
+    pub mapped: Vec<bool>,
+    /// Fault on any access to an unmapped segment and on double unmaps
+    pub checked: bool,
+    /// The decoded instructions of $m[0]
+    pub code: CodeCache,
+    /// Resource limits enforced when segments are mapped and loaded
+    pub limits: Limits,
+    /// Words in mapped segments, including $m[0]
+    pub mapped_words: usize,
+    /// Number of mapped segments, including $m[0]
+    pub live_segments: usize
+}
+
+impl UmState{
+    /// Creates a machine state with `program` as $m[0], all registers
+    /// zeroed and the program counter at the start of $m[0]
+    ///
+    /// Arguments:
+    /// * `program`: A vector of instructions.
+    pub fn new(program: Vec<u32>) -> Self{
+        let code = CodeCache::new(&program);
+        let mapped_words = program.len();
+        UmState{
+            registers: vec![0; 8],
+            memory: vec![Rc::new(program)],
+            unmap_index_values: vec![],
+            program_counter: 0,
+            mapped: vec![true],
+            checked: false,
+            code,
+            limits: Limits::default(),
+            mapped_words,
+            live_segments: 1
+        }
+    }
+
+    /// Decodes $m[0] again. Needed only after $m[0] was changed directly
+    /// rather than by executing instructions.
+    pub fn reload_code(&mut self){
+        self.code = CodeCache::new(&self.memory[0]);
+    }
+}
+
+type Umi = u32;
+pub struct Field { 
+    width: u32,
+    lsb: u32,
+}
+pub static RA: Field = Field {width: 3, lsb: 6}; 
+pub static RB: Field = Field {width: 3, lsb: 3}; 
+pub static RC: Field = Field {width: 3, lsb: 0}; 
+pub static RL: Field = Field {width: 3, lsb: 25}; 
+pub static VL: Field = Field {width: 25, lsb: 0}; 
+pub static OP: Field = Field {width: 4, lsb: 28};
+
+fn mask(bits: u32) -> u32 { (1 << bits) - 1 }
+
+pub fn get(field: &Field, instruction: Umi) -> u32 { 
+    (instruction >> field.lsb) & mask(field.width)
+}
+
+/// Function to perform the desired instructions on standard input and
+/// output.
+/// Returns why the machine halted, or the fault that stopped it.
+///
+/// Arguments:
+/// * `instructions`: A vector of instructions.
+pub fn instructs(instructions: Vec<u32>) -> Result<HaltReason, UmFault>{
+    Um::new(instructions).run()
+}
+
+/// Function to perform the desired instructions on the given I/O device.
+/// Pending output is flushed when the machine halts or faults.
+/// Returns why the machine halted, or the fault that stopped it.
+///
+/// Arguments:
+/// * `instructions`: A vector of instructions.
+/// * `io`: The device used by the Input and Output instructions.
+pub fn instructs_with_io<I: UmIo + ?Sized>(instructions: Vec<u32>, io: &mut I) -> Result<HaltReason, UmFault>{
+    Um::with_io(instructions, io).run()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::{instructs, instructs_with_io};
+    use crate::fault::{HaltReason, UmFault};
+    use crate::io::StreamIo;
+
+    fn inst(opcode: u32, a: u32, b: u32, c: u32) -> u32 {
+        (opcode << 28) | (a << 6) | (b << 3) | c
+    }
+    fn load_value(rl: u32, vl: u32) -> u32 {
+        (13 << 28) | (rl << 25) | vl
+    }
+
+    #[test]
+    fn halt_returns() {
+        assert_eq!(instructs(vec![inst(7, 0, 0, 0)]), Ok(HaltReason::Halted));
+    }
+    #[test]
+    fn divide_by_zero() {
+        let program = vec![load_value(1, 6), inst(5, 2, 1, 3), inst(7, 0, 0, 0)];
+        assert_eq!(instructs(program.clone()),
+            Err(UmFault::DivideByZero { pc: 1, instruction: program[1] }));
+    }
+    #[test]
+    fn unmap_zero() {
+        let program = vec![inst(9, 0, 0, 0)];
+        assert_eq!(instructs(program.clone()),
+            Err(UmFault::UnmappedSegment { pc: 0, instruction: program[0], segment: 0 }));
+    }
+    #[test]
+    fn load_unmapped_segment() {
+        let program = vec![load_value(1, 5), inst(1, 0, 1, 2)];
+        assert_eq!(instructs(program.clone()),
+            Err(UmFault::UnmappedSegment { pc: 1, instruction: program[1], segment: 5 }));
+    }
+    #[test]
+    fn store_out_of_bounds() {
+        let program = vec![load_value(1, 2), inst(8, 0, 2, 1), load_value(3, 2), inst(2, 2, 3, 0)];
+        assert_eq!(instructs(program.clone()),
+            Err(UmFault::SegmentOutOfBounds { pc: 3, instruction: program[3], segment: 1, offset: 2 }));
+    }
+    #[test]
+    fn invalid_opcode() {
+        let program = vec![inst(14, 0, 0, 0)];
+        assert_eq!(instructs(program.clone()),
+            Err(UmFault::InvalidOpcode { pc: 0, instruction: program[0], opcode: 14 }));
+    }
+    #[test]
+    fn output_out_of_range() {
+        let program = vec![load_value(1, 256), inst(10, 0, 0, 1)];
+        assert_eq!(instructs(program.clone()),
+            Err(UmFault::OutputOutOfRange { pc: 1, instruction: program[1], value: 256 }));
+    }
+    #[test]
+    fn load_program_out_of_range() {
+        let program = vec![load_value(1, 9), inst(12, 0, 0, 1)];
+        assert_eq!(instructs(program.clone()),
+            Err(UmFault::LoadProgramPcOutOfRange { pc: 1, instruction: program[1], target: 9 }));
+    }
+    #[test]
+    fn run_off_the_end() {
+        let program = vec![load_value(1, 1)];
+        assert_eq!(instructs(program),
+            Err(UmFault::SegmentOutOfBounds { pc: 1, instruction: 0, segment: 0, offset: 1 }));
+    }
+    #[test]
+    fn echo_through_buffers() {
+        let program = vec![inst(11, 0, 0, 1), inst(10, 0, 0, 1), inst(11, 0, 0, 1),
+            inst(10, 0, 0, 1), inst(11, 0, 0, 2), inst(7, 0, 0, 0)];
+        let mut io = StreamIo::new(&b"hi"[..], Vec::new());
+        assert_eq!(instructs_with_io(program, &mut io), Ok(HaltReason::Halted));
+        let (_, output) = io.into_parts().unwrap();
+        assert_eq!(output, b"hi");
+    }
+    #[test]
+    fn input_at_end_is_all_ones() {
+        let program = vec![inst(11, 0, 0, 1), load_value(2, 1), inst(3, 1, 1, 2),
+            inst(10, 0, 0, 1), inst(7, 0, 0, 0)];
+        let mut io = StreamIo::new(&b""[..], Vec::new());
+        assert_eq!(instructs_with_io(program, &mut io), Ok(HaltReason::Halted));
+        assert_eq!(io.into_parts().unwrap().1, vec![0]);
+    }
+}
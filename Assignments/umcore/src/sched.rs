@@ -0,0 +1,254 @@
+//! Several machines in one process, taking turns and talking to each other
+//! through byte channels.
+//!
+//! Every machine reads its Input from one channel and writes its Output to
+//! another. By default both are its own: the input channel holds whatever
+//! `Scheduler::input` put there and is closed, so the machine sees the end
+//! of input once it is drained, and the output channel collects bytes for
+//! `Scheduler::take_output`. `Scheduler::connect` makes one machine read
+//! what another writes instead. A channel stays open while the machine
+//! writing to it is running and is closed when that machine halts or
+//! faults.
+//!
+//! An Input from an empty open channel blocks: the device reports
+//! `io::ErrorKind::WouldBlock`, which leaves the machine as it was, and the
+//! machine is tried again on the next round. If a whole round passes with
+//! no machine executing anything, every remaining machine is blocked and
+//! `Scheduler::run` reports a deadlock.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::rc::Rc;
+use crate::fault::{HaltReason, UmFault};
+use crate::io::UmIo;
+use crate::memory::UmState;
+use crate::um::{Um, UmEvent, UmStats};
+
+/// Index of a machine in its scheduler
+pub type MachineId = usize;
+
+/// Bytes on their way from one machine to another
+#[derive(Debug, Default)]
+struct Channel {
+    bytes: VecDeque<u8>,
+    /// No more bytes will be written
+    closed: bool,
+}
+
+/// The I/O device of a scheduled machine
+pub struct ChannelIo {
+    input: Rc<RefCell<Channel>>,
+    output: Rc<RefCell<Channel>>,
+}
+
+impl UmIo for ChannelIo {
+    fn input(&mut self) -> io::Result<Option<u8>> {
+        let mut channel = self.input.borrow_mut();
+        match channel.bytes.pop_front() {
+            Some(byte) => Ok(Some(byte)),
+            None if channel.closed => Ok(None),
+            None => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+
+    fn output(&mut self, byte: u8) -> io::Result<()> {
+        self.output.borrow_mut().bytes.push_back(byte);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Where a scheduled machine stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// Ready to execute
+    Runnable,
+    /// Waiting for Input on an empty channel
+    Blocked,
+    /// Stopped by a Halt instruction
+    Halted(HaltReason),
+    /// Stopped by a fault, including an exceeded limit; the other
+    /// machines keep running
+    Faulted(UmFault),
+}
+
+impl Status {
+    /// Whether the machine will never execute again
+    pub fn is_finished(&self) -> bool {
+        matches!(self, Status::Halted(_) | Status::Faulted(_))
+    }
+}
+
+/// The machines left blocked on Input with nothing left to write to them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deadlock {
+    pub blocked: Vec<MachineId>,
+}
+
+impl fmt::Display for Deadlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ids: Vec<String> = self.blocked.iter().map(|id| id.to_string()).collect();
+        write!(f, "deadlock: machines {} are all blocked on Input", ids.join(", "))
+    }
+}
+
+impl std::error::Error for Deadlock {}
+
+/// Runs machines round-robin, each for up to a quantum of instructions per
+/// turn.
+pub struct Scheduler {
+    machines: Vec<(Um<ChannelIo>, Status)>,
+    quantum: u64,
+}
+
+impl Scheduler {
+    /// Creates a scheduler with no machines
+    ///
+    /// # Arguments:
+    /// * `quantum`: The most instructions a machine executes per turn
+    pub fn new(quantum: u64) -> Self {
+        Scheduler { machines: Vec::new(), quantum: quantum.max(1) }
+    }
+
+    /// Adds a machine with its own input and output channels, returning
+    /// its ID
+    ///
+    /// # Arguments:
+    /// * `state`: The state to start the machine from
+    pub fn add(&mut self, state: UmState) -> MachineId {
+        let input = Rc::new(RefCell::new(Channel { bytes: VecDeque::new(), closed: true }));
+        let io = ChannelIo { input, output: Rc::default() };
+        self.machines.push((Um::from_state(state, UmStats::default(), io), Status::Runnable));
+        self.machines.len() - 1
+    }
+
+    /// Makes machine `to` read what machine `from` writes, in place of its
+    /// own input channel
+    ///
+    /// # Arguments:
+    /// * `from`: The writing machine
+    /// * `to`: The reading machine
+    pub fn connect(&mut self, from: MachineId, to: MachineId) {
+        let channel = Rc::clone(&self.machines[from].0.io().output);
+        self.machines[to].0.io().input = channel;
+    }
+
+    /// Appends bytes to the input channel of machine `id`
+    ///
+    /// # Arguments:
+    /// * `id`: The machine
+    /// * `bytes`: The bytes to append
+    pub fn input(&mut self, id: MachineId, bytes: &[u8]) {
+        self.machines[id].0.io().input.borrow_mut().bytes.extend(bytes);
+    }
+
+    /// Removes and returns the bytes waiting in the output channel of
+    /// machine `id`
+    ///
+    /// # Arguments:
+    /// * `id`: The machine
+    pub fn take_output(&mut self, id: MachineId) -> Vec<u8> {
+        self.machines[id].0.io().output.borrow_mut().bytes.drain(..).collect()
+    }
+
+    /// Where machine `id` stands
+    pub fn status(&self, id: MachineId) -> Status {
+        self.machines[id].1
+    }
+
+    /// Machine `id` itself
+    pub fn machine(&mut self, id: MachineId) -> &mut Um<ChannelIo> {
+        &mut self.machines[id].0
+    }
+
+    /// Gives every unfinished machine one turn, returning whether any of
+    /// them executed an instruction or finished
+    pub fn round(&mut self) -> bool {
+        let mut progressed = false;
+        for (um, status) in &mut self.machines {
+            if status.is_finished() {
+                continue;
+            }
+            let executed = um.stats().executed;
+            let result = um.run_for(self.quantum);
+            *status = match result {
+                Ok(UmEvent::Running) => Status::Runnable,
+                Ok(UmEvent::Halted(reason)) => Status::Halted(reason),
+                Err(UmFault::Io { kind: io::ErrorKind::WouldBlock, .. }) => Status::Blocked,
+                Err(fault) => Status::Faulted(fault),
+            };
+            if status.is_finished() {
+                um.io().output.borrow_mut().closed = true;
+            }
+            progressed |= um.stats().executed > executed || status.is_finished();
+        }
+        progressed
+    }
+
+    /// Runs the machines until every one has halted or faulted
+    pub fn run(&mut self) -> Result<(), Deadlock> {
+        loop {
+            if self.machines.iter().all(|(_, status)| status.is_finished()) {
+                return Ok(());
+            }
+            if !self.round() {
+                let blocked = (0..self.machines.len()).filter(|&id| self.status(id) == Status::Blocked).collect();
+                return Err(Deadlock { blocked });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fault::HaltReason;
+    use crate::memory::UmState;
+    use crate::sched::{Deadlock, Scheduler, Status};
+
+    fn inst(opcode: u32, a: u32, b: u32, c: u32) -> u32 {
+        (opcode << 28) | (a << 6) | (b << 3) | c
+    }
+
+    /// Copies input to output, adding 1 to each byte, until the end of
+    /// input
+    fn increment() -> UmState {
+        let value = |r: u32, value: u32| (13 << 28) | (r << 25) | value;
+        UmState::new(vec![value(2, 1), value(5, 8), value(6, 3),
+            inst(11, 0, 0, 1), inst(3, 3, 1, 2), value(4, 10), inst(0, 4, 5, 3), inst(12, 0, 0, 4),
+            inst(10, 0, 0, 3), inst(12, 0, 0, 6), inst(7, 0, 0, 0)])
+    }
+
+    #[test]
+    fn pipes_machines_together() {
+        let mut scheduler = Scheduler::new(3);
+        let first = scheduler.add(increment());
+        let second = scheduler.add(increment());
+        scheduler.connect(first, second);
+        scheduler.input(first, b"HAL");
+        scheduler.run().unwrap();
+        assert_eq!(scheduler.status(second), Status::Halted(HaltReason::Halted));
+        assert_eq!(scheduler.take_output(second), b"JCN");
+        assert!(scheduler.take_output(first).is_empty());
+    }
+    #[test]
+    fn detects_deadlock() {
+        let mut scheduler = Scheduler::new(100);
+        let first = scheduler.add(increment());
+        let second = scheduler.add(increment());
+        let third = scheduler.add(UmState::new(vec![inst(7, 0, 0, 0)]));
+        scheduler.connect(first, second);
+        scheduler.connect(second, first);
+        assert_eq!(scheduler.run(), Err(Deadlock { blocked: vec![first, second] }));
+        assert_eq!(scheduler.status(third), Status::Halted(HaltReason::Halted));
+
+        // Input from outside gets the cycle going
+        scheduler.input(first, b"a");
+        assert!(scheduler.round());
+        assert_eq!(scheduler.machine(second).stats().bytes_read, 1);
+    }
+}
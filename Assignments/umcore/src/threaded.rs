@@ -0,0 +1,220 @@
+//! Direct-threaded execution of $m[0].
+//!
+//! Each basic block is translated once into a vector of closures, one per
+//! instruction, with the register numbers baked in. A block ends before the
+//! first Halt, Load Program or invalid word, which `Um` executes through
+//! the interpreter so that control transfers and faults behave exactly as
+//! they do there.
+//!
+//! Blocks are tagged with the epoch they were compiled in, and so is every
+//! word they cover. A Store to a covered word of $m[0], or a Load Program
+//! that replaces $m[0], starts a new epoch, which invalidates every block
+//! at once. Stores to words that are not code cost nothing extra.
+
+use std::rc::Rc;
+use crate::decode::Instruction;
+use crate::fault::UmFault;
+use crate::io::UmIo;
+use crate::machine;
+use crate::memory::UmState;
+use crate::um::UmStats;
+
+/// What the engine has to do after an instruction
+pub(crate) enum Flow {
+    /// Carry on with the next instruction of the block
+    Next,
+    /// The word at this offset of $m[0] was stored to
+    Code(usize),
+}
+
+/// One translated instruction. Faults are boxed to keep the common result
+/// small.
+pub(crate) type Op<I> = Box<dyn Fn(&mut UmState, &mut I, &mut UmStats) -> Result<Flow, Box<UmFault>>>;
+
+/// The translated instructions of a basic block, without its final
+/// control transfer
+pub(crate) struct Block<I> {
+    pub(crate) ops: Vec<Op<I>>,
+    epoch: u64,
+}
+
+/// Blocks of $m[0] translated so far, by their first address
+pub(crate) struct Threaded<I> {
+    blocks: Vec<Option<Rc<Block<I>>>>,
+    /// Epoch in which each word of $m[0] was last covered by a block
+    covered: Vec<u64>,
+    epoch: u64,
+    /// Instruction count at which the wall-clock limit is next checked
+    pub(crate) next_clock_check: u64,
+}
+
+/// The register file as an array. Register numbers are masked with 7 when
+/// it is indexed, which lets the compiler drop the bounds checks.
+fn registers(um: &mut UmState) -> &mut [u32; 8] {
+    um.registers.as_mut_slice().try_into().expect("eight registers")
+}
+
+/// Translates the instruction at `pc`, or returns None for the
+/// instructions that end a block. Only instructions that can fault update
+/// the program counter, so that the fault reports the right address; the
+/// engine sets it once the block is done.
+fn translate<I: UmIo>(pc: usize, instruction: Instruction) -> Option<Op<I>> {
+    let next = pc + 1;
+    let op: Op<I> = match instruction {
+        Instruction::CMov { a, b, c } => {
+            let (a, b, c) = (a as usize, b as usize, c as usize);
+            Box::new(move |um, _, _| {
+                let r = registers(um);
+                if r[c & 7] != 0 {
+                    r[a & 7] = r[b & 7];
+                }
+                Ok(Flow::Next)
+            })
+        }
+        Instruction::Load { a, b, c } => {
+            let (a, b, c) = (a as usize, b as usize, c as usize);
+            Box::new(move |um, _, _| {
+                um.program_counter = next;
+                machine::sload(um, a, b, c)?;
+                Ok(Flow::Next)
+            })
+        }
+        Instruction::Store { a, b, c } => {
+            let (a, b, c) = (a as usize, b as usize, c as usize);
+            Box::new(move |um, _, _| {
+                um.program_counter = next;
+                machine::store(um, a, b, c)?;
+                Ok(if um.registers[a] == 0 { Flow::Code(um.registers[b] as usize) } else { Flow::Next })
+            })
+        }
+        Instruction::Add { a, b, c } => {
+            let (a, b, c) = (a as usize, b as usize, c as usize);
+            Box::new(move |um, _, _| {
+                let r = registers(um);
+                r[a & 7] = r[b & 7].wrapping_add(r[c & 7]);
+                Ok(Flow::Next)
+            })
+        }
+        Instruction::Mul { a, b, c } => {
+            let (a, b, c) = (a as usize, b as usize, c as usize);
+            Box::new(move |um, _, _| {
+                let r = registers(um);
+                r[a & 7] = r[b & 7].wrapping_mul(r[c & 7]);
+                Ok(Flow::Next)
+            })
+        }
+        Instruction::Div { a, b, c } => {
+            let (a, b, c) = (a as usize, b as usize, c as usize);
+            Box::new(move |um, _, _| {
+                um.program_counter = next;
+                machine::div(um, a, b, c)?;
+                Ok(Flow::Next)
+            })
+        }
+        Instruction::Nand { a, b, c } => {
+            let (a, b, c) = (a as usize, b as usize, c as usize);
+            Box::new(move |um, _, _| {
+                let r = registers(um);
+                r[a & 7] = !(r[b & 7] & r[c & 7]);
+                Ok(Flow::Next)
+            })
+        }
+        Instruction::MapSegment { b, c } => {
+            let (b, c) = (b as usize, c as usize);
+            Box::new(move |um, _, _| {
+                um.program_counter = next;
+                machine::map_seg(um, b, c)?;
+                Ok(Flow::Next)
+            })
+        }
+        Instruction::UnmapSegment { c } => {
+            let c = c as usize;
+            Box::new(move |um, _, _| {
+                um.program_counter = next;
+                machine::unmap_seg(um, c)?;
+                Ok(Flow::Next)
+            })
+        }
+        Instruction::Output { c } => {
+            let c = c as usize;
+            Box::new(move |um, io, stats| {
+                um.program_counter = next;
+                machine::output(um, c, io)?;
+                stats.bytes_written += 1;
+                Ok(Flow::Next)
+            })
+        }
+        Instruction::Input { c } => {
+            let c = c as usize;
+            Box::new(move |um, io, stats| {
+                um.program_counter = next;
+                machine::input(um, c, io)?;
+                if um.registers[c] == u32::MAX {
+                    stats.input_eof = true;
+                } else {
+                    stats.bytes_read += 1;
+                }
+                Ok(Flow::Next)
+            })
+        }
+        Instruction::LoadValue { a, value } => {
+            let a = a as usize;
+            Box::new(move |um, _, _| {
+                registers(um)[a & 7] = value;
+                Ok(Flow::Next)
+            })
+        }
+        Instruction::Halt | Instruction::LoadProgram { .. } | Instruction::Invalid { .. } => return None,
+    };
+    Some(op)
+}
+
+impl<I: UmIo> Threaded<I> {
+    /// Creates an empty cache for a $m[0] of `len` words
+    pub(crate) fn new(len: usize) -> Self {
+        Threaded { blocks: vec![None; len], covered: vec![0; len], epoch: 1, next_clock_check: 0 }
+    }
+
+    /// Discards every block, e.g. because $m[0] now has `len` words of
+    /// different code
+    pub(crate) fn invalidate(&mut self, len: usize) {
+        if len == self.blocks.len() {
+            self.epoch += 1;
+        } else {
+            *self = Threaded { next_clock_check: self.next_clock_check, ..Threaded::new(len) };
+        }
+    }
+
+    /// Records a store to `offset` in $m[0]. Returns whether it changed
+    /// translated code, which invalidates every block.
+    pub(crate) fn stored(&mut self, offset: usize) -> bool {
+        let code = self.covered.get(offset) == Some(&self.epoch);
+        if code {
+            self.epoch += 1;
+        }
+        code
+    }
+
+    /// The block starting at the program counter, translating it if needed.
+    /// A program counter past the end of $m[0] gets an empty block.
+    pub(crate) fn block(&mut self, um: &UmState) -> Rc<Block<I>> {
+        let start = um.program_counter;
+        if let Some(Some(block)) = self.blocks.get(start) {
+            if block.epoch == self.epoch {
+                return Rc::clone(block);
+            }
+        }
+        let mut ops = Vec::new();
+        let mut pc = start;
+        while let Some(op) = um.code.get(pc).and_then(|instruction| translate(pc, instruction)) {
+            ops.push(op);
+            self.covered[pc] = self.epoch;
+            pc += 1;
+        }
+        let block = Rc::new(Block { ops, epoch: self.epoch });
+        if let Some(slot) = self.blocks.get_mut(start) {
+            *slot = Some(Rc::clone(&block));
+        }
+        block
+    }
+}
@@ -0,0 +1,612 @@
+use std::str::FromStr;
+use std::time::Instant;
+use crate::fault::{HaltReason, UmFault};
+use crate::io::{StdIo, UmIo};
+use crate::limits::{Limit, Limits};
+use crate::machine;
+use crate::decode::Instruction;
+use crate::memory::UmState;
+use crate::threaded::{Flow, Threaded};
+
+/// What happened after the machine was asked to execute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UmEvent {
+    /// Instructions were executed and the machine can keep running.
+    Running,
+    /// The machine has halted; further steps do nothing.
+    Halted(HaltReason),
+}
+
+/// How a machine executes `run` and `run_for`. `step` always interprets.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Engine {
+    /// Decode and dispatch one instruction at a time
+    #[default]
+    Interpreter,
+    /// Run basic blocks translated into direct-threaded closures
+    Threaded,
+}
+
+impl FromStr for Engine {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "interpreter" => Ok(Engine::Interpreter),
+            "threaded" => Ok(Engine::Threaded),
+            _ => Err(format!("unknown engine '{}', expected interpreter or threaded", name)),
+        }
+    }
+}
+
+/// Running totals of what a machine has done. They are saved in
+/// snapshots so a restored machine continues counting where it left off.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UmStats {
+    /// Instructions executed, including the final Halt
+    pub executed: u64,
+    /// Bytes delivered to Input
+    pub bytes_read: u64,
+    /// Bytes written by Output
+    pub bytes_written: u64,
+    /// Whether Input has reported the end of input
+    pub input_eof: bool,
+}
+
+/// A Universal Machine that can be embedded and driven one instruction at
+/// a time.
+///
+/// When an instruction faults, the program counter is left pointing at it,
+/// so the state can be inspected exactly as the program saw it.
+pub struct Um<I: UmIo = StdIo> {
+    state: UmState,
+    io: I,
+    halted: Option<HaltReason>,
+    stats: UmStats,
+    /// When the wall-clock limit runs out
+    deadline: Option<Instant>,
+    /// Translated blocks, when the threaded engine is selected
+    threaded: Option<Threaded<I>>,
+}
+
+impl Um<StdIo> {
+    /// Creates a machine running `program` on standard input and output
+    ///
+    /// # Arguments:
+    /// * `program`: The words loaded into $m[0]
+    pub fn new(program: Vec<u32>) -> Self {
+        Um::with_io(program, StdIo::stdio())
+    }
+}
+
+impl<I: UmIo> Um<I> {
+    /// Creates a machine running `program` on the given I/O device
+    ///
+    /// # Arguments:
+    /// * `program`: The words loaded into $m[0]
+    /// * `io`: The device used by the Input and Output instructions
+    pub fn with_io(program: Vec<u32>, io: I) -> Self {
+        Um::from_state(UmState::new(program), UmStats::default(), io)
+    }
+
+    /// Creates a machine that resumes from an existing state, e.g. one
+    /// restored from a snapshot
+    ///
+    /// # Arguments:
+    /// * `state`: The registers, segments and program counter to resume
+    /// * `stats`: The totals to continue counting from
+    /// * `io`: The device used by the Input and Output instructions
+    pub fn from_state(state: UmState, stats: UmStats, io: I) -> Self {
+        Um { state, io, halted: None, stats, deadline: None, threaded: None }
+    }
+
+    /// What the machine has done so far
+    pub fn stats(&self) -> UmStats {
+        self.stats
+    }
+
+    /// Mutable access to the totals, e.g. to wind them back along with the
+    /// state
+    pub fn stats_mut(&mut self) -> &mut UmStats {
+        &mut self.stats
+    }
+
+    /// Lets a halted machine execute again, once its state has been wound
+    /// back to before the Halt
+    pub fn resume(&mut self) {
+        self.halted = None;
+    }
+
+    /// The registers, segments and program counter of the machine
+    pub fn state(&self) -> &UmState {
+        &self.state
+    }
+
+    /// Mutable access to the machine state, e.g. to patch memory. Call
+    /// `UmState::reload_code` after changing $m[0] this way.
+    pub fn state_mut(&mut self) -> &mut UmState {
+        &mut self.state
+    }
+
+    /// Turns checked mode on or off. In checked mode every access to an
+    /// unmapped segment faults, as does unmapping a segment twice.
+    ///
+    /// # Arguments:
+    /// * `checked`: Whether to check segment IDs
+    pub fn set_checked(&mut self, checked: bool) {
+        self.state.checked = checked;
+    }
+
+    /// Sets the resource limits of the machine. An instruction that would
+    /// exceed one faults with `UmFault::LimitExceeded`. The wall-clock limit
+    /// starts counting now.
+    ///
+    /// # Arguments:
+    /// * `limits`: The new limits
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.state.limits = limits;
+        self.deadline = limits.wall_clock.map(|limit| Instant::now() + limit);
+    }
+
+    /// Selects the engine used by `run` and `run_for`
+    ///
+    /// # Arguments:
+    /// * `engine`: The engine to use
+    pub fn set_engine(&mut self, engine: Engine) {
+        self.threaded = match engine {
+            Engine::Interpreter => None,
+            Engine::Threaded => Some(Threaded::new(self.state.memory[0].len())),
+        };
+    }
+
+    /// A LimitExceeded fault for the instruction at the program counter
+    fn limit_fault(&self, limit: Limit) -> UmFault {
+        let pc = self.state.program_counter;
+        let instruction = self.state.memory[0].get(pc).copied().unwrap_or(0);
+        UmFault::LimitExceeded { pc, instruction, limit }
+    }
+
+    /// Faults if the instruction limit has been reached or the wall-clock
+    /// limit has run out. The clock is only read every 4096 instructions.
+    #[inline]
+    fn check_limits(&self) -> Result<(), UmFault> {
+        if self.deadline.is_none() && self.state.limits.instructions.is_none() {
+            return Ok(());
+        }
+        let limits = &self.state.limits;
+        let limit = match (limits.instructions, self.deadline) {
+            (Some(max), _) if self.stats.executed >= max => Limit::Instructions(max),
+            (_, Some(deadline)) if self.stats.executed.is_multiple_of(4096) && Instant::now() >= deadline => {
+                Limit::WallClock(limits.wall_clock.unwrap_or_default())
+            }
+            _ => return Ok(()),
+        };
+        Err(self.limit_fault(limit))
+    }
+
+    /// The I/O device of the machine
+    pub fn io(&mut self) -> &mut I {
+        &mut self.io
+    }
+
+    /// Consumes the machine and returns its I/O device
+    pub fn into_io(self) -> I {
+        self.io
+    }
+
+    /// Executes one instruction
+    #[inline]
+    pub fn step(&mut self) -> Result<UmEvent, UmFault> {
+        if let Some(reason) = self.halted {
+            return Ok(UmEvent::Halted(reason));
+        }
+        let pc = self.state.program_counter;
+        match self.check_limits().and_then(|()| self.execute()) {
+            Ok(event) => {
+                self.stats.executed += 1;
+                if let UmEvent::Halted(reason) = event {
+                    self.halted = Some(reason);
+                }
+                Ok(event)
+            }
+            Err(fault) => {
+                self.state.program_counter = pc;
+                // The fault is more useful to the caller than a failed flush
+                let _ = self.io.flush();
+                Err(fault)
+            }
+        }
+    }
+
+    /// Executes the Input or Output at the program counter without its
+    /// device, for a caller that replays what it read or wrote before.
+    /// `effect` stands in for the device, setting the registers and totals
+    /// it would have; limits are checked and the instruction is counted as
+    /// by `step`.
+    ///
+    /// # Arguments:
+    /// * `effect`: What the instruction does to the state and totals
+    pub fn step_replayed(&mut self, effect: impl FnOnce(&mut UmState, &mut UmStats)) -> Result<UmEvent, UmFault> {
+        if let Some(reason) = self.halted {
+            return Ok(UmEvent::Halted(reason));
+        }
+        if let Err(fault) = self.check_limits() {
+            // The fault is more useful to the caller than a failed flush
+            let _ = self.io.flush();
+            return Err(fault);
+        }
+        effect(&mut self.state, &mut self.stats);
+        self.state.program_counter += 1;
+        self.stats.executed += 1;
+        Ok(UmEvent::Running)
+    }
+
+    /// Executes at most `budget` instructions, stopping early on halt
+    ///
+    /// # Arguments:
+    /// * `budget`: The largest number of instructions to execute
+    pub fn run_for(&mut self, budget: u64) -> Result<UmEvent, UmFault> {
+        if self.threaded.is_some() {
+            return self.run_threaded(budget);
+        }
+        for _ in 0..budget {
+            if let UmEvent::Halted(reason) = self.step()? {
+                return Ok(UmEvent::Halted(reason));
+            }
+        }
+        Ok(self.halted.map_or(UmEvent::Running, UmEvent::Halted))
+    }
+
+    /// Executes instructions until the machine halts
+    pub fn run(&mut self) -> Result<HaltReason, UmFault> {
+        if self.threaded.is_some() {
+            loop {
+                if let UmEvent::Halted(reason) = self.run_threaded(u64::MAX)? {
+                    return Ok(reason);
+                }
+            }
+        }
+        loop {
+            if let UmEvent::Halted(reason) = self.step()? {
+                return Ok(reason);
+            }
+        }
+    }
+
+    /// Executes at most `budget` instructions with the threaded engine.
+    /// Instructions are counted, limited and reported exactly as the
+    /// interpreter does; the wall clock is read every 4096 instructions.
+    #[inline(never)]
+    fn run_threaded(&mut self, budget: u64) -> Result<UmEvent, UmFault> {
+        let mut left = budget;
+        while left > 0 {
+            if let Some(reason) = self.halted {
+                return Ok(UmEvent::Halted(reason));
+            }
+            let mut allowed = left;
+            if let Some(max) = self.state.limits.instructions {
+                if self.stats.executed >= max {
+                    let _ = self.io.flush();
+                    return Err(self.limit_fault(Limit::Instructions(max)));
+                }
+                allowed = allowed.min(max - self.stats.executed);
+            }
+            let threaded = self.threaded.as_mut().expect("threaded engine selected");
+            if let Some(deadline) = self.deadline {
+                if self.stats.executed >= threaded.next_clock_check {
+                    threaded.next_clock_check = self.stats.executed + 4096;
+                    if Instant::now() >= deadline {
+                        let limit = Limit::WallClock(self.state.limits.wall_clock.unwrap_or_default());
+                        let _ = self.io.flush();
+                        return Err(self.limit_fault(limit));
+                    }
+                }
+            }
+
+            let block = threaded.block(&self.state);
+            let start = self.state.program_counter;
+            let count = block.ops.len().min(allowed.min(usize::MAX as u64) as usize);
+            let mut ran = 0;
+            let mut code_changed = false;
+            for op in &block.ops[..count] {
+                match op(&mut self.state, &mut self.io, &mut self.stats) {
+                    Ok(Flow::Next) => ran += 1,
+                    Ok(Flow::Code(offset)) => {
+                        ran += 1;
+                        if threaded.stored(offset) {
+                            code_changed = true;
+                            break;
+                        }
+                    }
+                    Err(fault) => {
+                        self.state.program_counter = start + ran;
+                        self.stats.executed += ran as u64;
+                        // The fault is more useful to the caller than a failed flush
+                        let _ = self.io.flush();
+                        return Err(*fault);
+                    }
+                }
+            }
+            self.state.program_counter = start + ran;
+            self.stats.executed += ran as u64;
+            left -= ran as u64;
+            if code_changed || ran < block.ops.len() || left == 0 {
+                continue;
+            }
+
+            // The instruction that ends the block
+            let event = self.step()?;
+            left -= 1;
+            if let UmEvent::Halted(reason) = event {
+                return Ok(UmEvent::Halted(reason));
+            }
+        }
+        Ok(self.halted.map_or(UmEvent::Running, UmEvent::Halted))
+    }
+
+    /// Fetches, decodes and executes the instruction at the program counter
+    fn execute(&mut self) -> Result<UmEvent, UmFault> {
+        let um = &mut self.state;
+        let io = &mut self.io;
+        let pc = um.program_counter;
+        let instruction = match um.code.get(pc){
+            Some(instruction) => instruction,
+            None => return Err(UmFault::SegmentOutOfBounds {
+                pc, instruction: 0, segment: 0, offset: pc as u32
+            }),
+        };
+        um.program_counter += 1;
+
+        match instruction{
+            Instruction::CMov { a, b, c } => machine::cmov(um, a as usize, b as usize, c as usize),
+            Instruction::Load { a, b, c } => machine::sload(um, a as usize, b as usize, c as usize)?,
+            Instruction::Store { a, b, c } => {
+                machine::store(um, a as usize, b as usize, c as usize)?;
+                if let Some(threaded) = &mut self.threaded {
+                    if um.registers[a as usize] == 0 {
+                        threaded.stored(um.registers[b as usize] as usize);
+                    }
+                }
+            }
+            Instruction::Add { a, b, c } => machine::add(um, a as usize, b as usize, c as usize),
+            Instruction::Mul { a, b, c } => machine::mult(um, a as usize, b as usize, c as usize),
+            Instruction::Div { a, b, c } => machine::div(um, a as usize, b as usize, c as usize)?,
+            Instruction::Nand { a, b, c } => machine::nand(um, a as usize, b as usize, c as usize),
+            Instruction::Halt => return machine::halt(um, io).map(UmEvent::Halted),
+            Instruction::MapSegment { b, c } => machine::map_seg(um, b as usize, c as usize)?,
+            Instruction::UnmapSegment { c } => machine::unmap_seg(um, c as usize)?,
+            Instruction::Output { c } => {
+                machine::output(um, c as usize, io)?;
+                self.stats.bytes_written += 1;
+            }
+            Instruction::Input { c } => {
+                machine::input(um, c as usize, io)?;
+                if um.registers[c as usize] == u32::MAX{
+                    self.stats.input_eof = true;
+                }else{
+                    self.stats.bytes_read += 1;
+                }
+            }
+            Instruction::LoadProgram { b, c } => {
+                machine::load_program(um, b as usize, c as usize)?;
+                if let Some(threaded) = &mut self.threaded {
+                    if um.registers[b as usize] != 0 {
+                        threaded.invalidate(um.memory[0].len());
+                    }
+                }
+            }
+            Instruction::LoadValue { a, value } => machine::load_value(um, a as usize, value),
+            Instruction::Invalid { opcode } => {
+                let instruction = um.memory[0][pc];
+                return Err(UmFault::InvalidOpcode { pc, instruction, opcode: opcode as u32 });
+            }
+        }
+        Ok(UmEvent::Running)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use std::time::Duration;
+    use crate::um::{Engine, Um, UmEvent};
+    use crate::fault::{HaltReason, UmFault};
+    use crate::io::StreamIo;
+    use crate::limits::{Limit, Limits};
+
+    fn inst(opcode: u32, a: u32, b: u32, c: u32) -> u32 {
+        (opcode << 28) | (a << 6) | (b << 3) | c
+    }
+
+    #[test]
+    fn step_until_halt() {
+        let io = StreamIo::new(&b""[..], Vec::new());
+        let mut um = Um::with_io(vec![inst(6, 1, 0, 0), inst(7, 0, 0, 0)], io);
+        assert_eq!(um.step(), Ok(UmEvent::Running));
+        assert_eq!(um.state().registers[1], u32::MAX);
+        assert_eq!(um.step(), Ok(UmEvent::Halted(HaltReason::Halted)));
+        assert_eq!(um.step(), Ok(UmEvent::Halted(HaltReason::Halted)));
+    }
+    #[test]
+    fn replayed_steps_are_limited() {
+        let io = StreamIo::new(&b""[..], Vec::new());
+        let mut um = Um::with_io(vec![inst(11, 0, 0, 1), inst(11, 0, 0, 1)], io);
+        um.set_limits(Limits { instructions: Some(1), ..Limits::default() });
+        assert_eq!(um.step_replayed(|state, stats| {
+            state.registers[1] = 65;
+            stats.bytes_read += 1;
+        }), Ok(UmEvent::Running));
+        assert_eq!((um.state().program_counter, um.state().registers[1]), (1, 65));
+        assert_eq!((um.stats().executed, um.stats().bytes_read), (1, 1));
+        assert_eq!(um.step_replayed(|_, _| panic!("the limit was reached")),
+            Err(UmFault::LimitExceeded { pc: 1, instruction: inst(11, 0, 0, 1), limit: Limit::Instructions(1) }));
+    }
+    #[test]
+    fn run_for_stops_on_budget() {
+        // An infinite loop: goto r0 in program m[r0]
+        let io = StreamIo::new(&b""[..], Vec::new());
+        let mut um = Um::with_io(vec![inst(12, 0, 0, 0)], io);
+        assert_eq!(um.run_for(1000), Ok(UmEvent::Running));
+        assert_eq!(um.state().program_counter, 0);
+    }
+    #[test]
+    fn fault_leaves_pc_on_instruction() {
+        let io = StreamIo::new(&b""[..], Vec::new());
+        let mut um = Um::with_io(vec![inst(6, 1, 0, 0), inst(5, 1, 1, 0)], io);
+        assert!(matches!(um.run(), Err(UmFault::DivideByZero { pc: 1, .. })));
+        assert_eq!(um.state().program_counter, 1);
+    }
+    #[test]
+    fn load_program_shares_until_store() {
+        // goto r4 in program m[r2], where $m[1] stores r5 into $m[0][0]
+        let io = StreamIo::new(&b""[..], Vec::new());
+        let mut um = Um::with_io(vec![inst(12, 0, 2, 4)], io);
+        let code = vec![inst(2, 0, 0, 5), inst(7, 0, 0, 0)];
+        um.state_mut().memory.push(Rc::new(code.clone()));
+        um.state_mut().mapped.push(true);
+        um.state_mut().registers[2] = 1;
+        um.state_mut().registers[5] = 99;
+        assert_eq!(um.step(), Ok(UmEvent::Running));
+        assert!(Rc::ptr_eq(&um.state().memory[0], &um.state().memory[1]));
+        assert_eq!(um.run(), Ok(HaltReason::Halted));
+        assert_eq!(um.state().memory[0][0], 99);
+        assert_eq!(*um.state().memory[1], code);
+    }
+    #[test]
+    fn store_to_program_is_executed() {
+        // m[r0][r1] := r2 overwrites the Invalid word at 1 with a Halt
+        let io = StreamIo::new(&b""[..], Vec::new());
+        let mut um = Um::with_io(vec![inst(2, 0, 1, 2), 0xf0000000], io);
+        um.state_mut().registers[1] = 1;
+        um.state_mut().registers[2] = inst(7, 0, 0, 0);
+        assert_eq!(um.run(), Ok(HaltReason::Halted));
+    }
+    #[test]
+    fn unmap_frees_and_reuses_ids() {
+        let program = vec![inst(8, 0, 1, 2), inst(8, 0, 3, 2), inst(9, 0, 0, 1),
+            inst(8, 0, 4, 2), inst(7, 0, 0, 0)];
+        let io = StreamIo::new(&b""[..], Vec::new());
+        let mut um = Um::with_io(program, io);
+        um.state_mut().registers[2] = 3;
+        assert_eq!(um.run_for(3), Ok(UmEvent::Running));
+        assert!(um.state().memory[1].is_empty());
+        assert!(!um.state().mapped[1]);
+        assert_eq!(um.state().mapped_words, 5 + 3);
+        assert_eq!(um.run(), Ok(HaltReason::Halted));
+        assert_eq!(um.state().mapped_words, 5 + 3 + 3);
+        assert_eq!(um.state().registers[4], 1);
+        assert_eq!(*um.state().memory[1], vec![0; 3]);
+    }
+    #[test]
+    fn instruction_limit() {
+        let io = StreamIo::new(&b""[..], Vec::new());
+        let mut um = Um::with_io(vec![inst(12, 0, 0, 0)], io);
+        um.set_limits(Limits { instructions: Some(100), ..Limits::default() });
+        assert_eq!(um.run(), Err(UmFault::LimitExceeded { pc: 0, instruction: inst(12, 0, 0, 0),
+            limit: Limit::Instructions(100) }));
+        assert_eq!(um.stats().executed, 100);
+    }
+    #[test]
+    fn wall_clock_limit() {
+        let io = StreamIo::new(&b""[..], Vec::new());
+        let mut um = Um::with_io(vec![inst(12, 0, 0, 0)], io);
+        let limit = Duration::from_millis(10);
+        um.set_limits(Limits { wall_clock: Some(limit), ..Limits::default() });
+        assert!(matches!(um.run(), Err(UmFault::LimitExceeded { limit: Limit::WallClock(l), .. }) if l == limit));
+    }
+    #[test]
+    fn live_segment_limit() {
+        // Map, unmap and map again fits in two segments; a third does not
+        let program = vec![inst(8, 0, 1, 0), inst(9, 0, 0, 1), inst(8, 0, 1, 0), inst(8, 0, 2, 0)];
+        let io = StreamIo::new(&b""[..], Vec::new());
+        let mut um = Um::with_io(program.clone(), io);
+        um.set_limits(Limits { live_segments: Some(2), ..Limits::default() });
+        assert_eq!(um.run(), Err(UmFault::LimitExceeded { pc: 3, instruction: program[3],
+            limit: Limit::LiveSegments(2) }));
+        assert_eq!(um.state().live_segments, 2);
+    }
+    #[test]
+    fn threaded_sees_stores_to_code() {
+        // m[r0][r1] := r2 replaces `r3 := 65` with `r3 := 66` just ahead
+        let program = vec![inst(2, 0, 1, 2), (13 << 28) | (3 << 25) | 65, inst(10, 0, 0, 3), inst(7, 0, 0, 0)];
+        let mut um = Um::with_io(program, StreamIo::new(&b""[..], Vec::new()));
+        um.set_engine(Engine::Threaded);
+        um.state_mut().registers[1] = 1;
+        um.state_mut().registers[2] = (13 << 28) | (3 << 25) | 66;
+        assert_eq!(um.run(), Ok(HaltReason::Halted));
+        assert_eq!(um.stats().executed, 4);
+        assert_eq!(um.into_io().into_parts().unwrap().1, b"B");
+    }
+    #[test]
+    fn threaded_sees_stores_made_by_step() {
+        // The block at 2 is translated with `output r1`; a stepped Store
+        // then rewrites it to `output r4` before the jump back to it
+        let program = vec![inst(13, 0, 0, 0), inst(13, 0, 0, 0), inst(10, 0, 0, 1), inst(2, 0, 3, 2),
+            inst(12, 0, 0, 5)];
+        let mut um = Um::with_io(program, StreamIo::new(&b""[..], Vec::new()));
+        um.set_engine(Engine::Threaded);
+        let registers = &mut um.state_mut().registers;
+        registers[1] = u32::from(b'A');
+        registers[2] = inst(10, 0, 0, 4);
+        registers[3] = 2;
+        registers[4] = u32::from(b'B');
+        registers[5] = 2;
+        um.state_mut().program_counter = 2;
+        assert_eq!(um.run_for(1), Ok(UmEvent::Running));
+        assert_eq!(um.step(), Ok(UmEvent::Running));
+        assert_eq!(um.run_for(2), Ok(UmEvent::Running));
+        assert_eq!(um.state().program_counter, 3);
+        assert_eq!(um.into_io().into_parts().unwrap().1, b"AB");
+    }
+    #[test]
+    fn threaded_counts_like_interpreter() {
+        // r1 := r1 + r2 in a loop, with a divide by zero once r1 wraps to 0
+        let program = vec![inst(3, 1, 1, 2), inst(5, 3, 2, 1), inst(12, 0, 0, 0)];
+        let mut engines = Vec::new();
+        for engine in [Engine::Interpreter, Engine::Threaded] {
+            let mut um = Um::with_io(program.clone(), StreamIo::new(&b""[..], Vec::new()));
+            um.set_engine(engine);
+            um.state_mut().registers[2] = 1 << 30;
+            let first = um.run_for(5);
+            let (pc, executed) = (um.state().program_counter, um.stats().executed);
+            engines.push((first, pc, executed, um.run(), um.stats().executed));
+        }
+        assert_eq!(engines[0], engines[1]);
+        assert_eq!(engines[1].1, 2);
+        let mut um = Um::with_io(program, StreamIo::new(&b""[..], Vec::new()));
+        um.set_engine(Engine::Threaded);
+        um.state_mut().registers[2] = 1 << 30;
+        um.set_limits(Limits { instructions: Some(7), ..Limits::default() });
+        assert!(matches!(um.run(), Err(UmFault::LimitExceeded { pc: 1, limit: Limit::Instructions(7), .. })));
+    }
+    #[test]
+    fn checked_double_unmap() {
+        let program = vec![inst(8, 0, 1, 2), inst(9, 0, 0, 1), inst(9, 0, 0, 1)];
+        let io = StreamIo::new(&b""[..], Vec::new());
+        let mut um = Um::with_io(program.clone(), io);
+        um.set_checked(true);
+        assert_eq!(um.run(),
+            Err(UmFault::DoubleUnmap { pc: 2, instruction: program[2], segment: 1 }));
+    }
+    #[test]
+    fn unchecked_double_unmap_frees_once() {
+        // Unmap r1 twice, then map two segments into r2 and r3
+        let program = vec![inst(8, 0, 1, 2), inst(9, 0, 0, 1), inst(9, 0, 0, 1), inst(8, 0, 2, 2),
+            inst(8, 0, 3, 2), inst(7, 0, 0, 0)];
+        let mut um = Um::with_io(program, StreamIo::new(&b""[..], Vec::new()));
+        assert_eq!(um.run(), Ok(HaltReason::Halted));
+        let state = um.state();
+        assert_eq!((state.registers[2], state.registers[3]), (1, 2));
+        assert_eq!((state.live_segments, state.unmap_index_values.len()), (3, 0));
+    }
+    #[test]
+    fn checked_use_after_unmap() {
+        let program = vec![inst(8, 0, 1, 2), inst(9, 0, 0, 1), inst(1, 3, 1, 0)];
+        let io = StreamIo::new(&b""[..], Vec::new());
+        let mut um = Um::with_io(program.clone(), io);
+        um.set_checked(true);
+        um.state_mut().registers[2] = 4;
+        assert_eq!(um.run(),
+            Err(UmFault::UnmappedSegment { pc: 2, instruction: program[2], segment: 1 }));
+    }
+}
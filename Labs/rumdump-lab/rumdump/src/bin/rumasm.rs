@@ -0,0 +1,51 @@
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::process;
+use rumdump::rumasm;
+
+const USAGE: &str = "usage: rumasm [-o OUT.um] [FILE.asm]";
+
+fn main() {
+    let mut input = None;
+    let mut output = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = args.next(),
+            _ if arg.starts_with('-') => {
+                eprintln!("{}", USAGE);
+                process::exit(2);
+            }
+            _ => input = Some(arg),
+        }
+    }
+
+    let mut source = String::new();
+    let read = match &input {
+        Some(filename) => fs::read_to_string(filename).map(|text| source = text),
+        None => io::stdin().read_to_string(&mut source).map(|_| ()),
+    };
+    let name = input.as_deref().unwrap_or("<stdin>");
+    if let Err(error) = read {
+        eprintln!("rumasm: {}: {}", name, error);
+        process::exit(1);
+    }
+
+    let words = match rumasm::assemble(&source) {
+        Ok(words) => words,
+        Err(error) => {
+            eprintln!("rumasm: {}: {}", name, error);
+            process::exit(1);
+        }
+    };
+    let bytes = rumasm::to_bytes(&words);
+    let written = match &output {
+        Some(filename) => fs::write(filename, bytes),
+        None => io::stdout().write_all(&bytes),
+    };
+    if let Err(error) = written {
+        eprintln!("rumasm: {}", error);
+        process::exit(1);
+    }
+}
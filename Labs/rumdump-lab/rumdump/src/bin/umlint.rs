@@ -0,0 +1,36 @@
+use std::env;
+use std::process;
+use rumdump::rumcfg::Analysis;
+use rumdump::rumdis;
+use rumdump::rumlint;
+use rumdump::rumload;
+
+const USAGE: &str = "usage: umlint [FILE.um]";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() > 1 || args.first().is_some_and(|arg| arg.starts_with('-')) {
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    }
+    let input = args.first().map(String::as_str);
+    let name = input.unwrap_or("<stdin>");
+    let words = rumload::load(input).unwrap_or_else(|error| {
+        eprintln!("umlint: {}", error);
+        process::exit(1);
+    });
+    let analysis = Analysis::new(&words);
+
+    let findings = rumlint::lint(&words, &analysis);
+    for finding in &findings {
+        println!("{}:{}: {}  [{}]", name, finding.pc, finding.lint, rumdis::disassemble(words[finding.pc]));
+    }
+    let unresolved = analysis.unresolved.iter().filter(|&&pc| analysis.reachable[pc]).count();
+    if unresolved > 0 {
+        eprintln!("umlint: {} jumps could not be resolved; code reached only through them was not checked",
+            unresolved);
+    }
+    if !findings.is_empty() {
+        process::exit(1);
+    }
+}
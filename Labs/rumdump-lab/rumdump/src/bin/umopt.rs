@@ -0,0 +1,49 @@
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::process;
+use rumdump::{rumasm, rumload, rumopt};
+
+const USAGE: &str = "usage: umopt [-o OUT.um] [FILE.um]";
+
+fn main() {
+    let mut input = None;
+    let mut output = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = args.next(),
+            _ if arg.starts_with('-') => {
+                eprintln!("{}", USAGE);
+                process::exit(2);
+            }
+            _ => input = Some(arg),
+        }
+    }
+
+    let name = input.as_deref().unwrap_or("<stdin>");
+    let words = rumload::load(input.as_deref()).unwrap_or_else(|error| {
+        eprintln!("umopt: {}", error);
+        process::exit(1);
+    });
+    let optimized = match rumopt::optimize(&words) {
+        Ok(optimized) => {
+            eprintln!("umopt: {}: {} words, {} before", name, optimized.len(), words.len());
+            optimized
+        }
+        Err(error) => {
+            // The program is still written, so umopt can sit in any pipeline
+            eprintln!("umopt: {}: left as it is: {}", name, error);
+            words
+        }
+    };
+    let bytes = rumasm::to_bytes(&optimized);
+    let written = match &output {
+        Some(filename) => fs::write(filename, bytes),
+        None => io::stdout().write_all(&bytes),
+    };
+    if let Err(error) = written {
+        eprintln!("umopt: {}", error);
+        process::exit(1);
+    }
+}
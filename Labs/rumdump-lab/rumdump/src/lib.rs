@@ -1,2 +1,7 @@
+pub mod rumasm;
+pub mod rumcfg;
 pub mod rumdis;
+pub mod rumlint;
 pub mod rumload;
+pub mod rumopt;
+pub mod rumtrace;
@@ -1,12 +1,49 @@
 use std::env;
-use rumdump::rumdis;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::process;
+use rumdump::rumcfg::{self, Analysis};
 use rumdump::rumload;
+use rumdump::rumdis;
+use rumdump::rumtrace::{self, TraceReader};
 
 fn main() {
-    let input = env::args().nth(1);
-    let instructions = rumload::load(input.as_deref());
-    println!("{} instructions", instructions.len());
-    for instruction in instructions {
-    println!("{}", rumdis::disassemble(instruction))
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("--trace") {
+        let Some(filename) = args.get(1) else {
+            eprintln!("usage: rumdump --trace FILE");
+            process::exit(2);
+        };
+        if let Err(error) = dump_trace(filename) {
+            eprintln!("rumdump: {}: {}", filename, error);
+            process::exit(1);
+        }
+        return;
     }
+    let cfg = args.first().map(String::as_str) == Some("--cfg");
+    let input = args.get(cfg as usize);
+    let instructions = rumload::load(input.map(String::as_str)).unwrap_or_else(|error| {
+        eprintln!("rumdump: {}", error);
+        process::exit(1);
+    });
+    let analysis = Analysis::new(&instructions);
+    if cfg {
+        print!("{}", rumcfg::graphviz(&instructions, &analysis));
+        return;
+    }
+    println!("{} instructions", instructions.len());
+    print!("{}", rumcfg::listing(&instructions, &analysis));
+}
+
+/// Prints one line per record of a `rum --trace` file. Two traces can be
+/// compared instruction by instruction by diffing their dumps.
+fn dump_trace(filename: &str) -> io::Result<()> {
+    let reader = TraceReader::new(BufReader::new(File::open(filename)?))?;
+    let mut out = BufWriter::new(io::stdout().lock());
+    for record in reader {
+        let record = record?;
+        writeln!(out, "{:>8}: {:<36} {}", record.pc,
+            rumdis::disassemble(record.instruction), rumtrace::effects(&record))?;
     }
+    out.flush()
+}
@@ -0,0 +1,291 @@
+//! Assembler for the syntax printed by `rumdis::disassemble`.
+//!
+//! Each line holds at most one statement, optionally preceded by a label
+//! definition `name:` and followed by a `#` or `//` comment. Address
+//! prefixes such as `    12:` in `rumdump` listings are skipped. The trailing `;`
+//! printed by the disassembler is optional. Besides the fourteen
+//! instructions the assembler accepts:
+//!
+//! * `.data VALUE` emits VALUE as a raw word
+//! * `loadval rA, VALUE` loads any 32-bit VALUE into rA. Values that do not
+//!   fit in 25 bits expand to several instructions, and values whose
+//!   complement does not fit either need a scratch register:
+//!   `loadval rA, VALUE, rT`
+//!
+//! VALUE is a decimal or 0x-prefixed hex number, or a label, which stands
+//! for the word offset of the statement it names.
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// An assembly error and the 1-based line it was found on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Reg(u32),
+    Num(u32),
+    Ident(String),
+    Sym(&'static str),
+}
+
+/// A number or a label still to be resolved
+#[derive(Debug, Clone)]
+enum Value {
+    Num(u32),
+    Label(String),
+}
+
+#[derive(Debug, Clone)]
+enum Statement {
+    Inst(u32),
+    LoadValue(u32, Value),
+    Data(Value),
+    LoadVal(u32, Value, Option<u32>),
+}
+
+const SYMBOLS: [&str; 12] = [":=", "!=", "(", ")", "[", "]", "+", "*", "/", ";", ",", ":"];
+
+/// Encodes a three-register instruction
+pub fn encode(opcode: u32, a: u32, b: u32, c: u32) -> u32 {
+    (opcode << 28) | (a << 6) | (b << 3) | c
+}
+
+/// Encodes Load Value of `value`, which must fit in 25 bits
+pub fn encode_load_value(rl: u32, value: u32) -> u32 {
+    (13 << 28) | (rl << 25) | value
+}
+
+fn number(word: &str) -> Option<u32> {
+    match word.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => word.parse().ok(),
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        if let Some(sym) = SYMBOLS.iter().find(|sym| rest.starts_with(*sym)) {
+            tokens.push(Token::Sym(sym));
+            rest = &rest[sym.len()..];
+        } else {
+            let end = rest
+                .find(|ch: char| !(ch.is_ascii_alphanumeric() || ch == '_' || ch == '.'))
+                .unwrap_or(rest.len());
+            if end == 0 {
+                return Err(format!("unexpected character '{}'", rest.chars().next().unwrap()));
+            }
+            let word = &rest[..end];
+            let register = word.strip_prefix('r').and_then(|n| n.parse::<u32>().ok());
+            tokens.push(match register {
+                Some(r) if r < 8 && word.len() == 2 => Token::Reg(r),
+                _ if word.starts_with(|ch: char| ch.is_ascii_digit()) => {
+                    Token::Num(number(word).ok_or(format!("bad number '{}'", word))?)
+                }
+                _ => Token::Ident(word.to_string()),
+            });
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+fn value(token: &Token) -> Option<Value> {
+    match token {
+        Token::Num(n) => Some(Value::Num(*n)),
+        Token::Ident(name) if !name.starts_with('.') => Some(Value::Label(name.clone())),
+        _ => None,
+    }
+}
+
+/// Parses the tokens of one statement
+fn statement(tokens: &[Token]) -> Option<Statement> {
+    use Token::{Num, Reg, Sym};
+    let ident = |token: &Token, name: &str| matches!(token, Token::Ident(word) if word == name);
+    let inst = |opcode, a, b, c| Some(Statement::Inst(encode(opcode, a, b, c)));
+    match tokens {
+        [i, Sym("("), Reg(c), Sym("!="), Num(0), Sym(")"), Reg(a), Sym(":="), Reg(b)] if ident(i, "if") => {
+            inst(0, *a, *b, *c)
+        }
+        [Reg(a), Sym(":="), m, Sym("["), Reg(b), Sym("]"), Sym("["), Reg(c), Sym("]")] if ident(m, "m") => {
+            inst(1, *a, *b, *c)
+        }
+        [m, Sym("["), Reg(a), Sym("]"), Sym("["), Reg(b), Sym("]"), Sym(":="), Reg(c)] if ident(m, "m") => {
+            inst(2, *a, *b, *c)
+        }
+        [Reg(a), Sym(":="), Reg(b), Sym("+"), Reg(c)] => inst(3, *a, *b, *c),
+        [Reg(a), Sym(":="), Reg(b), Sym("*"), Reg(c)] => inst(4, *a, *b, *c),
+        [Reg(a), Sym(":="), Reg(b), Sym("/"), Reg(c)] => inst(5, *a, *b, *c),
+        [Reg(a), Sym(":="), Reg(b), n, Reg(c)] if ident(n, "nand") => inst(6, *a, *b, *c),
+        [h] if ident(h, "halt") => inst(7, 0, 0, 0),
+        [Reg(b), Sym(":="), m, s, Sym("("), Reg(c), w, Sym(")")]
+            if ident(m, "map") && ident(s, "segment") && ident(w, "words") => inst(8, 0, *b, *c),
+        [u, Reg(c)] if ident(u, "unmap") => inst(9, 0, 0, *c),
+        [o, Reg(c)] if ident(o, "output") => inst(10, 0, 0, *c),
+        [Reg(c), Sym(":="), i, Sym("("), Sym(")")] if ident(i, "input") => inst(11, 0, 0, *c),
+        [g, Reg(c), i, p, m, Sym("["), Reg(b), Sym("]")]
+            if ident(g, "goto") && ident(i, "in") && ident(p, "program") && ident(m, "m") => {
+            inst(12, 0, *b, *c)
+        }
+        [Reg(a), Sym(":="), v] => Some(Statement::LoadValue(*a, value(v)?)),
+        [d, v] if ident(d, ".data") => Some(Statement::Data(value(v)?)),
+        [l, Reg(a), Sym(","), v] if ident(l, "loadval") => Some(Statement::LoadVal(*a, value(v)?, None)),
+        [l, Reg(a), Sym(","), v, Sym(","), Reg(t)] if ident(l, "loadval") => {
+            Some(Statement::LoadVal(*a, value(v)?, Some(*t)))
+        }
+        _ => None,
+    }
+}
+
+/// Number of words a `loadval` of `value` expands to. Labels always fit
+/// in 25 bits.
+fn loadval_size(value: &Value) -> usize {
+    match value {
+        Value::Num(n) if *n >= 1 << 25 && !*n < 1 << 25 => 2,
+        Value::Num(n) if *n >= 1 << 25 => 5,
+        _ => 1,
+    }
+}
+
+/// Assembles `source` into UM words
+///
+/// # Arguments:
+/// * `source`: The assembly text
+pub fn assemble(source: &str) -> Result<Vec<u32>, AsmError> {
+    let mut labels: HashMap<String, u32> = HashMap::new();
+    let mut statements: Vec<(usize, Statement)> = Vec::new();
+    let mut address = 0;
+
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index + 1;
+        let error = |message: String| AsmError { line: line_number, message };
+        let text = line.split('#').next().unwrap().split("//").next().unwrap();
+        let mut tokens = tokenize(text).map_err(error)?;
+        // Address prefixes printed by rumdump are ignored
+        if let [Token::Num(_), Token::Sym(":"), ..] = tokens.as_slice() {
+            tokens.drain(..2);
+        }
+        if let [Token::Ident(name), Token::Sym(":"), ..] = tokens.as_slice() {
+            if labels.insert(name.clone(), address).is_some() {
+                return Err(error(format!("label '{}' is defined twice", name)));
+            }
+            tokens.drain(..2);
+        }
+        if tokens.last() == Some(&Token::Sym(";")) {
+            tokens.pop();
+        }
+        // The header line printed by rumdump
+        if let [Token::Num(_), Token::Ident(word)] = tokens.as_slice() {
+            if word == "instructions" {
+                continue;
+            }
+        }
+        if tokens.is_empty() {
+            continue;
+        }
+        let statement = statement(&tokens).ok_or(error(format!("cannot parse '{}'", text.trim())))?;
+        address += match &statement {
+            Statement::LoadVal(_, value, _) => loadval_size(value) as u32,
+            _ => 1,
+        };
+        statements.push((line_number, statement));
+    }
+
+    let mut words = Vec::with_capacity(address as usize);
+    for (line, statement) in statements {
+        let error = |message: String| AsmError { line, message };
+        let resolve = |value: &Value| match value {
+            Value::Num(n) => Ok(*n),
+            Value::Label(name) => labels.get(name).copied().ok_or(error(format!("undefined label '{}'", name))),
+        };
+        match statement {
+            Statement::Inst(word) => words.push(word),
+            Statement::Data(value) => words.push(resolve(&value)?),
+            Statement::LoadValue(a, value) => {
+                let n = resolve(&value)?;
+                if n >= 1 << 25 {
+                    return Err(error(format!("{} does not fit in 25 bits; use loadval", n)));
+                }
+                words.push(encode_load_value(a, n));
+            }
+            Statement::LoadVal(a, value, temp) => {
+                let n = resolve(&value)?;
+                match loadval_size(&value) {
+                    1 if n >= 1 << 25 => return Err(error(format!("label address {} does not fit in 25 bits", n))),
+                    1 => words.push(encode_load_value(a, n)),
+                    2 => {
+                        words.push(encode_load_value(a, !n));
+                        words.push(encode(6, a, a, a));
+                    }
+                    _ => {
+                        let t = temp.ok_or(error(format!("loadval of {} needs a scratch register", n)))?;
+                        if t == a {
+                            return Err(error("the scratch register must differ from the target".to_string()));
+                        }
+                        words.push(encode_load_value(a, n >> 16));
+                        words.push(encode_load_value(t, 1 << 16));
+                        words.push(encode(4, a, a, t));
+                        words.push(encode_load_value(t, n & 0xffff));
+                        words.push(encode(3, a, a, t));
+                    }
+                }
+            }
+        }
+    }
+    Ok(words)
+}
+
+/// Serializes words as a big-endian `.um` image
+pub fn to_bytes(words: &[u32]) -> Vec<u8> {
+    words.iter().flat_map(|word| word.to_be_bytes()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rumasm::{assemble, encode, encode_load_value};
+    use crate::rumdis::disassemble;
+
+    #[test]
+    fn round_trips_disassembly() {
+        let words = vec![encode(0, 1, 2, 3), encode(1, 1, 2, 3), encode(2, 1, 2, 3), encode(3, 1, 2, 3),
+            encode(4, 1, 2, 3), encode(5, 1, 2, 3), encode(6, 1, 2, 3), encode(7, 0, 0, 0),
+            encode(8, 0, 2, 3), encode(9, 0, 0, 3), encode(10, 0, 0, 3), encode(11, 0, 0, 3),
+            encode(12, 0, 2, 3), encode_load_value(5, 12345), 0xf0000000];
+        let text: Vec<String> = words.iter().map(|&w| disassemble(w)).collect();
+        assert_eq!(assemble(&text.join("\n")).unwrap(), words);
+    }
+    #[test]
+    fn labels_and_comments() {
+        let words = assemble("start: r1 := end; # jump target\ngoto r1 in program m[r0]\nend: halt // done\n.data start").unwrap();
+        assert_eq!(words, vec![encode_load_value(1, 2), encode(12, 0, 0, 1), 7 << 28, 0]);
+    }
+    #[test]
+    fn loadval_expands() {
+        assert_eq!(assemble("loadval r1, 7").unwrap(), vec![encode_load_value(1, 7)]);
+        assert_eq!(assemble("loadval r1, 0xffffffff").unwrap(), vec![encode_load_value(1, 0), encode(6, 1, 1, 1)]);
+        let words = assemble("loadval r1, 0x12345678, r2").unwrap();
+        assert_eq!(words.len(), 5);
+        assert!(assemble("loadval r1, 0x12345678").is_err());
+    }
+    #[test]
+    fn reports_line_numbers() {
+        let error = assemble("halt\nr1 := r2 - r3;").unwrap_err();
+        assert_eq!(error.line, 2);
+        assert!(assemble("r1 := 0x2000000;").is_err());
+    }
+}
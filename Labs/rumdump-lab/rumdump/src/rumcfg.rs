@@ -0,0 +1,432 @@
+//! Control-flow analysis of $m[0].
+//!
+//! Jumps are recognized from the idiom `rC := L; ... goto rC in program
+//! m[rB];` where rB was loaded with 0, including the conditional form in
+//! which a CMov chooses between two loaded targets. Every register holds 0
+//! at the entry point, so rB may also be a register that was never written.
+//! The constants each register may hold are followed from there along
+//! fall-through and resolved jump edges until nothing changes; where two
+//! paths meet with different values in a register, it is no longer known.
+//! Anything else is treated as an unresolved jump.
+//!
+//! An unresolved jump could go anywhere. Once one is reachable, every
+//! address that some Load Value loads and that starts a block, because it
+//! follows a block's end or is the target of a jump, is also taken as an
+//! entry point where nothing is known. The analysis is then no longer
+//! `complete`.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use crate::rumdis::{self, get, OP, RA, RB, RC, RL, VL};
+
+/// What is known about the code in $m[0]
+pub struct Analysis {
+    /// Resolved targets of each jump, by the address of its Load Program
+    pub jumps: BTreeMap<usize, BTreeSet<usize>>,
+    /// Load Programs whose target could not be determined
+    pub unresolved: BTreeSet<usize>,
+    /// Load Values that load a jump target, with the target they load
+    pub label_refs: BTreeMap<usize, usize>,
+    /// First address of each basic block
+    pub leaders: BTreeSet<usize>,
+    /// Addresses reachable from the entry point
+    pub reachable: Vec<bool>,
+}
+
+/// Possible constant values of a register and the Load Values that set them,
+/// sorted
+type Known = Option<Vec<(u32, usize)>>;
+
+/// What is known about each register before an instruction executes
+type Facts = [Known; 8];
+
+/// Source of the 0 a register holds at the entry point, which no Load
+/// Value put there
+const ENTRY: usize = usize::MAX;
+
+/// Where a Load Program goes
+enum Target {
+    /// To these addresses of $m[0], loaded by these Load Values
+    Resolved(Vec<(u32, usize)>),
+    /// Into another segment, leaving $m[0] for good
+    Leaves,
+    Unresolved,
+}
+
+fn opcode(word: u32) -> u32 {
+    get(&OP, word)
+}
+
+/// Whether execution never falls through from `word` to the next address
+pub(crate) fn ends_block(word: u32) -> bool {
+    matches!(opcode(word), 7 | 12) || opcode(word) > 13
+}
+
+/// Where the Load Program `word` goes, given `facts` before it
+fn target(facts: &Facts, word: u32, len: usize) -> Target {
+    match (&facts[get(&RB, word) as usize], &facts[get(&RC, word) as usize]) {
+        (Some(segment), Some(targets)) if segment.iter().all(|&(v, _)| v == 0)
+            && targets.iter().all(|&(v, _)| (v as usize) < len) => Target::Resolved(targets.clone()),
+        (Some(segment), _) if segment.iter().all(|&(v, _)| v != 0) => Target::Leaves,
+        _ => Target::Unresolved,
+    }
+}
+
+/// Updates `facts` for the effect of `word`, which is at `pc` and does not
+/// end its block
+fn transfer(facts: &mut Facts, word: u32, pc: usize) {
+    let (a, b, c) = (get(&RA, word) as usize, get(&RB, word) as usize, get(&RC, word) as usize);
+    match opcode(word) {
+        0 => {
+            facts[a] = match (&facts[a], &facts[b]) {
+                (Some(x), Some(y)) => {
+                    let mut both: Vec<(u32, usize)> = x.iter().chain(y).copied().collect();
+                    both.sort();
+                    both.dedup();
+                    Some(both)
+                }
+                _ => None,
+            }
+        }
+        1 | 3 | 4 | 5 | 6 => facts[a] = None,
+        8 => facts[b] = None,
+        11 => facts[c] = None,
+        13 => facts[get(&RL, word) as usize] = Some(vec![(get(&VL, word), pc)]),
+        _ => {}
+    }
+}
+
+/// Adds the facts of another path into `pc` to what is known there,
+/// returning whether that changed
+fn flow(entry: &mut [Option<Facts>], work: &mut Vec<usize>, pc: usize, facts: &Facts) -> bool {
+    let Some(known) = &mut entry[pc] else {
+        entry[pc] = Some(facts.clone());
+        work.push(pc);
+        return true;
+    };
+    let values = |known: &Vec<(u32, usize)>| known.iter().map(|&(v, _)| v).collect::<BTreeSet<u32>>();
+    let mut changed = false;
+    for (mine, theirs) in known.iter_mut().zip(facts) {
+        let merged = match (&*mine, theirs) {
+            (Some(x), Some(y)) if values(x) == values(y) => {
+                let mut sources: Vec<(u32, usize)> = x.iter().chain(y).copied().collect();
+                sources.sort();
+                sources.dedup();
+                Some(sources)
+            }
+            _ => None,
+        };
+        if merged != *mine {
+            *mine = merged;
+            changed = true;
+        }
+    }
+    if changed {
+        work.push(pc);
+    }
+    changed
+}
+
+impl Analysis {
+    /// Analyzes the words of $m[0]
+    pub fn new(words: &[u32]) -> Self {
+        let mut analysis = Analysis {
+            jumps: BTreeMap::new(),
+            unresolved: BTreeSet::new(),
+            label_refs: BTreeMap::new(),
+            leaders: BTreeSet::new(),
+            reachable: vec![false; words.len()],
+        };
+        analysis.find_jumps(words);
+        analysis.find_leaders(words);
+        analysis.find_reachable(words);
+        analysis
+    }
+
+    /// Follows register constants through the code to resolve jumps
+    fn find_jumps(&mut self, words: &[u32]) {
+        if words.is_empty() {
+            return;
+        }
+        let mut entry: Vec<Option<Facts>> = vec![None; words.len()];
+        let mut work = Vec::new();
+        let mut targets = BTreeSet::new();
+        flow(&mut entry, &mut work, 0, &std::array::from_fn(|_| Some(vec![(0, ENTRY)])));
+        loop {
+            while let Some(pc) = work.pop() {
+                let word = words[pc];
+                let mut facts = entry[pc].clone().unwrap();
+                if opcode(word) == 12 {
+                    if let Target::Resolved(resolved) = target(&facts, word, words.len()) {
+                        for (to, _) in resolved {
+                            targets.insert(to as usize);
+                            flow(&mut entry, &mut work, to as usize, &facts);
+                        }
+                    }
+                } else if !ends_block(word) && pc + 1 < words.len() {
+                    transfer(&mut facts, word, pc);
+                    flow(&mut entry, &mut work, pc + 1, &facts);
+                }
+            }
+            let unresolved = entry.iter().zip(words).any(|(facts, &word)| matches!(facts,
+                Some(facts) if opcode(word) == 12 && matches!(target(facts, word, words.len()), Target::Unresolved)));
+            if !unresolved {
+                break;
+            }
+            let loaded: Vec<usize> = entry.iter().zip(words)
+                .filter(|&(facts, &word)| facts.is_some() && opcode(word) == 13)
+                .map(|(_, &word)| get(&VL, word) as usize)
+                .filter(|&to| to < words.len() && (to == 0 || ends_block(words[to - 1]) || targets.contains(&to)))
+                .collect();
+            let mut seeded = false;
+            for to in loaded {
+                seeded |= flow(&mut entry, &mut work, to, &Facts::default());
+            }
+            if !seeded {
+                break;
+            }
+        }
+
+        // Nothing is known about code that no path reaches
+        let unknown = Facts::default();
+        for (pc, facts) in entry.iter().enumerate() {
+            if opcode(words[pc]) != 12 {
+                continue;
+            }
+            match target(facts.as_ref().unwrap_or(&unknown), words[pc], words.len()) {
+                Target::Resolved(targets) => {
+                    let jump = self.jumps.entry(pc).or_default();
+                    for (to, source) in targets {
+                        jump.insert(to as usize);
+                        if source != ENTRY {
+                            self.label_refs.insert(source, to as usize);
+                        }
+                    }
+                }
+                Target::Leaves => {}
+                Target::Unresolved => {
+                    self.unresolved.insert(pc);
+                }
+            }
+        }
+    }
+
+    fn find_leaders(&mut self, words: &[u32]) {
+        if !words.is_empty() {
+            self.leaders.insert(0);
+        }
+        for (pc, &word) in words.iter().enumerate() {
+            if ends_block(word) && pc + 1 < words.len() {
+                self.leaders.insert(pc + 1);
+            }
+        }
+        for targets in self.jumps.values() {
+            self.leaders.extend(targets);
+        }
+    }
+
+    fn find_reachable(&mut self, words: &[u32]) {
+        let mut work = vec![0];
+        while let Some(pc) = work.pop() {
+            if pc >= words.len() || self.reachable[pc] {
+                continue;
+            }
+            self.reachable[pc] = true;
+            if let Some(targets) = self.jumps.get(&pc) {
+                work.extend(targets);
+            }
+            if !ends_block(words[pc]) {
+                work.push(pc + 1);
+            }
+        }
+    }
+
+    /// Whether every reachable jump was resolved, so that unreachable words
+    /// are known to be data
+    pub fn complete(&self) -> bool {
+        self.unresolved.iter().all(|&pc| !self.reachable[pc])
+    }
+
+    /// Whether the word at `pc` is data rather than code
+    pub fn is_data(&self, pc: usize) -> bool {
+        self.complete() && !self.reachable[pc]
+    }
+
+    /// The address ranges of the basic blocks, in order
+    pub fn blocks(&self, len: usize) -> Vec<(usize, usize)> {
+        let starts: Vec<usize> = self.leaders.iter().copied().collect();
+        starts.iter().enumerate().map(|(i, &start)| (start, starts.get(i + 1).copied().unwrap_or(len))).collect()
+    }
+}
+
+/// Name of the label for `address`
+pub fn label(address: usize) -> String {
+    format!("L{}", address)
+}
+
+/// Text for the word at `pc`, with jump targets shown as labels
+fn statement(words: &[u32], analysis: &Analysis, pc: usize) -> String {
+    let word = words[pc];
+    if analysis.is_data(pc) {
+        return format!(".data 0x{:08x}", word);
+    }
+    match analysis.label_refs.get(&pc) {
+        Some(&target) => format!("r{} := {};", get(&RL, word), label(target)),
+        None => rumdis::disassemble(word),
+    }
+}
+
+/// An address-prefixed listing of $m[0] split into basic blocks, which
+/// `rumasm` accepts
+pub fn listing(words: &[u32], analysis: &Analysis) -> String {
+    let targets: BTreeSet<usize> = analysis.jumps.values().flatten().copied().collect();
+    let mut out = String::new();
+    for (start, end) in analysis.blocks(words.len()) {
+        if start > 0 {
+            out.push('\n');
+        }
+        if targets.contains(&start) {
+            writeln!(out, "{}:", label(start)).unwrap();
+        }
+        for pc in start..end {
+            write!(out, "{:>8}: {}", pc, statement(words, analysis, pc)).unwrap();
+            if analysis.unresolved.contains(&pc) {
+                out.push_str("  # unresolved jump");
+            }
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// A Graphviz digraph of the basic blocks of $m[0] and the edges between
+/// them. Blocks ending in an unresolved jump get a dashed edge to `unknown`.
+pub fn graphviz(words: &[u32], analysis: &Analysis) -> String {
+    let blocks = analysis.blocks(words.len());
+    let mut out = String::from("digraph cfg {\n    node [shape=box, fontname=monospace];\n");
+    let mut unknown = false;
+    for &(start, end) in &blocks {
+        if !analysis.reachable[start] && analysis.complete() {
+            continue;
+        }
+        let mut text = String::new();
+        for pc in start..end {
+            write!(text, "{}: {}\\l", pc, statement(words, analysis, pc).replace('"', "\\\"")).unwrap();
+        }
+        writeln!(out, "    {} [label=\"{}\"];", label(start), text).unwrap();
+        let last = end - 1;
+        if !ends_block(words[last]) && end < words.len() {
+            writeln!(out, "    {} -> {};", label(start), label(end)).unwrap();
+        }
+        for &target in analysis.jumps.get(&last).into_iter().flatten() {
+            writeln!(out, "    {} -> {};", label(start), label(target)).unwrap();
+        }
+        if analysis.unresolved.contains(&last) {
+            writeln!(out, "    {} -> unknown [style=dashed];", label(start)).unwrap();
+            unknown = true;
+        }
+    }
+    if unknown {
+        out.push_str("    unknown [shape=plaintext];\n");
+    }
+    out.push_str("}\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rumasm::assemble;
+    use crate::rumcfg::{listing, Analysis};
+
+    const BRANCH: &str = "
+        r0 := 0
+        r1 := 1
+        r2 := yes
+        r3 := no
+        if (r1 != 0) r3 := r2;
+        goto r3 in program m[r0];
+        .data 0xdeadbeef
+    yes: halt
+    no: halt";
+
+    #[test]
+    fn resolves_conditional_jump() {
+        let words = assemble(BRANCH).unwrap();
+        let analysis = Analysis::new(&words);
+        assert_eq!(analysis.jumps[&5].iter().copied().collect::<Vec<_>>(), vec![7, 8]);
+        assert!(analysis.complete());
+        assert!(analysis.is_data(6));
+        assert_eq!(analysis.blocks(words.len()), vec![(0, 6), (6, 7), (7, 8), (8, 9)]);
+    }
+    #[test]
+    fn listing_reassembles() {
+        let words = assemble(BRANCH).unwrap();
+        let text = listing(&words, &Analysis::new(&words));
+        assert!(text.contains("L7:\n       7: halt"));
+        assert!(text.contains("       2: r2 := L7;"));
+        assert_eq!(assemble(&text).unwrap(), words);
+    }
+    #[test]
+    fn facts_come_only_from_real_paths() {
+        // pc 6 and 7 are never executed, so the goto at 8 only sees r3 = 10
+        let words = assemble("
+            r5 := 1
+            r5 := 2
+            r0 := 0
+            r3 := a
+            r4 := join
+            goto r4 in program m[r0];
+            r0 := 0
+            r3 := b
+        join: goto r3 in program m[r0];
+            halt
+        a: r1 := 65
+        b: output r1;
+            halt").unwrap();
+        let analysis = Analysis::new(&words);
+        assert_eq!(analysis.jumps[&8].iter().copied().collect::<Vec<_>>(), vec![10]);
+        assert!(analysis.complete());
+        assert!(analysis.is_data(6) && analysis.is_data(7) && !analysis.is_data(10));
+        assert!(listing(&words, &analysis).contains("L10:\n      10: r1 := 65;"));
+
+        // Both paths into the goto are real, with different r3
+        let words = assemble("
+            r0 := 0
+            r1 := 1
+            r3 := a
+            r2 := skip
+            r4 := join
+            if (r1 != 0) r4 := r2;
+            goto r4 in program m[r0];
+        skip: r3 := b
+        join: goto r3 in program m[r0];
+        a: halt
+        b: halt").unwrap();
+        let analysis = Analysis::new(&words);
+        assert_eq!(analysis.jumps[&6].iter().copied().collect::<Vec<_>>(), vec![7, 8]);
+        assert!(analysis.unresolved.contains(&8));
+        assert!(!analysis.complete());
+    }
+    #[test]
+    fn registers_start_at_zero() {
+        // r0 is never written, so it still holds the 0 it started with
+        let words = assemble("
+            r6 := t
+            goto r6 in program m[r0];
+            .data 0xdeadbeef
+        t: goto r5 in program m[r0];").unwrap();
+        let analysis = Analysis::new(&words);
+        assert_eq!(analysis.jumps[&1].iter().copied().collect::<Vec<_>>(), vec![3]);
+        assert_eq!(analysis.jumps[&3].iter().copied().collect::<Vec<_>>(), vec![0]);
+        assert!(analysis.complete());
+        assert!(analysis.is_data(2));
+        assert_eq!(analysis.label_refs.keys().copied().collect::<Vec<_>>(), vec![0]);
+    }
+    #[test]
+    fn unknown_target_is_unresolved() {
+        let words = assemble("r1 := m[r0][r0];\ngoto r1 in program m[r0];\nhalt").unwrap();
+        let analysis = Analysis::new(&words);
+        assert!(analysis.unresolved.contains(&1));
+        assert!(!analysis.is_data(2));
+    }
+}
@@ -9,12 +9,12 @@ pub struct Field {
     lsb: u32,
 }
 
-static RA: Field = Field { width: 3, lsb: 6 };
-static RB: Field = Field { width: 3, lsb: 3 };
-static RC: Field = Field { width: 3, lsb: 0 };
-static RL: Field = Field { width: 3, lsb: 25 };
-static VL: Field = Field { width: 25, lsb: 0 };
-static OP: Field = Field { width: 4, lsb: 28 };
+pub static RA: Field = Field { width: 3, lsb: 6 };
+pub static RB: Field = Field { width: 3, lsb: 3 };
+pub static RC: Field = Field { width: 3, lsb: 0 };
+pub static RL: Field = Field { width: 3, lsb: 25 };
+pub static VL: Field = Field { width: 25, lsb: 0 };
+pub static OP: Field = Field { width: 4, lsb: 28 };
 
 /// Given a `field` and `instruction`, extract
 /// that field from the instruction as a u32
@@ -27,6 +27,12 @@ fn op(instruction: Umi) -> Option<Opcode> {
     FromPrimitive::from_u32(bitpack::getu(instruction as u64, OP.width as u64, OP.lsb as u64).unwrap() as u32)
 }
 
+/// Whether `inst` has one of the fourteen opcodes, rather than being shown
+/// as `.data` by `disassemble`
+pub fn is_instruction(inst: Umi) -> bool {
+    op(inst).is_some()
+}
+
 pub fn disassemble(inst: Umi) -> String {
 
     match op(inst) {
@@ -0,0 +1,279 @@
+//! Static checks of the code in $m[0].
+//!
+//! The reachable words found by `rumcfg` are walked with a forward dataflow
+//! analysis that tracks, for every register, whether it has been written
+//! and whether it holds a constant loaded by Load Value. Jumps that
+//! `rumcfg` could not resolve are assumed to reach every reachable word, so
+//! a warning holds on every path the analysis knows about. Words reachable
+//! only through such jumps are not checked.
+
+use std::fmt;
+use crate::rumcfg::{ends_block, Analysis};
+use crate::rumdis::{self, get, OP, RA, RB, RC, RL, VL};
+
+/// A problem found in the program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lint {
+    /// A reachable word with no valid opcode, which faults when executed
+    InvalidOpcode { word: u32 },
+    /// A register read before anything has written it, so it is still 0
+    UnwrittenRead { register: usize },
+    /// A Division whose divisor is always 0
+    DivideByZero { register: usize },
+    /// An Output of a constant that does not fit in a byte
+    WideOutput { register: usize, value: u32 },
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Lint::InvalidOpcode { word } => write!(f, "0x{:08x} is not a valid instruction", word),
+            Lint::UnwrittenRead { register } => {
+                write!(f, "r{} is read but never written, so it is always 0", register)
+            }
+            Lint::DivideByZero { register } => write!(f, "divisor r{} is always 0", register),
+            Lint::WideOutput { register, value } => {
+                write!(f, "output of r{}, which holds {} and does not fit in a byte", register, value)
+            }
+        }
+    }
+}
+
+/// A lint and the address of the word it was found at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Finding {
+    pub pc: usize,
+    pub lint: Lint,
+}
+
+/// What is known about a register on every path to an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Value {
+    /// Nothing has written it
+    Unwritten,
+    /// Load Value set it to this constant
+    Const(u32),
+    /// Map Segment set it to a segment identifier, which is never 0
+    Segment,
+    Unknown,
+}
+
+pub(crate) type State = [Value; 8];
+
+/// Combines what is known on two paths into what holds on both
+fn join(into: &mut State, from: &State) -> bool {
+    let mut changed = false;
+    for (x, &y) in into.iter_mut().zip(from) {
+        if *x != y && *x != Value::Unknown {
+            *x = Value::Unknown;
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// The registers an instruction reads
+pub(crate) fn reads(word: u32) -> Vec<usize> {
+    let (a, b, c) = (get(&RA, word) as usize, get(&RB, word) as usize, get(&RC, word) as usize);
+    let mut registers = match get(&OP, word) {
+        0 | 1 | 3 | 4 | 5 | 6 | 12 => vec![b, c],
+        2 => vec![a, b, c],
+        8..=10 => vec![c],
+        _ => vec![],
+    };
+    registers.sort_unstable();
+    registers.dedup();
+    registers
+}
+
+/// Applies an instruction to what is known about the registers
+fn transfer(word: u32, state: &mut State) {
+    let (a, b, c) = (get(&RA, word) as usize, get(&RB, word) as usize, get(&RC, word) as usize);
+    match get(&OP, word) {
+        0 => {
+            state[a] = match state[c] {
+                Value::Const(0) | Value::Unwritten => state[a],
+                Value::Const(_) | Value::Segment => state[b],
+                Value::Unknown if state[a] == state[b] => state[a],
+                Value::Unknown => Value::Unknown,
+            }
+        }
+        1 | 3 | 4 | 5 | 6 => state[a] = Value::Unknown,
+        8 => state[b] = Value::Segment,
+        11 => state[c] = Value::Unknown,
+        13 => state[get(&RL, word) as usize] = Value::Const(get(&VL, word)),
+        _ => {}
+    }
+}
+
+/// What is known about the registers before each reachable word
+pub(crate) fn flow(words: &[u32], analysis: &Analysis) -> Vec<Option<State>> {
+    let mut states: Vec<Option<State>> = vec![None; words.len()];
+    if words.is_empty() {
+        return states;
+    }
+    states[0] = Some([Value::Unwritten; 8]);
+    let mut work = vec![0];
+    // What holds after any unresolved jump, which may go anywhere
+    let mut wild: Option<State> = None;
+    loop {
+        while let Some(pc) = work.pop() {
+            let mut state = states[pc].expect("queued words have a state");
+            transfer(words[pc], &mut state);
+            let mut next: Vec<usize> = analysis.jumps.get(&pc).into_iter().flatten().copied().collect();
+            if !ends_block(words[pc]) && pc + 1 < words.len() {
+                next.push(pc + 1);
+            }
+            if analysis.unresolved.contains(&pc) {
+                match &mut wild {
+                    Some(wild) => {
+                        join(wild, &state);
+                    }
+                    None => wild = Some(state),
+                }
+            }
+            for target in next {
+                let changed = match &mut states[target] {
+                    Some(known) => join(known, &state),
+                    slot => {
+                        *slot = Some(state);
+                        true
+                    }
+                };
+                if changed {
+                    work.push(target);
+                }
+            }
+        }
+        let Some(wild) = wild else { break };
+        for (pc, slot) in states.iter_mut().enumerate() {
+            if let Some(known) = slot {
+                if join(known, &wild) {
+                    work.push(pc);
+                }
+            }
+        }
+        if work.is_empty() {
+            break;
+        }
+    }
+    states
+}
+
+/// Checks the reachable code of $m[0], returning the findings in address
+/// order
+///
+/// # Arguments:
+/// * `words`: The words of $m[0]
+/// * `analysis`: The control flow of `words`
+pub fn lint(words: &[u32], analysis: &Analysis) -> Vec<Finding> {
+    let states = flow(words, analysis);
+    let mut findings = Vec::new();
+    for (pc, &word) in words.iter().enumerate() {
+        let Some(state) = states[pc] else { continue };
+        if !rumdis::is_instruction(word) {
+            findings.push(Finding { pc, lint: Lint::InvalidOpcode { word } });
+            continue;
+        }
+        for register in reads(word) {
+            if state[register] == Value::Unwritten {
+                findings.push(Finding { pc, lint: Lint::UnwrittenRead { register } });
+            }
+        }
+        let c = get(&RC, word) as usize;
+        match (get(&OP, word), state[c]) {
+            (5, Value::Const(0)) => findings.push(Finding { pc, lint: Lint::DivideByZero { register: c } }),
+            (10, Value::Const(value)) if value > 255 => {
+                findings.push(Finding { pc, lint: Lint::WideOutput { register: c, value } })
+            }
+            _ => {}
+        }
+    }
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rumasm::assemble;
+    use crate::rumcfg::Analysis;
+    use crate::rumlint::{lint, Finding, Lint};
+
+    fn check(source: &str) -> Vec<Finding> {
+        let words = assemble(source).unwrap();
+        lint(&words, &Analysis::new(&words))
+    }
+
+    #[test]
+    fn reports_each_lint() {
+        let findings = check("
+            r1 := 0
+            r2 := 300
+            r3 := r2 / r1;
+            output r2;
+            output r4;
+            .data 0xf0000000
+            halt");
+        assert_eq!(findings, vec![
+            Finding { pc: 2, lint: Lint::DivideByZero { register: 1 } },
+            Finding { pc: 3, lint: Lint::WideOutput { register: 2, value: 300 } },
+            Finding { pc: 4, lint: Lint::UnwrittenRead { register: 4 } },
+            Finding { pc: 5, lint: Lint::InvalidOpcode { word: 0xf000_0000 } },
+        ]);
+    }
+    #[test]
+    fn joins_paths() {
+        // r2 is 0 on one path and 5 on the other, so the division is fine;
+        // r4 is 0 on both, and the data after the halt is never reached
+        let findings = check("
+            r0 := 0
+            r1 := 1
+            r2 := 0
+            r4 := 0
+            r5 := other
+            r6 := join
+            if (r1 != 0) r6 := r5;
+            goto r6 in program m[r0];
+        other:
+            r2 := 5
+        join:
+            r3 := r1 / r2;
+            r3 := r1 / r4;
+            halt
+            .data 0xffffffff");
+        assert_eq!(findings, vec![Finding { pc: 10, lint: Lint::DivideByZero { register: 4 } }]);
+    }
+    #[test]
+    fn skipped_code_does_not_reach_a_jump() {
+        // The words after the first goto never run, so the goto at `join`
+        // only goes to `set`, which writes r1 before the output reads it
+        let findings = check("
+            r0 := 0
+            r3 := set
+            r4 := join
+            goto r4 in program m[r0];
+            r0 := 0
+            r3 := print
+        join:
+            goto r3 in program m[r0];
+            halt
+        set:
+            r1 := 65
+        print:
+            output r1;
+            halt");
+        assert_eq!(findings, vec![]);
+    }
+    #[test]
+    fn unresolved_jump_reaches_everything() {
+        // The jump may go to `divide` after r1 := 7, so r1 is not always 0
+        let findings = check("
+            r0 := 0
+            r1 := 0
+        divide:
+            r3 := r5 / r1;
+            r1 := 7
+            r2 := m[r0][r0];
+            goto r2 in program m[r0];");
+        assert_eq!(findings, vec![Finding { pc: 2, lint: Lint::UnwrittenRead { register: 5 } }]);
+    }
+}
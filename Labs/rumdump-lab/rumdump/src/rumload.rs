@@ -1,17 +1,12 @@
-use std::convert::TryInto;
+//! Loading `.um` programs with umcore's loader.
 
-pub fn load(input: Option<&str>) -> Vec<u32> {
-    let mut raw_reader: Box<dyn std::io::BufRead> = match input {
-    None => Box::new(std::io::BufReader::new(std::io::stdin())),
-    Some(filename) => Box::new(std::io::BufReader::new(
-    std::fs::File::open(filename).unwrap(),
-    )),
-    };
-    let mut buf = Vec::<u8>::new();
-    raw_reader.read_to_end(&mut buf).unwrap();
-    let instructions: Vec<u32> = buf
-    .chunks_exact(4)
-    .map(|x| u32::from_be_bytes(x.try_into().unwrap()))
-    .collect();
-    instructions
-    }
+use umcore::loader::{self, Encoding, LoadError};
+
+/// Loads a big-endian `.um` program from a file, or from standard input if
+/// there is none
+///
+/// # Arguments:
+/// * `input`: The program file
+pub fn load(input: Option<&str>) -> Result<Vec<u32>, LoadError> {
+    loader::load(input, Encoding::BigEndian)
+}
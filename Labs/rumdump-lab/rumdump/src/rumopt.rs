@@ -0,0 +1,556 @@
+//! Peephole optimization of the code in $m[0].
+//!
+//! The program is rewritten in rounds, each applying one kind of rewrite to
+//! the reachable code and then analyzing the result afresh:
+//!
+//! * NOT of an AND built from NANDs becomes a single NAND, and a double
+//!   NOT becomes a move or disappears
+//! * a CMov whose condition is always 0, or that moves a register onto
+//!   itself, is removed
+//! * a CMov whose condition is a nonzero constant and whose source holds a
+//!   constant becomes a Load Value
+//! * a Load Value whose register is overwritten before it is read is
+//!   removed
+//!
+//! Removing words moves the words after them, so every Load Value that
+//! `rumcfg` found loading a jump target is relocated. This is only sound
+//! when every reachable jump was resolved, every Load and Store addresses
+//! a segment known not to be $m[0], and the targets loaded reach nothing
+//! but CMovs and the jumps themselves; `optimize` refuses other programs.
+//! Registers are left as they were at Halt, apart from those holding a
+//! relocated target, but not at a fault.
+
+use std::fmt;
+use crate::rumasm::{encode, encode_load_value};
+use crate::rumcfg::{ends_block, Analysis};
+use crate::rumdis::{get, OP, RA, RB, RC, RL};
+use crate::rumlint::{flow, reads, State, Value};
+
+/// Why a program cannot be optimized
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OptError {
+    /// A Load Program whose target is unknown
+    UnresolvedJump { pc: usize },
+    /// A Load or Store whose segment may be $m[0]
+    CodeAccess { pc: usize },
+    /// An instruction other than a CMov or a jump that may use a loaded
+    /// jump target
+    TargetAsData { pc: usize },
+}
+
+impl fmt::Display for OptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OptError::UnresolvedJump { pc } => write!(f, "the jump at {} could not be resolved", pc),
+            OptError::CodeAccess { pc } => write!(f, "the instruction at {} may read or write $m[0]", pc),
+            OptError::TargetAsData { pc } => write!(f, "the instruction at {} may use a jump target as data", pc),
+        }
+    }
+}
+
+impl std::error::Error for OptError {}
+
+/// What becomes of each word in a round: kept as this word, or removed
+type Edits = Vec<Option<u32>>;
+
+/// A kind of rewrite, which records what it changes in the edits
+type Rule = fn(&Facts, &mut Edits);
+
+/// What a rewrite rule needs to know about the program
+struct Facts<'a> {
+    words: &'a [u32],
+    analysis: Analysis,
+    /// Registers before each reachable word
+    states: Vec<Option<State>>,
+    /// Registers that may be read after each word, as bit masks
+    live: Vec<u8>,
+}
+
+fn opcode(word: u32) -> u32 {
+    get(&OP, word)
+}
+
+fn fields(word: u32) -> (usize, usize, usize) {
+    (get(&RA, word) as usize, get(&RB, word) as usize, get(&RC, word) as usize)
+}
+
+/// The register an instruction certainly sets, if any. A CMov may leave
+/// its register as it was, so it sets none.
+fn writes(word: u32) -> Option<usize> {
+    let (a, b, c) = fields(word);
+    match opcode(word) {
+        1 | 3..=6 => Some(a),
+        8 => Some(b),
+        11 => Some(c),
+        13 => Some(get(&RL, word) as usize),
+        _ => None,
+    }
+}
+
+/// Whether `value` is known to be 0
+fn zero(value: Value) -> bool {
+    matches!(value, Value::Const(0) | Value::Unwritten)
+}
+
+/// Whether `value` is known not to be 0
+fn nonzero(value: Value) -> bool {
+    matches!(value, Value::Const(v) if v != 0) || value == Value::Segment
+}
+
+/// Registers that may be read after each word. Every register is live at
+/// Halt and at a Load Program that leaves $m[0]; none is at a fault.
+fn liveness(words: &[u32], analysis: &Analysis) -> Vec<u8> {
+    let mut live_in = vec![0_u8; words.len()];
+    let mut live_out = vec![0_u8; words.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for pc in (0..words.len()).rev().filter(|&pc| analysis.reachable[pc]) {
+            let word = words[pc];
+            let mut out = match (opcode(word), analysis.jumps.get(&pc)) {
+                (7, _) | (12, None) => 0xff,
+                _ => 0,
+            };
+            for &target in analysis.jumps.get(&pc).into_iter().flatten() {
+                out |= live_in[target];
+            }
+            if !ends_block(word) && pc + 1 < words.len() {
+                out |= live_in[pc + 1];
+            }
+            let mut used = out;
+            if let Some(register) = writes(word) {
+                used &= !(1 << register);
+            }
+            for register in reads(word) {
+                used |= 1 << register;
+            }
+            if out != live_out[pc] || used != live_in[pc] {
+                live_out[pc] = out;
+                live_in[pc] = used;
+                changed = true;
+            }
+        }
+    }
+    live_out
+}
+
+/// Checks that the jump targets in `label_refs` are only moved by CMovs
+/// and used by the jumps they resolve, so that relocating them changes
+/// nothing else. A target of 0 never moves and is left out.
+fn target_uses(words: &[u32], analysis: &Analysis) -> Result<(), OptError> {
+    // Registers that may hold a target, as bit masks, before each word
+    let mut held: Vec<Option<u8>> = vec![None; words.len()];
+    if words.is_empty() {
+        return Ok(());
+    }
+    held[0] = Some(0);
+    let mut work = vec![0];
+    while let Some(pc) = work.pop() {
+        let word = words[pc];
+        let mut mask = held[pc].expect("queued words have registers");
+        let (a, b, c) = fields(word);
+        let jump = analysis.jumps.get(&pc);
+        for register in reads(word) {
+            let moved = match opcode(word) {
+                0 => register == b && register != c,
+                12 => register == c && register != b && jump.is_some(),
+                _ => false,
+            };
+            if mask & (1 << register) != 0 && !moved {
+                return Err(OptError::TargetAsData { pc });
+            }
+        }
+        // A program that leaves $m[0] takes every register with it
+        if opcode(word) == 12 && jump.is_none() && mask != 0 {
+            return Err(OptError::TargetAsData { pc });
+        }
+        if opcode(word) == 0 && mask & (1 << b) != 0 {
+            mask |= 1 << a;
+        } else if let Some(register) = writes(word) {
+            mask &= !(1 << register);
+        }
+        if analysis.label_refs.get(&pc).is_some_and(|&target| target != 0) {
+            mask |= 1 << get(&RL, word);
+        }
+        let mut next: Vec<usize> = jump.into_iter().flatten().copied().collect();
+        if !ends_block(word) && pc + 1 < words.len() {
+            next.push(pc + 1);
+        }
+        for to in next {
+            let merged = held[to].unwrap_or(0) | mask;
+            if held[to] != Some(merged) {
+                held[to] = Some(merged);
+                work.push(to);
+            }
+        }
+    }
+    Ok(())
+}
+
+impl<'a> Facts<'a> {
+    fn new(words: &'a [u32]) -> Result<Self, OptError> {
+        let analysis = Analysis::new(words);
+        if let Some(&pc) = analysis.unresolved.iter().find(|&&pc| analysis.reachable[pc]) {
+            return Err(OptError::UnresolvedJump { pc });
+        }
+        target_uses(words, &analysis)?;
+        let states = flow(words, &analysis);
+        for (pc, &word) in words.iter().enumerate() {
+            let Some(state) = states[pc] else { continue };
+            let (a, b, _) = fields(word);
+            match opcode(word) {
+                1 if !nonzero(state[b]) => return Err(OptError::CodeAccess { pc }),
+                2 if !nonzero(state[a]) => return Err(OptError::CodeAccess { pc }),
+                _ => {}
+            }
+        }
+        let live = liveness(words, &analysis);
+        Ok(Facts { words, analysis, states, live })
+    }
+
+    /// Whether register `r` may be read after the word at `pc`
+    fn live(&self, pc: usize, r: usize) -> bool {
+        self.live[pc] & (1 << r) != 0
+    }
+
+    /// The `n` words from `pc`, if they are reachable and only entered from
+    /// the first
+    fn run(&self, pc: usize, n: usize) -> Option<&'a [u32]> {
+        let words = self.words.get(pc..pc + n)?;
+        let entered = (pc + 1..pc + n).any(|pc| self.analysis.leaders.contains(&pc));
+        (self.states[pc].is_some() && !entered).then_some(words)
+    }
+}
+
+/// Replaces NOT(rB nand rC) and NOT(NOT(rB)) spelled out with NANDs
+fn nand_idioms(facts: &Facts, edits: &mut Edits) {
+    let mut pc = 0;
+    while pc < facts.words.len() {
+        let state = match facts.states[pc] {
+            Some(state) if opcode(facts.words[pc]) == 6 => state,
+            _ => {
+                pc += 1;
+                continue;
+            }
+        };
+        // rT := rB nand rC; rA := rT nand rT; rD := rA nand rA
+        if let Some(&[w0, w1, w2]) = facts.run(pc, 3) {
+            let (t, b, c) = fields(w0);
+            let (a, a_t, a_t2) = fields(w1);
+            let (d, d_a, d_a2) = fields(w2);
+            if opcode(w1) == 6 && opcode(w2) == 6 && (a_t, a_t2) == (t, t) && (d_a, d_a2) == (a, a)
+                && (t == d || !facts.live(pc + 2, t)) && (a == d || !facts.live(pc + 2, a)) {
+                edits[pc] = Some(encode(6, d as u32, b as u32, c as u32));
+                edits[pc + 1] = None;
+                edits[pc + 2] = None;
+                pc += 3;
+                continue;
+            }
+        }
+        // rT := rB nand rB; rA := rT nand rT
+        if let Some(&[w0, w1]) = facts.run(pc, 2) {
+            let (t, b, b2) = fields(w0);
+            let (a, a_t, a_t2) = fields(w1);
+            if b == b2 && opcode(w1) == 6 && (a_t, a_t2) == (t, t) && (t == a || !facts.live(pc + 1, t)) {
+                let nonzero = (0..8).find(|&k| matches!(state[k], Value::Const(v) if v != 0));
+                if a == b {
+                    edits[pc] = None;
+                    edits[pc + 1] = None;
+                    pc += 2;
+                    continue;
+                }
+                if let Some(k) = nonzero {
+                    edits[pc] = Some(encode(0, a as u32, b as u32, k as u32));
+                    edits[pc + 1] = None;
+                    pc += 2;
+                    continue;
+                }
+            }
+        }
+        pc += 1;
+    }
+}
+
+/// Removes CMovs that never move, or that move a register onto itself
+fn idle_cmovs(facts: &Facts, edits: &mut Edits) {
+    for (pc, &word) in facts.words.iter().enumerate() {
+        let Some(state) = facts.states[pc] else { continue };
+        let (a, b, c) = fields(word);
+        if opcode(word) == 0 && (a == b || zero(state[c])) {
+            edits[pc] = None;
+        }
+    }
+}
+
+/// Turns CMovs that always move a constant into Load Values
+fn constant_cmovs(facts: &Facts, edits: &mut Edits) {
+    for (pc, &word) in facts.words.iter().enumerate() {
+        let Some(state) = facts.states[pc] else { continue };
+        let (a, b, c) = fields(word);
+        if opcode(word) != 0 || zero(state[c]) || state[c] == Value::Unknown {
+            continue;
+        }
+        match state[b] {
+            Value::Const(value) => edits[pc] = Some(encode_load_value(a as u32, value)),
+            Value::Unwritten => edits[pc] = Some(encode_load_value(a as u32, 0)),
+            Value::Segment | Value::Unknown => {}
+        }
+    }
+}
+
+/// Removes Load Values whose register is overwritten before it is read
+fn dead_load_values(facts: &Facts, edits: &mut Edits) {
+    for (pc, &word) in facts.words.iter().enumerate() {
+        if facts.states[pc].is_some() && opcode(word) == 13 && !facts.live(pc, get(&RL, word) as usize) {
+            edits[pc] = None;
+        }
+    }
+}
+
+/// Applies `edits`, moving jump targets to where their words now are. A
+/// removed target moves to the word that followed it.
+fn apply(facts: &Facts, edits: &Edits) -> Vec<u32> {
+    let mut moved = Vec::with_capacity(edits.len() + 1);
+    let mut kept = 0;
+    for edit in edits {
+        moved.push(kept);
+        kept += edit.is_some() as usize;
+    }
+    moved.push(kept);
+    edits.iter().enumerate().filter_map(|(pc, edit)| {
+        let word = (*edit)?;
+        match facts.analysis.label_refs.get(&pc) {
+            Some(&target) if opcode(word) == 13 => Some(encode_load_value(get(&RL, word), moved[target] as u32)),
+            _ => Some(word),
+        }
+    }).collect()
+}
+
+/// Optimizes the words of $m[0], returning the shorter program
+///
+/// # Arguments:
+/// * `words`: The words of $m[0]
+pub fn optimize(words: &[u32]) -> Result<Vec<u32>, OptError> {
+    rewrite(words, &[nand_idioms, idle_cmovs, constant_cmovs, dead_load_values])
+}
+
+/// Applies `rules` in rounds until none of them changes anything. A round
+/// whose result can no longer be analyzed is dropped, and the program
+/// before it is returned.
+fn rewrite(words: &[u32], rules: &[Rule]) -> Result<Vec<u32>, OptError> {
+    Facts::new(words)?;
+    let mut words = words.to_vec();
+    loop {
+        let mut changed = false;
+        for rule in rules {
+            let facts = Facts::new(&words).expect("kept programs have been analyzed");
+            let mut edits: Edits = words.iter().map(|&word| Some(word)).collect();
+            rule(&facts, &mut edits);
+            if edits.iter().zip(&words).any(|(edit, word)| edit != &Some(*word)) {
+                let next = apply(&facts, &edits);
+                if Facts::new(&next).is_err() {
+                    return Ok(words);
+                }
+                words = next;
+                changed = true;
+            }
+        }
+        if !changed {
+            return Ok(words);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use umcore::io::StreamIo;
+    use umcore::um::Um;
+    use crate::rumasm::{assemble, encode};
+    use crate::rumopt::{dead_load_values, optimize, rewrite, Edits, Facts, OptError};
+
+    fn optimized(source: &str) -> Result<Vec<u32>, OptError> {
+        optimize(&assemble(source).unwrap())
+    }
+
+    fn expected(source: &str) -> Result<Vec<u32>, OptError> {
+        Ok(assemble(source).unwrap())
+    }
+
+    #[test]
+    fn rewrites_nand_idioms() {
+        let not_and = "
+            r1 := r2 nand r3;
+            r4 := r1 nand r1;
+            r5 := r4 nand r4;
+            r1 := 0
+            r4 := 0
+            output r5;
+            halt";
+        assert_eq!(optimized(not_and), expected("r5 := r2 nand r3;\nr1 := 0\nr4 := 0\noutput r5;\nhalt"));
+        let double_not = "
+            r2 := input();
+            r7 := 1
+            r1 := r2 nand r2;
+            r3 := r1 nand r1;
+            r1 := 0
+            output r3;
+            halt";
+        assert_eq!(optimized(double_not),
+            expected("r2 := input();\nr7 := 1\nif (r7 != 0) r3 := r2;\nr1 := 0\noutput r3;\nhalt"));
+    }
+    #[test]
+    fn simplifies_cmovs_and_dead_load_values() {
+        let source = "
+            r1 := 1
+            r2 := 66
+            if (r0 != 0) r3 := r2;
+            if (r1 != 0) r3 := r2;
+            if (r1 != 0) r4 := r4;
+            r5 := 7
+            r5 := 8
+            output r3;
+            output r5;
+            halt";
+        assert_eq!(optimized(source),
+            expected("r1 := 1\nr2 := 66\nr3 := 66\nr5 := 8\noutput r3;\noutput r5;\nhalt"));
+    }
+    #[test]
+    fn relocates_jump_targets() {
+        let source = "
+            r0 := 0
+            r1 := 1
+            r6 := 5
+            r6 := 6
+            r2 := done
+            goto r2 in program m[r0];
+            .data 0xffffffff
+        done:
+            r3 := 9
+            r3 := 10
+            output r3;
+            halt";
+        let result = "
+            r0 := 0
+            r1 := 1
+            r6 := 6
+            r2 := done
+            goto r2 in program m[r0];
+            .data 0xffffffff
+        done:
+            r3 := 10
+            output r3;
+            halt";
+        assert_eq!(optimized(source), expected(result));
+    }
+    /// Runs `words` on `input` to the end, returning whether it halted
+    /// rather than faulted and what it wrote. Faults move with the words.
+    fn run(words: Vec<u32>, input: &[u8]) -> (bool, Vec<u8>) {
+        let mut um = Um::with_io(words, StreamIo::new(input, Vec::new()));
+        let halted = um.run().is_ok();
+        (halted, um.into_io().into_parts().unwrap().1)
+    }
+
+    #[test]
+    fn optimized_programs_behave_the_same() {
+        let programs = [
+            // The fall-through into `join` is never executed
+            "
+            r5 := 1
+            r5 := 2
+            r0 := 0
+            r3 := set
+            r4 := join
+            goto r4 in program m[r0];
+            r0 := 0
+            r3 := print
+        join:
+            goto r3 in program m[r0];
+            halt
+        set:
+            r1 := 65
+        print:
+            output r1;
+            halt",
+            // Two paths into one block, and a heap segment
+            "
+            r0 := 0
+            r7 := 1
+            r1 := input();
+            r2 := 1
+            r2 := map segment (r2 words);
+            r6 := 3
+            r6 := 4
+            m[r2][r0] := r1;
+            r5 := other
+            r4 := join
+            if (r1 != 0) r4 := r5;
+            goto r4 in program m[r0];
+        other:
+            r3 := r1 nand r1;
+            r1 := r3 nand r3;
+            r1 := m[r2][r0];
+            output r1;
+        join:
+            if (r0 != 0) r1 := r6;
+            output r6;
+            halt",
+        ];
+        for source in programs {
+            let words = assemble(source).unwrap();
+            let optimized = optimize(&words).unwrap();
+            assert!(optimized.len() < words.len());
+            for input in [&b""[..], b"x"] {
+                assert_eq!(run(optimized.clone(), input), run(words.clone(), input));
+            }
+        }
+    }
+    #[test]
+    fn refuses_unsafe_programs() {
+        assert_eq!(optimized("r1 := m[r0][r0];\noutput r1;\nhalt"),
+            Err(OptError::CodeAccess { pc: 0 }));
+        let unresolved = "r1 := 1\nr2 := map segment (r1 words);\nr3 := m[r2][r0];\ngoto r3 in program m[r0];\nhalt";
+        assert_eq!(optimized(unresolved), Err(OptError::UnresolvedJump { pc: 3 }));
+        // The segment of the Load is read from input, so it may be 0
+        assert_eq!(optimized("r1 := input();\nr2 := m[r1][r0];\noutput r2;\nhalt"),
+            Err(OptError::CodeAccess { pc: 1 }));
+        // r2 is added to r7 as well as jumped through, so the output would
+        // change with the target
+        let source = "
+            r0 := 0
+            r5 := 60
+            r5 := 61
+            r2 := target
+            r7 := 60
+            r6 := r2 + r7;
+            output r6;
+            output r5;
+            goto r2 in program m[r0];
+        target:
+            halt";
+        assert_eq!(optimized(source), Err(OptError::TargetAsData { pc: 5 }));
+        assert_eq!(run(assemble(source).unwrap(), b""), (true, b"E=".to_vec()));
+        // A jump that nothing reaches is data
+        assert_eq!(optimized("r1 := 5\nr1 := 66\noutput r1;\nhalt\ngoto r1 in program m[r0];"),
+            expected("r1 := 66\noutput r1;\nhalt\ngoto r1 in program m[r0];"));
+    }
+    /// Turns the jump target of `goto r2` into input, so that the jump can
+    /// no longer be resolved
+    fn unresolve(facts: &Facts, edits: &mut Edits) {
+        for &pc in facts.analysis.label_refs.keys() {
+            edits[pc] = Some(encode(11, 0, 0, 2));
+        }
+    }
+
+    #[test]
+    fn stops_before_an_unsafe_round() {
+        let source = "
+            r2 := 5
+            r2 := done
+            goto r2 in program m[r0];
+        done:
+            halt";
+        let words = assemble(source).unwrap();
+        let safe = assemble("r2 := done\ngoto r2 in program m[r0];\ndone: halt").unwrap();
+        assert_eq!(rewrite(&words, &[dead_load_values, unresolve]), Ok(safe));
+    }
+}
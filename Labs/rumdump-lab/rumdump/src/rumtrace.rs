@@ -0,0 +1,236 @@
+use std::io::{self, Read, Write};
+
+/// Magic bytes and version at the start of every trace file
+pub const MAGIC: &[u8; 4] = b"UMTR";
+pub const VERSION: u8 = 1;
+
+const REGISTERS: u8 = 1 << 0;
+const STORES: u8 = 1 << 1;
+const OUTPUT: u8 = 1 << 2;
+const INPUT: u8 = 1 << 3;
+const INPUT_EOF: u8 = 1 << 4;
+const FAULT: u8 = 1 << 5;
+
+/// One executed instruction and its effects.
+///
+/// On disk a record is a flags byte, the pc as a LEB128 varint and the
+/// instruction word big-endian, followed by only the parts the flags name:
+/// a bitmask of changed registers and their new values, the stores as
+/// (segment, offset, value) varints, and the byte written or read. A
+/// faulting instruction gets a last record with only the fault flag set.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TraceRecord {
+    pub pc: u32,
+    pub instruction: u32,
+    /// Registers that changed, as (register, new value)
+    pub registers: Vec<(u8, u32)>,
+    /// Words written, as (segment, offset, value)
+    pub stores: Vec<(u32, u32, u32)>,
+    /// Byte written by Output
+    pub output: Option<u8>,
+    /// Byte read by Input, or None at the end of input
+    pub input: Option<Option<u8>>,
+    /// The instruction faulted, so it had no effects
+    pub fault: bool,
+}
+
+fn write_varint<W: Write>(out: &mut W, mut value: u32) -> io::Result<()> {
+    let mut buf = [0_u8; 5];
+    let mut len = 0;
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf[len] = byte;
+            len += 1;
+            break;
+        }
+        buf[len] = byte | 0x80;
+        len += 1;
+    }
+    out.write_all(&buf[..len])
+}
+
+fn read_u8<R: Read>(input: &mut R) -> io::Result<u8> {
+    let mut byte = [0_u8; 1];
+    input.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn read_varint<R: Read>(input: &mut R) -> io::Result<u32> {
+    let mut value: u32 = 0;
+    for shift in (0..35).step_by(7) {
+        let byte = read_u8(input)?;
+        value |= ((byte & 0x7f) as u32) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::InvalidData, "varint longer than 5 bytes"))
+}
+
+/// Writes the trace file header
+pub fn write_header<W: Write>(out: &mut W) -> io::Result<()> {
+    out.write_all(MAGIC)?;
+    out.write_all(&[VERSION])
+}
+
+/// Appends one record to a trace
+pub fn write_record<W: Write>(out: &mut W, record: &TraceRecord) -> io::Result<()> {
+    let mut flags = 0;
+    if !record.registers.is_empty() {
+        flags |= REGISTERS;
+    }
+    if !record.stores.is_empty() {
+        flags |= STORES;
+    }
+    if record.output.is_some() {
+        flags |= OUTPUT;
+    }
+    match record.input {
+        Some(Some(_)) => flags |= INPUT,
+        Some(None) => flags |= INPUT_EOF,
+        None => {}
+    }
+    if record.fault {
+        flags |= FAULT;
+    }
+    out.write_all(&[flags])?;
+    write_varint(out, record.pc)?;
+    out.write_all(&record.instruction.to_be_bytes())?;
+    if flags & REGISTERS != 0 {
+        let mask = record.registers.iter().fold(0_u8, |mask, &(r, _)| mask | 1 << r);
+        out.write_all(&[mask])?;
+        let mut registers = record.registers.clone();
+        registers.sort();
+        for (_, value) in registers {
+            write_varint(out, value)?;
+        }
+    }
+    if flags & STORES != 0 {
+        write_varint(out, record.stores.len() as u32)?;
+        for &(segment, offset, value) in &record.stores {
+            write_varint(out, segment)?;
+            write_varint(out, offset)?;
+            write_varint(out, value)?;
+        }
+    }
+    if let Some(byte) = record.output {
+        out.write_all(&[byte])?;
+    }
+    if let Some(Some(byte)) = record.input {
+        out.write_all(&[byte])?;
+    }
+    Ok(())
+}
+
+/// Reads the records of a trace file one at a time
+pub struct TraceReader<R: Read> {
+    input: R,
+}
+
+impl<R: Read> TraceReader<R> {
+    /// Checks the header of a trace and returns a reader for its records
+    pub fn new(mut input: R) -> io::Result<Self> {
+        let mut header = [0_u8; 5];
+        input.read_exact(&mut header)?;
+        if &header[..4] != MAGIC || header[4] != VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a version 1 UM trace"));
+        }
+        Ok(TraceReader { input })
+    }
+
+    fn record(&mut self, flags: u8) -> io::Result<TraceRecord> {
+        let mut record = TraceRecord { pc: read_varint(&mut self.input)?, ..Default::default() };
+        let mut word = [0_u8; 4];
+        self.input.read_exact(&mut word)?;
+        record.instruction = u32::from_be_bytes(word);
+        if flags & REGISTERS != 0 {
+            let mask = read_u8(&mut self.input)?;
+            for r in 0..8 {
+                if mask & (1 << r) != 0 {
+                    record.registers.push((r, read_varint(&mut self.input)?));
+                }
+            }
+        }
+        if flags & STORES != 0 {
+            for _ in 0..read_varint(&mut self.input)? {
+                let segment = read_varint(&mut self.input)?;
+                let offset = read_varint(&mut self.input)?;
+                let value = read_varint(&mut self.input)?;
+                record.stores.push((segment, offset, value));
+            }
+        }
+        if flags & OUTPUT != 0 {
+            record.output = Some(read_u8(&mut self.input)?);
+        }
+        if flags & INPUT != 0 {
+            record.input = Some(Some(read_u8(&mut self.input)?));
+        }
+        if flags & INPUT_EOF != 0 {
+            record.input = Some(None);
+        }
+        record.fault = flags & FAULT != 0;
+        Ok(record)
+    }
+}
+
+impl<R: Read> Iterator for TraceReader<R> {
+    type Item = io::Result<TraceRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut flags = [0_u8; 1];
+        match self.input.read(&mut flags) {
+            Ok(0) => None,
+            Ok(_) => Some(self.record(flags[0])),
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// Renders the effects of a record, e.g. `r1=5 m[1][2]=7 out=72`
+pub fn effects(record: &TraceRecord) -> String {
+    let mut parts: Vec<String> = Vec::new();
+    for (r, value) in &record.registers {
+        parts.push(format!("r{}={}", r, value));
+    }
+    for (segment, offset, value) in &record.stores {
+        parts.push(format!("m[{}][{}]={}", segment, offset, value));
+    }
+    if let Some(byte) = record.output {
+        parts.push(format!("out={}", byte));
+    }
+    match record.input {
+        Some(Some(byte)) => parts.push(format!("in={}", byte)),
+        Some(None) => parts.push("in=EOF".to_string()),
+        None => {}
+    }
+    if record.fault {
+        parts.push("FAULT".to_string());
+    }
+    parts.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rumtrace::{write_header, write_record, TraceReader, TraceRecord};
+
+    #[test]
+    fn round_trip() {
+        let records = vec![
+            TraceRecord { pc: 0, instruction: 0xd2000048, registers: vec![(1, 72)], ..Default::default() },
+            TraceRecord { pc: 300, instruction: 0x20000000, stores: vec![(1, 2, u32::MAX)], ..Default::default() },
+            TraceRecord { pc: 2, instruction: 0xa0000001, output: Some(72), ..Default::default() },
+            TraceRecord { pc: 3, instruction: 0xb0000001, registers: vec![(1, u32::MAX)],
+                input: Some(None), ..Default::default() },
+            TraceRecord { pc: 4, instruction: 0x50000000, fault: true, ..Default::default() },
+        ];
+        let mut buf = Vec::new();
+        write_header(&mut buf).unwrap();
+        for record in &records {
+            write_record(&mut buf, record).unwrap();
+        }
+        let read: Vec<TraceRecord> = TraceReader::new(&buf[..]).unwrap().map(|r| r.unwrap()).collect();
+        assert_eq!(read, records);
+    }
+}